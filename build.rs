@@ -1,7 +1,15 @@
+// Track embedding lives in its own file so the simulator can share it.
+#[path = "build/tracks.rs"]
+mod tracks;
+
 fn main() {
     linker_be_nice();
-    // make sure linkall.x is the last linker script (otherwise might cause problems with flip-link)
-    println!("cargo:rustc-link-arg=-Tlinkall.x");
+    if targets_board() {
+        // make sure linkall.x is the last linker script (otherwise might cause problems with flip-link)
+        println!("cargo:rustc-link-arg=-Tlinkall.x");
+    }
+
+    tracks::generate_tracks(std::path::Path::new("."));
 }
 
 fn linker_be_nice() {
@@ -63,8 +71,16 @@ fn linker_be_nice() {
         std::process::exit(0);
     }
 
-    println!(
-        "cargo:rustc-link-arg=-Wl,--error-handling-script={}",
-        std::env::current_exe().unwrap().display()
-    );
+    if targets_board() {
+        println!(
+            "cargo:rustc-link-arg=-Wl,--error-handling-script={}",
+            std::env::current_exe().unwrap().display()
+        );
+    }
+}
+
+/// The linker scripts only exist for the board; host builds (the unit tests,
+/// the simulator) link as usual.
+fn targets_board() -> bool {
+    std::env::var("TARGET").is_ok_and(|target| target.starts_with("xtensa"))
 }
@@ -0,0 +1,241 @@
+//! Track embedding shared by the firmware's `build.rs` and the simulator's.
+
+use std::{
+    env,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+// The codec and container code are plain `core` Rust, so the build script
+// reuses the firmware sources instead of keeping a second implementation.
+#[allow(dead_code)]
+#[path = "../src/dsp/adpcm.rs"]
+mod adpcm;
+#[allow(dead_code)]
+#[path = "../src/dsp/wav.rs"]
+mod wav;
+
+/// Directory scanned for tracks to embed.
+const MUSIC_DIR: &str = "assets/music";
+/// Optional file inside `MUSIC_DIR` setting the playback order, titles and artists.
+const MANIFEST: &str = "manifest.txt";
+
+/// A track found in `MUSIC_DIR`, with the display data resolved.
+struct TrackEntry {
+    file: String,
+    title: Option<String>,
+    artist: Option<String>,
+}
+
+/// Scans `MUSIC_DIR`, compresses every track and writes the `TRACKS` table
+/// included by `src/assets.rs`. Adding a song is just dropping a file in the folder.
+///
+/// `root` is the firmware crate directory, relative to the running build script.
+pub fn generate_tracks(root: &Path) {
+    let music_dir = root.join(MUSIC_DIR);
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let music_out = out_dir.join("music");
+    fs::create_dir_all(&music_out).unwrap();
+
+    for file in [
+        "build/tracks.rs",
+        "src/dsp/adpcm.rs",
+        "src/dsp/wav.rs",
+        MUSIC_DIR,
+    ] {
+        println!("cargo:rerun-if-changed={}", root.join(file).display());
+    }
+
+    let entries = collect_tracks(&music_dir);
+    assert!(!entries.is_empty(), "no tracks found in {MUSIC_DIR}");
+    assert!(
+        entries.len() <= u8::MAX as usize,
+        "too many tracks in {MUSIC_DIR}"
+    );
+
+    let mut table = String::new();
+    writeln!(
+        table,
+        "// Generated by build.rs from {MUSIC_DIR}. Do not edit."
+    )
+    .unwrap();
+    writeln!(
+        table,
+        "pub static TRACKS: [crate::music::Track; {}] = [",
+        entries.len()
+    )
+    .unwrap();
+
+    for entry in &entries {
+        let source = music_dir.join(&entry.file);
+        println!("cargo:rerun-if-changed={}", source.display());
+        let input = fs::read(&source).unwrap();
+
+        let (output, tags) = encode_track(&entry.file, &input);
+        let title = entry
+            .title
+            .clone()
+            .or(tags.0)
+            .unwrap_or_else(|| file_stem(&entry.file));
+        let artist = entry.artist.clone().or(tags.1);
+
+        // The whole file name, so `a.wav` and `a.raw` do not overwrite each other
+        let target = music_out.join(format!("{}.wav", entry.file));
+        fs::write(&target, output).unwrap();
+
+        writeln!(table, "    crate::music::Track {{").unwrap();
+        writeln!(table, "        title: {title:?},").unwrap();
+        writeln!(table, "        artist: {artist:?},").unwrap();
+        writeln!(table, "        file: {:?},", entry.file).unwrap();
+        writeln!(
+            table,
+            "        bytes: include_bytes!({:?}),",
+            target.display()
+        )
+        .unwrap();
+        writeln!(table, "    }},").unwrap();
+    }
+    writeln!(table, "];").unwrap();
+
+    fs::write(out_dir.join("tracks.rs"), table).unwrap();
+}
+
+/// Lists the tracks in manifest order, followed by unlisted files sorted by name.
+fn collect_tracks(music_dir: &Path) -> Vec<TrackEntry> {
+    let mut files: Vec<String> = fs::read_dir(music_dir)
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".wav") || name.ends_with(".raw"))
+        .collect();
+    files.sort();
+
+    let mut entries = Vec::new();
+    let manifest = fs::read_to_string(music_dir.join(MANIFEST)).unwrap_or_default();
+    for line in manifest.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = line.split('|').map(str::trim);
+        let file = fields.next().unwrap_or_default().to_string();
+        let Some(position) = files.iter().position(|f| *f == file) else {
+            println!("cargo:warning={MANIFEST} lists '{file}', which is not in {MUSIC_DIR}");
+            continue;
+        };
+        files.remove(position);
+
+        let non_empty = |field: Option<&str>| field.filter(|f| !f.is_empty()).map(String::from);
+        entries.push(TrackEntry {
+            file,
+            title: non_empty(fields.next()),
+            artist: non_empty(fields.next()),
+        });
+    }
+
+    entries.extend(files.into_iter().map(|file| TrackEntry {
+        file,
+        title: None,
+        artist: None,
+    }));
+    entries
+}
+
+fn file_stem(file: &str) -> String {
+    Path::new(file)
+        .file_stem()
+        .unwrap()
+        .to_string_lossy()
+        .into_owned()
+}
+
+type Tags = (Option<String>, Option<String>);
+
+/// Compresses a track to IMA ADPCM (about 4:1) and returns it with its WAV tags.
+///
+/// Headerless `.raw` files are read as 11025 Hz mono 16-bit PCM. WAV files in
+/// any other format than 16-bit PCM are embedded unchanged.
+fn encode_track(file: &str, input: &[u8]) -> (Vec<u8>, Tags) {
+    if !wav::is_wav(input) {
+        let output = encode_wav(wav::AudioFormat::RAW_DEFAULT, input, None, None);
+        return (output, (None, None));
+    }
+
+    let track = wav::parse(input).unwrap_or_else(|err| panic!("invalid WAV file {file}: {err:?}"));
+    let tags = (
+        track.title.map(String::from),
+        track.artist.map(String::from),
+    );
+    if track.format.encoding == wav::Encoding::Pcm && track.format.bits_per_sample == 16 {
+        let output = encode_wav(track.format, track.data, track.title, track.artist);
+        (output, tags)
+    } else {
+        println!("cargo:warning={file} is not 16-bit PCM WAV, embedding it as is");
+        (input.to_vec(), tags)
+    }
+}
+
+/// Builds a complete IMA ADPCM WAV file from 16-bit PCM samples.
+fn encode_wav(
+    format: wav::AudioFormat,
+    pcm: &[u8],
+    title: Option<&str>,
+    artist: Option<&str>,
+) -> Vec<u8> {
+    let samples: Vec<i16> = pcm
+        .chunks_exact(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]))
+        .collect();
+    let total_frames = samples.len() / format.channels as usize;
+
+    let (format, data) = adpcm::encode_pcm_to_adpcm(format, &samples);
+    let channels = format.channels as usize;
+    let block_align = format.block_align as usize;
+    let frames_per_block = format.frames_per_block();
+
+    let sample_rate = format.sample_rate;
+    let mut fmt = Vec::new();
+    fmt.extend_from_slice(&0x0011u16.to_le_bytes());
+    fmt.extend_from_slice(&(channels as u16).to_le_bytes());
+    fmt.extend_from_slice(&sample_rate.to_le_bytes());
+    let byte_rate = sample_rate as u64 * block_align as u64 / frames_per_block as u64;
+    fmt.extend_from_slice(&(byte_rate as u32).to_le_bytes());
+    fmt.extend_from_slice(&(block_align as u16).to_le_bytes());
+    fmt.extend_from_slice(&4u16.to_le_bytes());
+    fmt.extend_from_slice(&2u16.to_le_bytes());
+    fmt.extend_from_slice(&(frames_per_block as u16).to_le_bytes());
+
+    let mut info = b"INFO".to_vec();
+    for (id, value) in [(b"INAM", title), (b"IART", artist)] {
+        if let Some(value) = value {
+            let mut text = value.as_bytes().to_vec();
+            text.push(0);
+            push_chunk(&mut info, id, &text);
+        }
+    }
+
+    let mut body = b"WAVE".to_vec();
+    push_chunk(&mut body, b"fmt ", &fmt);
+    push_chunk(&mut body, b"fact", &(total_frames as u32).to_le_bytes());
+    push_chunk(&mut body, b"LIST", &info);
+    push_chunk(&mut body, b"data", &data);
+
+    let mut file = b"RIFF".to_vec();
+    push_chunk_size(&mut file, body.len());
+    file.extend_from_slice(&body);
+    file
+}
+
+fn push_chunk(out: &mut Vec<u8>, id: &[u8; 4], body: &[u8]) {
+    out.extend_from_slice(id);
+    push_chunk_size(out, body.len());
+    out.extend_from_slice(body);
+    if body.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+fn push_chunk_size(out: &mut Vec<u8>, size: usize) {
+    out.extend_from_slice(&(size as u32).to_le_bytes());
+}
@@ -0,0 +1,336 @@
+//! Streams a WAV file into the player over its USB serial port.
+//!
+//! 16-bit PCM and IMA ADPCM files are sent as they are; `--adpcm` compresses
+//! 16-bit PCM on the way, a quarter of the data for the link to carry. The
+//! player decides the pace: audio only goes out as its credits allow, so the
+//! file is sent as fast as it is played.
+//!
+//! ```text
+//! cargo run -- [--adpcm] DEVICE FILE.wav
+//! ```
+
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    process::{self, Command},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+// The framing and the codec are plain `core` Rust, shared with the firmware.
+#[allow(dead_code)]
+#[path = "../../src/dsp/adpcm.rs"]
+mod adpcm;
+#[allow(dead_code)]
+#[path = "../../src/streaming/protocol.rs"]
+mod protocol;
+#[allow(dead_code)]
+#[path = "../../src/dsp/wav.rs"]
+mod wav;
+
+/// Where `protocol` finds the codec, as in the firmware.
+mod dsp {
+    pub(crate) use super::adpcm;
+    pub(crate) use super::wav::{AudioFormat, Encoding};
+}
+
+use protocol::{FrameDecoder, MAX_AUDIO, MAX_FRAME, Message};
+use wav::{AudioFormat, Encoding};
+
+/// Longest wait for the player to answer the start of the stream.
+const START_TIMEOUT: Duration = Duration::from_secs(2);
+/// Longest silence from the player once its buffer is full. Credits are
+/// repeated while it plays, even paused, so this means it left the stream.
+const STALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Command line options.
+struct Options {
+    /// Compress 16-bit PCM to IMA ADPCM before sending.
+    adpcm: bool,
+    /// Serial device of the player.
+    device: String,
+    /// WAV file to stream.
+    file: String,
+}
+
+impl Options {
+    fn parse() -> Result<Self, String> {
+        let mut adpcm = false;
+        let mut paths = Vec::new();
+        for arg in env::args().skip(1) {
+            match arg.as_str() {
+                "--adpcm" => adpcm = true,
+                _ if arg.starts_with("--") => return Err(format!("unknown option {arg}")),
+                _ => paths.push(arg),
+            }
+        }
+        let [device, file] = <[String; 2]>::try_from(paths)
+            .map_err(|_| "expected a device and a file".to_string())?;
+        Ok(Self {
+            adpcm,
+            device,
+            file,
+        })
+    }
+}
+
+fn main() {
+    let options = Options::parse().unwrap_or_else(|err| {
+        eprintln!("{err}\nusage: pds-sender [--adpcm] DEVICE FILE.wav");
+        process::exit(2);
+    });
+    let input = fs::read(&options.file).unwrap_or_else(|err| {
+        eprintln!("cannot read {}: {err}", options.file);
+        process::exit(1);
+    });
+    let (format, data) = prepare(&input, options.adpcm).unwrap_or_else(|err| {
+        eprintln!("cannot stream {}: {err}", options.file);
+        process::exit(1);
+    });
+    let port = open(&options.device).unwrap_or_else(|err| {
+        eprintln!("cannot open {}: {err}", options.device);
+        process::exit(1);
+    });
+
+    if let Err(err) = stream(port, format, &data) {
+        eprintln!("\n{err}");
+        process::exit(1);
+    }
+}
+
+/// Reads the WAV file in `input` and returns the audio to send: whole frames
+/// or blocks in a format the player accepts.
+fn prepare(input: &[u8], compress: bool) -> Result<(AudioFormat, Vec<u8>), String> {
+    let track = wav::parse(input).map_err(|err| format!("invalid WAV file: {err:?}"))?;
+    let format = track.format;
+    let (format, mut data) = match format.encoding {
+        Encoding::Pcm if format.bits_per_sample != 16 => {
+            return Err("only 16-bit PCM and IMA ADPCM can be streamed".into());
+        }
+        Encoding::Pcm if compress => {
+            let samples: Vec<i16> = track
+                .data
+                .chunks_exact(2)
+                .map(|b| i16::from_le_bytes([b[0], b[1]]))
+                .collect();
+            // The same blocks as the tracks embedded in flash
+            adpcm::encode_pcm_to_adpcm(format, &samples)
+        }
+        Encoding::Pcm | Encoding::ImaAdpcm => (format, track.data.to_vec()),
+    };
+    if !protocol::is_supported(&format) {
+        return Err(format!("unsupported format {format:?}"));
+    }
+    // A short final block cannot be streamed; it is a few milliseconds at most
+    data.truncate(data.len() / format.block_align as usize * format.block_align as usize);
+    Ok((format, data))
+}
+
+/// Opens the serial device in raw mode, so frames pass through untouched.
+fn open(path: &str) -> io::Result<File> {
+    let flag = if cfg!(target_os = "macos") {
+        "-f"
+    } else {
+        "-F"
+    };
+    let status = Command::new("stty")
+        .args([flag, path, "raw", "-echo"])
+        .status()?;
+    if !status.success() {
+        return Err(io::Error::other("stty failed"));
+    }
+    OpenOptions::new().read(true).write(true).open(path)
+}
+
+/// Sends `data` as one stream, as fast as the player's credits allow.
+fn stream(mut port: File, format: AudioFormat, data: &[u8]) -> Result<(), String> {
+    let credits = listen(port.try_clone().map_err(|err| err.to_string())?);
+
+    send(&mut port, &Message::Start(format))?;
+    let mut limit = match credits.recv_timeout(START_TIMEOUT) {
+        Ok(limit) => limit,
+        Err(_) => return Err("no answer from the player".into()),
+    };
+    eprintln!(
+        "Streaming {} KiB of {:?} at {} Hz, {} channel(s)",
+        data.len() / 1024,
+        format.encoding,
+        format.sample_rate,
+        format.channels,
+    );
+
+    // Whole frames or blocks only, as many as fit in one message
+    let unit = format.block_align as usize;
+    let chunk = MAX_AUDIO / unit * unit;
+    let started = Instant::now();
+    let mut offset = 0;
+    let mut shown = None;
+    while offset < data.len() {
+        // The latest credit is the one that counts
+        while let Ok(latest) = credits.try_recv() {
+            limit = latest;
+        }
+        let room = limit.saturating_sub(offset) / unit * unit;
+        if room == 0 {
+            limit = match credits.recv_timeout(STALL_TIMEOUT) {
+                Ok(latest) => latest,
+                Err(RecvTimeoutError::Timeout) => return Err("the player left the stream".into()),
+                Err(RecvTimeoutError::Disconnected) => return Err("the device was closed".into()),
+            };
+            continue;
+        }
+
+        let len = room.min(chunk).min(data.len() - offset);
+        let audio = Message::Audio {
+            offset: offset as u32,
+            data: &data[offset..offset + len],
+        };
+        send(&mut port, &audio)?;
+        offset += len;
+
+        let percent = offset * 100 / data.len();
+        if shown != Some(percent) {
+            shown = Some(percent);
+            eprint!("\r{percent:3}% sent");
+        }
+    }
+
+    send(&mut port, &Message::End)?;
+    eprintln!(" in {:.1}s", started.elapsed().as_secs_f32());
+    Ok(())
+}
+
+fn send(port: &mut File, message: &Message) -> Result<(), String> {
+    let mut frame = [0; MAX_FRAME];
+    let len = message.encode(&mut frame);
+    port.write_all(&frame[..len])
+        .map_err(|err| format!("write failed: {err}"))
+}
+
+/// Collects the player's credits on a thread of its own, as the end of the
+/// window granted: the stream may be sent up to that offset. Console output
+/// on the same port is skipped.
+fn listen(mut port: File) -> mpsc::Receiver<usize> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut decoder = FrameDecoder::new();
+        let mut buf = [0; 256];
+        while let Ok(len) = port.read(&mut buf)
+            && len > 0
+        {
+            for &byte in &buf[..len] {
+                if let Some(Ok(Message::Credit { offset, free })) = decoder.push(byte)
+                    && sender.send(offset as usize + free as usize).is_err()
+                {
+                    return;
+                }
+            }
+        }
+    });
+    receiver
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::ffi::CStr;
+    use std::os::fd::AsRawFd;
+    use std::os::raw::{c_char, c_int};
+
+    use super::*;
+
+    unsafe extern "C" {
+        fn grantpt(fd: c_int) -> c_int;
+        fn unlockpt(fd: c_int) -> c_int;
+        fn ptsname(fd: c_int) -> *const c_char;
+    }
+
+    /// Room the fake player grants, small enough to need many credits.
+    const WINDOW: usize = 3000;
+
+    /// Opens a pseudoterminal pair, returning its master side and the path of
+    /// the other, which plays the player's serial device.
+    fn pty() -> (File, String) {
+        let master = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/ptmx")
+            .unwrap();
+        let fd = master.as_raw_fd();
+        // SAFETY: `fd` is an open pseudoterminal master, and this is the only
+        // test calling `ptsname`.
+        let path = unsafe {
+            assert_eq!(grantpt(fd), 0);
+            assert_eq!(unlockpt(fd), 0);
+            CStr::from_ptr(ptsname(fd)).to_str().unwrap().to_owned()
+        };
+        (master, path)
+    }
+
+    fn send_credit(port: &mut File, offset: usize, free: usize) {
+        let credit = Message::Credit {
+            offset: offset as u32,
+            free: free as u32,
+        };
+        send(port, &credit).unwrap();
+    }
+
+    /// Plays the player's side of a stream: answers the start, grants
+    /// [`WINDOW`] bytes at a time, with console text in between, and returns
+    /// the format and the audio received.
+    fn player(mut port: File) -> (AudioFormat, Vec<u8>) {
+        let mut decoder = FrameDecoder::new();
+        let mut format = None;
+        let mut data = Vec::new();
+        let mut granted = 0;
+        let mut buf = [0; 256];
+        loop {
+            let len = port.read(&mut buf).unwrap();
+            assert!(len > 0, "the sender closed the port");
+            for &byte in &buf[..len] {
+                let credit = match decoder.push(byte) {
+                    Some(Ok(Message::Start(start))) => {
+                        format = Some(start);
+                        true
+                    }
+                    Some(Ok(Message::Audio {
+                        offset,
+                        data: audio,
+                    })) => {
+                        let offset = offset as usize;
+                        assert_eq!(offset, data.len(), "audio out of order");
+                        assert!(offset + audio.len() <= granted, "sent beyond the credit");
+                        data.extend_from_slice(audio);
+                        // Another credit once half the window is used
+                        granted - data.len() <= WINDOW / 2
+                    }
+                    Some(Ok(Message::End)) => return (format.unwrap(), data),
+                    Some(result) => panic!("unexpected frame {result:?}"),
+                    None => false,
+                };
+                if credit {
+                    port.write_all(b"> ok\r\n").unwrap();
+                    granted = data.len() + WINDOW;
+                    send_credit(&mut port, data.len(), WINDOW);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stream_follows_the_players_credits_over_a_pty() {
+        let samples: Vec<i16> = (0..5000).map(|i| (i * 37 % 2000 - 1000) as i16).collect();
+        let data: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        let format = AudioFormat::pcm(11_025, 2, 16);
+
+        let (master, path) = pty();
+        let port = open(&path).unwrap();
+        let player = thread::spawn(move || player(master));
+
+        stream(port, format, &data).unwrap();
+        let (received_format, received) = player.join().unwrap();
+        assert_eq!(received_format, format);
+        assert_eq!(received, data);
+    }
+}
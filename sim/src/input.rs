@@ -0,0 +1,181 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    thread,
+};
+
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::{Duration, Timer};
+
+use pds::button::{self, ButtonId, ButtonTimings};
+use pds::encoder::{self, EncoderConfig};
+use pds::hal::VirtualPin;
+
+use crate::screen::{SCREEN_REQUESTS, ScreenRequest};
+use crate::serial;
+use crate::sink::SHUTDOWN;
+
+static ENCODER_BUTTON: VirtualPin = VirtualPin::new();
+static PREV_BUTTON: VirtualPin = VirtualPin::new();
+static NEXT_BUTTON: VirtualPin = VirtualPin::new();
+static ENCODER_A: VirtualPin = VirtualPin::new();
+static ENCODER_B: VirtualPin = VirtualPin::new();
+
+/// Command lines waiting to run, from stdin or a script.
+static COMMANDS: Channel<CriticalSectionRawMutex, String, 8> = Channel::new();
+
+/// How long a click holds the button down.
+const CLICK: Duration = Duration::from_millis(60);
+/// Time for one detent of a `turn`, slow enough not to accelerate.
+const DETENT: Duration = Duration::from_millis(150);
+/// Encoder pin levels (A, B) through one detent, starting from rest at (1, 1).
+const CLOCKWISE: [(bool, bool); 4] = [(false, true), (false, false), (true, false), (true, true)];
+const COUNTER_CLOCKWISE: [(bool, bool); 4] =
+    [(true, false), (false, false), (false, true), (true, true)];
+
+/// Forwards command lines from `script`, or stdin if `None`, on a thread of
+/// its own so reading never blocks the executor. The end of the input quits.
+pub fn read_commands(script: Option<File>) {
+    let reader: Box<dyn BufRead + Send> = match script {
+        Some(file) => Box::new(BufReader::new(file)),
+        None => Box::new(BufReader::new(io::stdin())),
+    };
+    thread::spawn(move || {
+        for line in reader.lines().map_while(Result::ok) {
+            embassy_futures::block_on(COMMANDS.send(line));
+        }
+        embassy_futures::block_on(COMMANDS.send("quit".into()));
+    });
+}
+
+/// Stand-in for the board's `button_task`: [`button::watch_button`] on a [`VirtualPin`].
+#[embassy_executor::task(pool_size = 3)]
+pub async fn button_task(id: ButtonId, timings: ButtonTimings) {
+    button::watch_button(pin(id), id, timings).await
+}
+
+/// Stand-in for the board's `encoder_reader_task`: [`encoder::read_encoder`] on two [`VirtualPin`]s.
+#[embassy_executor::task]
+pub async fn encoder_task(config: EncoderConfig) {
+    encoder::read_encoder(&ENCODER_A, &ENCODER_B, config).await
+}
+
+/// Runs the commands read by [`read_commands`], standing in for the user's hands.
+///
+/// One command per line; blank lines and `#` comments are skipped:
+/// - `click <button>`, `double <button>`: short presses
+/// - `hold <button> <ms>`: a press held for `ms`
+/// - `press <button>`, `release <button>`: e.g. to turn the encoder while held
+/// - `turn <detents> [ms]`: rotates the encoder, clockwise if positive, taking
+///   `ms` per detent (150 by default; shorter spins accelerate)
+/// - `wait <ms>`: lets the player run
+/// - `shot <file.png>`: saves the screen, `show`: prints it
+/// - `serial <line>`: types a line into the serial console
+/// - `quit`: finishes the WAV file and exits
+///
+/// Buttons are `encoder`, `prev` and `next`.
+#[embassy_executor::task]
+pub async fn command_task() {
+    loop {
+        let line = COMMANDS.receive().await;
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        log::debug!("> {line}");
+        if let Err(err) = run(line).await {
+            log::warn!("{err}: {line}");
+        }
+    }
+}
+
+async fn run(line: &str) -> Result<(), &'static str> {
+    let mut words = line.split_whitespace();
+    let command = words.next().unwrap_or_default();
+    let mut arg = || words.next().ok_or("missing argument");
+
+    match command {
+        "click" => click(button(arg()?)?).await,
+        "double" => {
+            let id = button(arg()?)?;
+            click(id).await;
+            Timer::after(CLICK).await;
+            click(id).await;
+        }
+        "hold" => {
+            let id = button(arg()?)?;
+            let ms = number(arg()?)?;
+            pin(id).set_high(false);
+            Timer::after_millis(ms).await;
+            pin(id).set_high(true);
+        }
+        "press" => pin(button(arg()?)?).set_high(false),
+        "release" => pin(button(arg()?)?).set_high(true),
+        "turn" => {
+            let detents: i32 = arg()?.parse().map_err(|_| "invalid number")?;
+            let period = match arg() {
+                Ok(ms) => Duration::from_millis(number(ms)?),
+                Err(_) => DETENT,
+            };
+            turn(detents, period).await;
+        }
+        "wait" => Timer::after_millis(number(arg()?)?).await,
+        "shot" => {
+            let path = arg()?.to_string();
+            SCREEN_REQUESTS.send(ScreenRequest::Save(path)).await;
+        }
+        "show" => SCREEN_REQUESTS.send(ScreenRequest::Show).await,
+        "serial" => serial::type_line(line[command.len()..].trim()).await,
+        "quit" => {
+            // Let the display serve the screenshots still queued
+            Timer::after_millis(200).await;
+            SHUTDOWN.signal(());
+        }
+        _ => return Err("unknown command"),
+    }
+    Ok(())
+}
+
+async fn click(id: ButtonId) {
+    pin(id).set_high(false);
+    Timer::after(CLICK).await;
+    pin(id).set_high(true);
+}
+
+/// Steps the encoder pins through whole Gray cycles.
+async fn turn(detents: i32, period: Duration) {
+    let cycle = if detents > 0 {
+        CLOCKWISE
+    } else {
+        COUNTER_CLOCKWISE
+    };
+    let step = period / cycle.len() as u32;
+    for _ in 0..detents.unsigned_abs() {
+        for (a, b) in cycle {
+            Timer::after(step).await;
+            ENCODER_A.set_high(a);
+            ENCODER_B.set_high(b);
+        }
+    }
+}
+
+fn pin(id: ButtonId) -> &'static VirtualPin {
+    match id {
+        ButtonId::Encoder => &ENCODER_BUTTON,
+        ButtonId::Prev => &PREV_BUTTON,
+        ButtonId::Next => &NEXT_BUTTON,
+    }
+}
+
+fn button(name: &str) -> Result<ButtonId, &'static str> {
+    match name {
+        "encoder" => Ok(ButtonId::Encoder),
+        "prev" => Ok(ButtonId::Prev),
+        "next" => Ok(ButtonId::Next),
+        _ => Err("unknown button"),
+    }
+}
+
+fn number(text: &str) -> Result<u64, &'static str> {
+    text.parse().map_err(|_| "invalid number")
+}
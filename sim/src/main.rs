@@ -0,0 +1,136 @@
+//! Desktop simulator of the player.
+//!
+//! Runs the firmware's hardware-independent modules unchanged: the same
+//! [`Player`](pds::player::Player), screen and menu, button gestures and encoder
+//! decoding. Only the board tasks are replaced: the audio goes to a WAV file,
+//! the screen to a framebuffer that can be saved as PNG, the buttons and
+//! encoder are driven by commands typed on stdin or read from a script, and
+//! the serial console answers on stdout, or on a terminal device given with
+//! `--serial` that `pds-sender` can stream audio into.
+//!
+//! ```text
+//! cargo run -- [--script FILE] [--wav FILE] [--scale N] [--serial DEVICE]
+//! ```
+
+use std::{env, fs::File, process, time::SystemTime};
+
+use embassy_executor::Spawner;
+use embassy_time::Duration;
+
+mod input;
+mod screen;
+mod serial;
+mod sink;
+
+use pds::audio::{button_handler_task, volume_handler_task};
+use pds::button::{ButtonId, ButtonTimings};
+use pds::encoder::EncoderConfig;
+use screen::Framebuffer;
+use serial::SimSerial;
+use sink::WavSink;
+
+/// Command line options.
+struct Options {
+    /// Commands to run instead of reading stdin.
+    script: Option<String>,
+    /// Where the audio output is written.
+    wav: String,
+    /// Pixel size of saved screenshots.
+    scale: usize,
+    /// Terminal device used as the serial port instead of the `serial` command and stdout.
+    serial: Option<String>,
+}
+
+impl Options {
+    fn parse() -> Result<Self, String> {
+        let mut options = Self {
+            script: None,
+            wav: "pds-sim.wav".into(),
+            scale: 4,
+            serial: None,
+        };
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+            match arg.as_str() {
+                "--script" => options.script = Some(value()?),
+                "--wav" => options.wav = value()?,
+                "--serial" => options.serial = Some(value()?),
+                "--scale" => {
+                    options.scale = value()?
+                        .parse()
+                        .ok()
+                        .filter(|&scale| scale > 0)
+                        .ok_or("--scale needs a positive number")?;
+                }
+                _ => return Err(format!("unknown option {arg}")),
+            }
+        }
+        Ok(options)
+    }
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let options = Options::parse().unwrap_or_else(|err| {
+        eprintln!(
+            "{err}\nusage: pds-sim [--script FILE] [--wav FILE] [--scale N] [--serial DEVICE]"
+        );
+        process::exit(2);
+    });
+    let script = options.script.map(|path| {
+        File::open(&path).unwrap_or_else(|err| {
+            eprintln!("cannot open {path}: {err}");
+            process::exit(1);
+        })
+    });
+    let wav = WavSink::create(&options.wav).unwrap_or_else(|err| {
+        eprintln!("cannot create {}: {err}", options.wav);
+        process::exit(1);
+    });
+    log::info!("Writing audio to {}", options.wav);
+    let serial = match &options.serial {
+        Some(path) => SimSerial::open(path).unwrap_or_else(|err| {
+            eprintln!("cannot open {path}: {err}");
+            process::exit(1);
+        }),
+        None => SimSerial::default(),
+    };
+
+    // Same gesture timings as the board's buttons
+    spawner
+        .spawn(input::button_task(
+            ButtonId::Encoder,
+            ButtonTimings::new().with_double_click(Duration::from_millis(300)),
+        ))
+        .unwrap();
+    for id in [ButtonId::Prev, ButtonId::Next] {
+        spawner
+            .spawn(input::button_task(
+                id,
+                ButtonTimings::new()
+                    .with_long_press(Duration::from_millis(500))
+                    .with_repeat(Duration::from_millis(250)),
+            ))
+            .unwrap();
+    }
+    spawner.spawn(button_handler_task()).unwrap();
+    spawner.spawn(volume_handler_task()).unwrap();
+    spawner
+        .spawn(input::encoder_task(EncoderConfig::new()))
+        .unwrap();
+    spawner.spawn(input::command_task()).unwrap();
+    spawner.spawn(serial::console_task(serial)).unwrap();
+    spawner
+        .spawn(screen::display_task(Framebuffer::new(options.scale)))
+        .unwrap();
+
+    let seed = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |time| time.subsec_nanos());
+    spawner.spawn(sink::audio_task(wav, seed)).unwrap();
+
+    input::read_commands(script);
+}
@@ -0,0 +1,141 @@
+use std::{convert::Infallible, fs::File, io::BufWriter, iter, path::Path};
+
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::*};
+
+use pds::display;
+use pds::hal::Panel;
+
+const WIDTH: usize = 128;
+const HEIGHT: usize = 64;
+
+/// Requests for the frame on screen, served on every flush.
+pub enum ScreenRequest {
+    /// Writes the frame to a PNG file.
+    Save(String),
+    /// Prints the frame to the terminal.
+    Show,
+}
+
+pub static SCREEN_REQUESTS: Channel<CriticalSectionRawMutex, ScreenRequest, 4> = Channel::new();
+
+/// In-memory stand-in for the 128x64 OLED.
+///
+/// Drawing goes to a back buffer like on the SH1106 driver; flushing copies it
+/// to the frame "on screen", which is what screenshots show. Lit pixels take
+/// the brightness level as their gray value.
+pub struct Framebuffer {
+    buffer: [[bool; WIDTH]; HEIGHT],
+    pixels: [[bool; WIDTH]; HEIGHT],
+    brightness: u8,
+    /// Pixel size of saved screenshots.
+    scale: usize,
+}
+
+impl Framebuffer {
+    pub fn new(scale: usize) -> Self {
+        Self {
+            buffer: [[false; WIDTH]; HEIGHT],
+            pixels: [[false; WIDTH]; HEIGHT],
+            brightness: 0,
+            scale,
+        }
+    }
+
+    /// Saves the frame as an 8-bit grayscale PNG.
+    pub fn save_png(&self, path: &Path) -> Result<(), png::EncodingError> {
+        let scale = self.scale;
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, (WIDTH * scale) as u32, (HEIGHT * scale) as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let lit = self.brightness.max(1);
+        let mut data = Vec::with_capacity(WIDTH * HEIGHT * scale * scale);
+        for row in &self.pixels {
+            let line: Vec<u8> = row
+                .iter()
+                .flat_map(|&on| iter::repeat_n(if on { lit } else { 0 }, scale))
+                .collect();
+            for _ in 0..scale {
+                data.extend_from_slice(&line);
+            }
+        }
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&data)?;
+        writer.finish()
+    }
+
+    /// Renders the frame as text, two pixel rows per line using half blocks.
+    pub fn to_text(&self) -> String {
+        let mut text = String::with_capacity((WIDTH + 1) * HEIGHT / 2 * 3);
+        for rows in self.pixels.chunks(2) {
+            for (&top, &bottom) in rows[0].iter().zip(&rows[1]) {
+                text.push(match (top, bottom) {
+                    (false, false) => ' ',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (true, true) => '█',
+                });
+            }
+            text.push('\n');
+        }
+        text
+    }
+}
+
+impl OriginDimensions for Framebuffer {
+    fn size(&self) -> Size {
+        Size::new(WIDTH as u32, HEIGHT as u32)
+    }
+}
+
+impl DrawTarget for Framebuffer {
+    type Color = BinaryColor;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if let (Ok(x), Ok(y)) = (usize::try_from(point.x), usize::try_from(point.y))
+                && x < WIDTH
+                && y < HEIGHT
+            {
+                self.buffer[y][x] = color.is_on();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Panel for Framebuffer {
+    type BusError = Infallible;
+
+    async fn flush(&mut self) -> Result<(), Self::BusError> {
+        self.pixels = self.buffer;
+        while let Ok(request) = SCREEN_REQUESTS.try_receive() {
+            match request {
+                ScreenRequest::Save(path) => match self.save_png(Path::new(&path)) {
+                    Ok(()) => log::info!("Saved screen to {path}"),
+                    Err(err) => log::error!("Failed to save {path}: {err}"),
+                },
+                ScreenRequest::Show => print!("{}", self.to_text()),
+            }
+        }
+        Ok(())
+    }
+
+    async fn set_brightness(&mut self, level: u8) -> Result<(), Self::BusError> {
+        self.brightness = level;
+        Ok(())
+    }
+}
+
+/// Stand-in for the board's `display_task`: [`display::render_ui`] on a [`Framebuffer`].
+#[embassy_executor::task]
+pub async fn display_task(screen: Framebuffer) {
+    display::render_ui(screen).await
+}
@@ -0,0 +1,92 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    process::Command,
+    thread,
+};
+
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, pipe::Pipe};
+
+use pds::console;
+use pds::hal::SerialPort;
+
+/// Bytes received by the simulated serial port: typed by the `serial`
+/// command or read from the device given with `--serial`. Large enough for
+/// a few stream frames in flight.
+static SERIAL_INPUT: Pipe<CriticalSectionRawMutex, 4096> = Pipe::new();
+
+/// Stand-in for the board's USB serial port: receives what the `serial`
+/// command types and prints whatever is sent back on stdout, or talks to a
+/// terminal device such as one end of a pty pair.
+///
+/// The default port is driven by the `serial` command and answers on stdout.
+#[derive(Default)]
+pub struct SimSerial {
+    device: Option<File>,
+}
+
+impl SimSerial {
+    /// Uses the terminal device at `path` as the port, e.g. for `pds-sender`
+    /// to stream into. Everything read from it is received on a thread of
+    /// its own, so reading never blocks the executor.
+    pub fn open(path: &str) -> io::Result<Self> {
+        // Raw mode, so stream frames pass through untouched
+        let flag = if cfg!(target_os = "macos") {
+            "-f"
+        } else {
+            "-F"
+        };
+        let status = Command::new("stty")
+            .args([flag, path, "raw", "-echo"])
+            .status()?;
+        if !status.success() {
+            return Err(io::Error::other("stty failed"));
+        }
+
+        let device = OpenOptions::new().read(true).write(true).open(path)?;
+        let mut reader = device.try_clone()?;
+        thread::spawn(move || {
+            let mut buf = [0; 256];
+            while let Ok(len) = reader.read(&mut buf)
+                && len > 0
+            {
+                embassy_futures::block_on(SERIAL_INPUT.write_all(&buf[..len]));
+            }
+            log::warn!("Serial device closed");
+        });
+        Ok(Self {
+            device: Some(device),
+        })
+    }
+}
+
+impl SerialPort for SimSerial {
+    type Error = io::Error;
+
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        Ok(SERIAL_INPUT.read(buf).await)
+    }
+
+    async fn write_all(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        match &mut self.device {
+            Some(device) => device.write_all(bytes),
+            None => {
+                let mut stdout = io::stdout().lock();
+                stdout.write_all(bytes)?;
+                stdout.flush()
+            }
+        }
+    }
+}
+
+/// Types `line` into the serial port and presses Enter.
+pub async fn type_line(line: &str) {
+    SERIAL_INPUT.write_all(line.as_bytes()).await;
+    SERIAL_INPUT.write_all(b"\r\n").await;
+}
+
+/// Stand-in for the board's `console_task`: [`console::run_console`] on a [`SimSerial`].
+#[embassy_executor::task]
+pub async fn console_task(port: SimSerial) {
+    console::run_console(port).await
+}
@@ -0,0 +1,89 @@
+use std::{fs::File, io::BufWriter};
+
+use embassy_futures::select::select;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{Duration, Instant};
+use hound::{SampleFormat, WavSpec, WavWriter};
+
+use pds::audio::SAMPLE_RATE;
+use pds::dsp::FRAME_BYTES;
+use pds::hal::AudioSink;
+use pds::player;
+
+/// Asks [`audio_task`] to finish the WAV file and end the simulation.
+pub static SHUTDOWN: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// WAV file in the I2S output format (16-bit stereo at [`SAMPLE_RATE`]),
+/// written in real time as if a DAC were consuming it.
+///
+/// A "DAC" started along with the file plays it at the sample rate; the
+/// frames written ahead of it are what the sink holds as queued, like the
+/// board's DMA ring, so the player is paced exactly as on the device.
+pub struct WavSink {
+    writer: WavWriter<BufWriter<File>>,
+    start: Instant,
+    frames: u64,
+}
+
+impl WavSink {
+    pub fn create(path: &str) -> hound::Result<Self> {
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        Ok(Self {
+            writer: WavWriter::create(path, spec)?,
+            start: Instant::now(),
+            frames: 0,
+        })
+    }
+
+    /// Completes the header, returning the length of the recording.
+    pub fn finalize(self) -> hound::Result<Duration> {
+        self.writer.finalize()?;
+        Ok(Duration::from_micros(
+            self.frames * 1_000_000 / SAMPLE_RATE as u64,
+        ))
+    }
+
+    fn queued_bytes(&self) -> usize {
+        let played = self.start.elapsed().as_micros() * SAMPLE_RATE as u64 / 1_000_000;
+        self.frames.saturating_sub(played) as usize * FRAME_BYTES
+    }
+}
+
+impl AudioSink for WavSink {
+    type Error = hound::Error;
+
+    /// About 90ms, in the range of the board's DMA ring.
+    const CAPACITY: usize = 4096;
+
+    fn available(&mut self) -> Result<usize, Self::Error> {
+        Ok(Self::CAPACITY.saturating_sub(self.queued_bytes()))
+    }
+
+    fn push(&mut self, bytes: &[u8]) -> Result<usize, Self::Error> {
+        let len = bytes.len().min(self.available()?) / FRAME_BYTES * FRAME_BYTES;
+        for sample in bytes[..len].chunks_exact(2) {
+            self.writer
+                .write_sample(i16::from_le_bytes([sample[0], sample[1]]))?;
+        }
+        self.frames += (len / FRAME_BYTES) as u64;
+        Ok(len)
+    }
+}
+
+/// Stand-in for the board's `audio_task`: [`player::play`] into a [`WavSink`]
+/// until [`SHUTDOWN`], then closes the file and exits.
+#[embassy_executor::task]
+pub async fn audio_task(mut sink: WavSink, seed: u32) {
+    select(player::play(&mut sink, seed), SHUTDOWN.wait()).await;
+
+    match sink.finalize() {
+        Ok(length) => log::info!("Wrote {:.1}s of audio", length.as_millis() as f32 / 1000.0),
+        Err(err) => log::error!("Failed to finish the WAV file: {err}"),
+    }
+    std::process::exit(0);
+}
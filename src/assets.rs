@@ -1,10 +1,8 @@
-pub static SOUND_WAVE_BYTES: &[u8] = include_bytes!("../assets/sound-wave.gif");
 pub static PLAY_BYTES: &[u8] = include_bytes!("../assets/play.bmp");
 pub static PAUSE_BYTES: &[u8] = include_bytes!("../assets/pause.bmp");
 pub static PREV_BYTES: &[u8] = include_bytes!("../assets/prev.bmp");
 pub static NEXT_BYTES: &[u8] = include_bytes!("../assets/next.bmp");
 pub static SOUND_ICON_BYTES: &[u8] = include_bytes!("../assets/sound.bmp");
-pub static TETRIS_MUSIC: &[u8] = include_bytes!("../assets/tetris.raw");
-pub static LIKE_A_STONE_MUSIC: &[u8] = include_bytes!("../assets/like_a_stone.raw");
-pub static MARIO_WORLD: &[u8] = include_bytes!("../assets/mario-world.raw");
-pub static TOP_GEAR: &[u8] = include_bytes!("../assets/top-gear.raw");
+
+// Track table generated by `build.rs` from the files in `assets/music/`.
+include!(concat!(env!("OUT_DIR"), "/tracks.rs"));
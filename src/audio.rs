@@ -1,184 +1,118 @@
-use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
-use embassy_sync::signal::Signal;
-use embassy_time::{Duration, Instant, Timer};
-use esp_hal::{Blocking, i2s::master::I2sTx};
-
-use crate::button::ButtonSignal;
-use crate::encoder::{ENCODER_CHANNEL, EncoderDirection};
-use crate::music::Musics;
-
-/// Shared system volume (0-100%).
-pub static VOLUME: AtomicU8 = AtomicU8::new(50);
-/// Current playback progress percentage.
-pub static CURRENT_PERCENTAGE: AtomicU8 = AtomicU8::new(0);
-/// Signal to toggle Play/Pause state.
-pub static IS_PLAYING_SIGNAL: ButtonSignal = Signal::new();
-/// Atomic flag for current playback status.
-pub static IS_PLAYING: AtomicBool = AtomicBool::new(false);
-/// Signal to trigger next track.
-pub static NEXT: ButtonSignal = Signal::new();
-/// Signal to trigger previous track or restart current.
-pub static PREVIOUS: ButtonSignal = Signal::new();
-/// Index of the currently loaded track.
-pub static CURRENT_MUSIC_INDEX: AtomicU8 = AtomicU8::new(0);
-
-/// DMA buffer size configuration.
-/// 4092 bytes is the hardware limit for a single ESP32 DMA descriptor.
-/// We use a multiplier of 4 to create a circular buffer of ~16KB.
-pub const DMA_BUFFER_SIZE: usize = 4 * 4092;
-
-/// Handles volume adjustments based on rotary encoder input.
-/// Listens to ENCODER_CHANNEL and updates the global VOLUME atomic.
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use crate::button::{BUTTON_EVENTS, ButtonEvent, ButtonId, ENCODER_BUTTON_HELD, ENCODER_HOLD_USED};
+use crate::dsp::SpectrumSnapshot;
+use crate::encoder::{ENCODER_CHANNEL, EncoderDirection, EncoderEvent};
+use crate::menu::{MENU_INPUT, MENU_OPEN, MenuInput};
+use crate::player::{PLAYER_COMMANDS, PlayerCommand, PlayerState};
+
+/// Latest spectrum of the audio sent to the DAC, read by the display.
+pub static SPECTRUM: SpectrumSnapshot = SpectrumSnapshot::new();
+/// Distance covered by one seek step, in seconds.
+pub static SEEK_STEP_SECONDS: AtomicU8 = AtomicU8::new(5);
+
+/// Output sample rate of the I2S peripheral, in Hz.
+/// Tracks recorded at other rates are converted by the pipeline's resampler.
+pub const SAMPLE_RATE: u32 = 11025;
+
+/// Listens to ENCODER_CHANNEL and turns the volume up or down.
+/// While the encoder button is held, rotation seeks within the track instead,
+/// and while the menu is open it moves through the menu.
+/// Accelerated detents count as several steps in each case.
 #[embassy_executor::task]
 pub async fn volume_handler_task() {
     loop {
-        let direction = ENCODER_CHANNEL.receive().await;
-
-        match direction {
-            EncoderDirection::Clockwise => {
-                VOLUME
-                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| {
-                        Some((v + 5).min(100)) // Cap at 100%
-                    })
-                    .ok();
+        let EncoderEvent { direction, steps } = ENCODER_CHANNEL.receive().await;
+
+        if MENU_OPEN.load(Ordering::Relaxed) {
+            let input = match direction {
+                EncoderDirection::Clockwise => MenuInput::Increment,
+                EncoderDirection::CounterClockwise => MenuInput::Decrement,
+            };
+            for _ in 0..steps {
+                MENU_INPUT.send(input).await;
             }
+            continue;
+        }
 
-            EncoderDirection::CounterClockwise => {
-                // decrease min to 0
-                VOLUME
-                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |v| {
-                        Some(v.saturating_sub(5)) // Floor at 0%
-                    })
-                    .ok();
-            }
+        if ENCODER_BUTTON_HELD.load(Ordering::Relaxed) {
+            ENCODER_HOLD_USED.store(true, Ordering::Relaxed);
+            let step = SEEK_STEP_SECONDS.load(Ordering::Relaxed) as i32 * steps as i32;
+            let seconds = match direction {
+                EncoderDirection::Clockwise => step,
+                EncoderDirection::CounterClockwise => -step,
+            };
+            PLAYER_COMMANDS.send(PlayerCommand::Seek(seconds)).await;
+            continue;
         }
 
-        log::info!("Volume changed: {}", VOLUME.load(Ordering::Relaxed));
+        // The player keeps the volume within 0-100%
+        let delta = steps.saturating_mul(5).min(100) as i8;
+        let delta = match direction {
+            EncoderDirection::Clockwise => delta,
+            EncoderDirection::CounterClockwise => -delta,
+        };
+        PLAYER_COMMANDS
+            .send(PlayerCommand::ChangeVolume(delta))
+            .await;
     }
 }
 
-/// Core audio engine task.
-/// Manages I2S DMA transfers, track switching, and real-time gain scaling.
+/// Maps button gestures onto player actions.
+///
+/// - Encoder button: click toggles play/pause, double click cycles the
+///   playback mode, long press opens or closes the menu. Holding it while
+///   turning seeks (see [`volume_handler_task`]), which cancels the click.
+/// - Prev/Next: click skips, holding seeks backwards/forwards in steps.
+///
+/// While the menu is open, clicks navigate it instead.
 #[embassy_executor::task]
-pub async fn audio_task(
-    mut i2s_tx: I2sTx<'static, Blocking>,
-    tx_buffer: &'static mut [u8; DMA_BUFFER_SIZE],
-) {
-    // Initialize circular DMA transfer for continuous playback
-    let mut transfer = i2s_tx.write_dma_circular(tx_buffer).unwrap();
-
-    let mut current_music = Musics::from_index(&CURRENT_MUSIC_INDEX.load(Ordering::Relaxed));
-    let mut audio_data = current_music.bytes();
-    let mut total_len = audio_data.len();
-
-    let mut audio_offset = 0;
-    let mut is_playing = IS_PLAYING.load(Ordering::Relaxed);
-    let mut last_log_time = Instant::now();
-
+pub async fn button_handler_task() {
     loop {
-        // 1. Handle Control Signals (Play/Pause/Next/Prev)
-        if IS_PLAYING_SIGNAL.try_take().is_some() {
-            is_playing = !is_playing;
-            log::info!("Play/pause");
-        }
-        if NEXT.try_take().is_some() {
-            let new_music = current_music.next();
-            load_track(
-                &mut current_music,
-                &mut audio_data,
-                &mut total_len,
-                &mut audio_offset,
-                new_music,
-            );
-            is_playing = true;
-            log::info!("Next music: {}", current_music.title());
-        }
-
-        if PREVIOUS.try_take().is_some() {
-            // Restart if >10% played, otherwise go to previous track
-            if (audio_offset * 100) / total_len > 10 {
-                audio_offset = 0;
-                CURRENT_PERCENTAGE.store(0, Ordering::Relaxed);
-                log::info!("Restarting current music: {}", current_music.title());
-            } else {
-                let new_music = current_music.prev();
-                load_track(
-                    &mut current_music,
-                    &mut audio_data,
-                    &mut total_len,
-                    &mut audio_offset,
-                    new_music,
-                );
-                log::info!("Previous music: {}", current_music.title());
+        let (id, event) = BUTTON_EVENTS.receive().await;
+        let menu_open = MENU_OPEN.load(Ordering::Relaxed);
+        // Gestures of a hold already used for seeking are dropped
+        let hold_used = ENCODER_HOLD_USED.load(Ordering::Relaxed);
+
+        match (id, event) {
+            (ButtonId::Encoder, ButtonEvent::Press) => {
+                ENCODER_HOLD_USED.store(false, Ordering::Relaxed);
+                ENCODER_BUTTON_HELD.store(true, Ordering::Relaxed);
             }
-            is_playing = true;
-        }
-
-        IS_PLAYING.store(is_playing, Ordering::Relaxed);
-
-        // 2. Audio Processing & DMA Feed
-        let avail = transfer.available().unwrap();
-
-        if !is_playing {
-            // Feed silence to prevent audio artifacts while paused
-            let silence = [0u8; 512];
-            let chunk = avail.min(512);
-            transfer.push(&silence[..chunk]).unwrap();
-            Timer::after(Duration::from_millis(10)).await;
-            continue;
-        }
-
-        if avail > 1024 {
-            let chunk_size = 512.min(avail).min(audio_data.len() - audio_offset);
-            let audio_chunk = &audio_data[audio_offset..audio_offset + chunk_size];
-
-            let mut amplified = [0u8; 512];
-            let volume_level = VOLUME.load(Ordering::Relaxed);
-            let gain = (volume_level as f32) / 100.0;
-
-            // Apply software volume scaling (Gain) to 16-bit PCM samples
-            for (i, sample_bytes) in audio_chunk.chunks_exact(2).enumerate() {
-                let sample = i16::from_le_bytes([sample_bytes[0], sample_bytes[1]]);
-                let amplified_sample = ((sample as f32) * gain) as i16;
-                amplified[i * 2..i * 2 + 2].copy_from_slice(&amplified_sample.to_le_bytes());
+            (ButtonId::Encoder, ButtonEvent::Release) => {
+                ENCODER_BUTTON_HELD.store(false, Ordering::Relaxed);
             }
-            // Send to DMA
-            transfer.push(&amplified[..chunk_size]).unwrap();
-            audio_offset += chunk_size;
-
-            // Track Progress Logging
-            if last_log_time.elapsed() > Duration::from_secs(1) {
-                let percent = (audio_offset * 100) / total_len;
-                CURRENT_PERCENTAGE.store(percent as u8, Ordering::Relaxed);
-                log::info!("Playing: {percent}%");
-                last_log_time = Instant::now();
+            (ButtonId::Encoder, ButtonEvent::LongPress) if !hold_used => {
+                ENCODER_HOLD_USED.store(true, Ordering::Relaxed);
+                MENU_INPUT.send(MenuInput::Toggle).await;
             }
-
-            // Stop at EOF
-            if audio_offset >= audio_data.len() {
-                audio_offset = 0;
-                is_playing = false;
-                IS_PLAYING.store(is_playing, Ordering::Relaxed);
-                CURRENT_PERCENTAGE.store(0, Ordering::Relaxed);
-                log::info!("Music '{}' ended!", current_music.title());
+            (ButtonId::Encoder, ButtonEvent::Click) if !hold_used => {
+                if menu_open {
+                    MENU_INPUT.send(MenuInput::Select).await;
+                } else {
+                    PLAYER_COMMANDS.send(PlayerCommand::Toggle).await;
+                }
+            }
+            (ButtonId::Encoder, ButtonEvent::DoubleClick) if !hold_used && !menu_open => {
+                let mode = PlayerState::current().mode.next();
+                PLAYER_COMMANDS.send(PlayerCommand::SetMode(mode)).await;
+            }
+            (ButtonId::Prev, ButtonEvent::Click) if menu_open => {
+                MENU_INPUT.send(MenuInput::Back).await;
             }
+            (ButtonId::Next, ButtonEvent::Click) if menu_open => {
+                MENU_INPUT.send(MenuInput::Select).await;
+            }
+            (ButtonId::Prev, ButtonEvent::Click) => PLAYER_COMMANDS.send(PlayerCommand::Prev).await,
+            (ButtonId::Next, ButtonEvent::Click) => PLAYER_COMMANDS.send(PlayerCommand::Next).await,
+            (ButtonId::Prev | ButtonId::Next, ButtonEvent::LongPress | ButtonEvent::Repeat)
+                if !menu_open =>
+            {
+                let step = SEEK_STEP_SECONDS.load(Ordering::Relaxed) as i32;
+                let seconds = if id == ButtonId::Prev { -step } else { step };
+                PLAYER_COMMANDS.send(PlayerCommand::Seek(seconds)).await;
+            }
+            _ => {}
         }
-        Timer::after(Duration::from_millis(5)).await;
     }
 }
-
-/// Helper to update track state (Internal logic)
-fn load_track(
-    music: &mut Musics,
-    data: &mut &[u8],
-    total: &mut usize,
-    offset: &mut usize,
-    new_music: Musics,
-) {
-    *music = new_music;
-    CURRENT_MUSIC_INDEX.store(music.to_index(), Ordering::Relaxed);
-    *data = music.bytes();
-    *total = data.len();
-    *offset = 0;
-    CURRENT_PERCENTAGE.store(0, Ordering::Relaxed);
-}
@@ -0,0 +1,44 @@
+use esp_hal::{
+    Blocking,
+    dma::{DmaError, DmaTransferTxCircular},
+    i2s::master::I2sTx,
+};
+
+use crate::hal::AudioSink;
+use crate::player;
+
+/// DMA buffer size configuration.
+/// 4092 bytes is the hardware limit for a single ESP32 DMA descriptor.
+/// We use a multiplier of 4 to create a circular buffer of ~16KB.
+pub const DMA_BUFFER_SIZE: usize = 4 * 4092;
+
+/// The circular I2S DMA transfer, which keeps playing whatever was pushed last.
+type I2sSink<'a> = DmaTransferTxCircular<'a, I2sTx<'static, Blocking>>;
+
+impl AudioSink for I2sSink<'_> {
+    type Error = DmaError;
+
+    const CAPACITY: usize = DMA_BUFFER_SIZE;
+
+    fn available(&mut self) -> Result<usize, Self::Error> {
+        DmaTransferTxCircular::available(self)
+    }
+
+    fn push(&mut self, bytes: &[u8]) -> Result<usize, Self::Error> {
+        DmaTransferTxCircular::push(self, bytes)
+    }
+}
+
+/// Core audio engine task.
+/// Starts the circular I2S DMA transfer and lets [`player::play`] feed it.
+/// `seed` drives the shuffle order and should come from the hardware RNG.
+#[embassy_executor::task]
+pub async fn audio_task(
+    mut i2s_tx: I2sTx<'static, Blocking>,
+    tx_buffer: &'static mut [u8; DMA_BUFFER_SIZE],
+    seed: u32,
+) {
+    // Initialize circular DMA transfer for continuous playback
+    let transfer = i2s_tx.write_dma_circular(tx_buffer).unwrap();
+    player::play(transfer, seed).await
+}
@@ -0,0 +1,16 @@
+use esp_hal::gpio::{AnyPin, Input, InputConfig, Pull};
+
+use crate::button::{self, ButtonId, ButtonTimings};
+
+/// Monitors a GPIO pin and publishes its gestures, see [`button::watch_button`].
+///
+/// # Parameters
+/// - `pin_gpio`: GPIO pin to monitor (active low)
+/// - `id`: Which button this is, sent along with each event
+/// - `timings`: Gestures to recognise and their timings
+#[embassy_executor::task(pool_size = 3)]
+pub async fn button_task(pin_gpio: AnyPin<'static>, id: ButtonId, timings: ButtonTimings) {
+    let config = InputConfig::default().with_pull(Pull::Up);
+    let button = Input::new(pin_gpio, config);
+    button::watch_button(button, id, timings).await
+}
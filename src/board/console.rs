@@ -0,0 +1,27 @@
+use core::convert::Infallible;
+
+use esp_hal::{Async, usb_serial_jtag::UsbSerialJtag};
+
+use crate::console;
+use crate::hal::SerialPort;
+
+impl SerialPort for UsbSerialJtag<'_, Async> {
+    type Error = Infallible;
+
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        embedded_io_async::Read::read(self, buf).await
+    }
+
+    async fn write_all(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+        embedded_io_async::Write::write_all(self, bytes).await
+    }
+}
+
+/// Command shell on the USB-Serial-JTAG port, the same USB connector used
+/// for flashing. The logs stay on RTT, so the port carries only the console
+/// and the audio streamed from a computer. USB flow control holds the host
+/// back while the FIFO is full, so no byte of a stream is lost here.
+#[embassy_executor::task]
+pub async fn console_task(port: UsbSerialJtag<'static, Async>) {
+    console::run_console(port).await
+}
@@ -0,0 +1,33 @@
+use display_interface::DisplayError;
+use display_interface_i2c::I2CInterface;
+use esp_hal::{Async, i2c::master::I2c};
+use oled_async::{displays::sh1106, mode::GraphicsMode};
+
+use crate::display;
+use crate::hal::Panel;
+
+/// Type alias for the SH1106 OLED display using I2C and Async mode.
+pub type OledDisplay = GraphicsMode<sh1106::Sh1106_128_64, I2CInterface<I2c<'static, Async>>>;
+
+impl Panel for OledDisplay {
+    type BusError = DisplayError;
+
+    async fn flush(&mut self) -> Result<(), Self::BusError> {
+        GraphicsMode::flush(self).await
+    }
+
+    /// The panel's contrast register is how the OLED controls brightness.
+    async fn set_brightness(&mut self, level: u8) -> Result<(), Self::BusError> {
+        self.set_contrast(level).await
+    }
+}
+
+/// Main task for UI rendering.
+///
+/// Initialises the SH1106 and hands it to [`display::render_ui`], which pushes
+/// the frames drawn by the hardware-independent [`Ui`](crate::display::Ui).
+#[embassy_executor::task]
+pub async fn display_task(mut display: OledDisplay) {
+    display.init().await.unwrap();
+    display::render_ui(display).await
+}
@@ -0,0 +1,16 @@
+use esp_hal::gpio::{AnyPin, Input, InputConfig, Pull};
+
+use crate::encoder::{self, EncoderConfig};
+
+/// Reads a rotary encoder wired to two GPIO pins, see [`encoder::read_encoder`].
+#[embassy_executor::task]
+pub async fn encoder_reader_task(
+    pin_a: AnyPin<'static>,
+    pin_b: AnyPin<'static>,
+    config: EncoderConfig,
+) {
+    let input_config = InputConfig::default().with_pull(Pull::Up);
+    let tra = Input::new(pin_a, input_config);
+    let trb = Input::new(pin_b, input_config);
+    encoder::read_encoder(tra, trb, config).await
+}
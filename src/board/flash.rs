@@ -0,0 +1,104 @@
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash, check_erase, check_read,
+    check_write,
+};
+use esp_bootloader_esp_idf::partitions::{
+    self, DataPartitionSubType, PARTITION_TABLE_MAX_LEN, PartitionType,
+};
+use esp_hal::peripherals::FLASH;
+use esp_storage::FlashStorage;
+
+use crate::persist::{self, SettingsStore};
+use crate::storage::KvStore;
+
+/// The `settings` data partition, addressed from its own start.
+///
+/// Offsets outside the partition are rejected, so the store can never
+/// touch the application or the partition table.
+pub struct FlashPartition {
+    flash: FlashStorage<'static>,
+    offset: u32,
+    size: u32,
+}
+
+impl ErrorType for FlashPartition {
+    type Error = NorFlashErrorKind;
+}
+
+impl ReadNorFlash for FlashPartition {
+    const READ_SIZE: usize = FlashStorage::READ_SIZE;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len())?;
+        self.flash
+            .read(self.offset + offset, bytes)
+            .map_err(|err| err.kind())
+    }
+
+    fn capacity(&self) -> usize {
+        self.size as usize
+    }
+}
+
+impl NorFlash for FlashPartition {
+    const WRITE_SIZE: usize = FlashStorage::WRITE_SIZE;
+    const ERASE_SIZE: usize = FlashStorage::ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(self, from, to)?;
+        self.flash
+            .erase(self.offset + from, self.offset + to)
+            .map_err(|err| err.kind())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len())?;
+        self.flash
+            .write(self.offset + offset, bytes)
+            .map_err(|err| err.kind())
+    }
+}
+
+/// Opens the store in the `settings` partition listed in `partitions.csv`.
+///
+/// Returns `None` (after logging why) if the partition is missing or
+/// unreadable; the player then simply starts from the defaults every time.
+pub fn open_settings(flash: FLASH<'static>) -> Option<SettingsStore<FlashPartition>> {
+    let mut flash = FlashStorage::new(flash);
+
+    let mut buffer = [0; PARTITION_TABLE_MAX_LEN];
+    let table = match partitions::read_partition_table(&mut flash, &mut buffer) {
+        Ok(table) => table,
+        Err(err) => {
+            log::error!("Failed to read partition table: {err:?}");
+            return None;
+        }
+    };
+    let (offset, size) =
+        match table.find_partition(PartitionType::Data(DataPartitionSubType::Undefined)) {
+            Ok(Some(entry)) => (entry.offset(), entry.len()),
+            Ok(None) => {
+                log::warn!("No settings partition, state will not be saved");
+                return None;
+            }
+            Err(err) => {
+                log::error!("Failed to look up settings partition: {err:?}");
+                return None;
+            }
+        };
+
+    let partition = FlashPartition {
+        flash,
+        offset,
+        size,
+    };
+    KvStore::mount(partition)
+        .inspect_err(|err| log::error!("Failed to mount settings store: {err:?}"))
+        .ok()
+}
+
+/// Writes the player state to the settings partition as it changes.
+#[embassy_executor::task]
+pub async fn persist_task(store: SettingsStore<FlashPartition>) {
+    persist::save_changes(store).await
+}
@@ -0,0 +1,13 @@
+use esp_hal::gpio::Input;
+
+use crate::hal::InputSource;
+
+impl InputSource for Input<'_> {
+    fn is_high(&mut self) -> bool {
+        Input::is_high(self)
+    }
+
+    async fn wait_for_any_edge(&mut self) {
+        Input::wait_for_any_edge(self).await
+    }
+}
@@ -0,0 +1,18 @@
+//! Board support: the tasks and drivers that touch `esp-hal`.
+//!
+//! Everything outside this module is plain `core` and Embassy code that also
+//! builds for a host machine, which is how the simulator in `sim/` runs the
+//! same player, menu and screen logic as the firmware. The code here only
+//! implements the [`hal`](crate::hal) traits on the peripherals and runs the
+//! generic loops on them as Embassy tasks.
+//!
+//! Only built with the `esp32s3` feature, on by default; without it the
+//! library builds for the host and its unit tests run there.
+
+pub mod audio;
+pub mod button;
+pub mod console;
+pub mod display;
+pub mod encoder;
+pub mod flash;
+mod gpio;
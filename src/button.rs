@@ -1,33 +1,330 @@
-use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
-use embassy_time::{Duration, Timer};
-use esp_hal::gpio::{AnyPin, Input, InputConfig, Pull};
+use core::sync::atomic::AtomicBool;
+use embassy_futures::select::{Either, select};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::{Duration, Instant, Timer};
 
-/// A thread-safe signal to notify tasks of button events.
-pub type ButtonSignal = Signal<CriticalSectionRawMutex, bool>;
+use crate::hal::InputSource;
 
-/// Monitors a GPIO pin for button presses with 20ms debouncing.
+/// Gesture events from every button, consumed by `button_handler_task`.
+pub static BUTTON_EVENTS: Channel<CriticalSectionRawMutex, (ButtonId, ButtonEvent), 16> =
+    Channel::new();
+
+/// Set while the encoder push button is held down.
+/// Turning the encoder during a hold seeks instead of changing the volume.
+pub static ENCODER_BUTTON_HELD: AtomicBool = AtomicBool::new(false);
+/// Set when the encoder was turned during the current hold,
+/// so releasing the button does not also count as a click.
+pub static ENCODER_HOLD_USED: AtomicBool = AtomicBool::new(false);
+
+/// Identifies the physical button an event comes from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ButtonId {
+    Encoder,
+    Prev,
+    Next,
+}
+
+/// Events recognised by [`ButtonDetector`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ButtonEvent {
+    /// The button went down.
+    Press,
+    /// The button went up.
+    Release,
+    /// A short press, reported once it cannot become a double click.
+    Click,
+    /// Two short presses within the double-click window.
+    DoubleClick,
+    /// The button has been held for the long-press time.
+    LongPress,
+    /// Sent periodically while the button stays held after a long press.
+    Repeat,
+}
+
+/// Timings of the gestures recognised by [`ButtonDetector`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ButtonTimings {
+    /// Time the contacts are left to settle after each edge.
+    pub debounce: Duration,
+    /// Longest gap between two clicks that still makes a double click.
+    /// `None` disables double clicks, so clicks are reported on release.
+    pub double_click: Option<Duration>,
+    /// How long the button must be held for a long press.
+    pub long_press: Duration,
+    /// Interval between repeats while held after a long press; `None` disables them.
+    pub repeat: Option<Duration>,
+}
+
+impl ButtonTimings {
+    /// Clicks and long presses only, with a 20ms debounce.
+    pub const fn new() -> Self {
+        Self {
+            debounce: Duration::from_millis(20),
+            double_click: None,
+            long_press: Duration::from_millis(800),
+            repeat: None,
+        }
+    }
+
+    pub const fn with_debounce(mut self, debounce: Duration) -> Self {
+        self.debounce = debounce;
+        self
+    }
+
+    pub const fn with_double_click(mut self, window: Duration) -> Self {
+        self.double_click = Some(window);
+        self
+    }
+
+    pub const fn with_long_press(mut self, long_press: Duration) -> Self {
+        self.long_press = long_press;
+        self
+    }
+
+    pub const fn with_repeat(mut self, interval: Duration) -> Self {
+        self.repeat = Some(interval);
+        self
+    }
+}
+
+impl Default for ButtonTimings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    /// Button down. `clicks` is 2 for the second press of a double click.
+    Pressed {
+        since: Instant,
+        clicks: u8,
+        long: bool,
+        next_repeat: Instant,
+    },
+    /// Released after a short press, waiting to see whether a second one follows.
+    Released {
+        at: Instant,
+    },
+}
+
+/// Turns the debounced level of a button into gesture events.
+///
+/// The detector holds no timers itself: the caller feeds it every level change
+/// and wakes it again at [`deadline`](Self::deadline), which keeps it usable
+/// with any input source.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ButtonDetector {
+    timings: ButtonTimings,
+    state: State,
+}
+
+impl ButtonDetector {
+    pub const fn new(timings: ButtonTimings) -> Self {
+        Self {
+            timings,
+            state: State::Idle,
+        }
+    }
+
+    pub fn timings(&self) -> ButtonTimings {
+        self.timings
+    }
+
+    /// Next time [`update`](Self::update) must be called even if the level
+    /// does not change, or `None` if only a level change matters.
+    pub fn deadline(&self) -> Option<Instant> {
+        match self.state {
+            State::Idle => None,
+            State::Pressed {
+                since, long: false, ..
+            } => Some(since + self.timings.long_press),
+            State::Pressed { next_repeat, .. } => self.timings.repeat.map(|_| next_repeat),
+            State::Released { at } => self.timings.double_click.map(|window| at + window),
+        }
+    }
+
+    /// Advances the state machine with the current level at `now`, returning
+    /// the events that occurred (at most two, e.g. `Release` then `Click`).
+    pub fn update(
+        &mut self,
+        pressed: bool,
+        now: Instant,
+    ) -> impl Iterator<Item = ButtonEvent> + use<> {
+        let (state, events) = self.next(pressed, now);
+        self.state = state;
+        events.into_iter().flatten()
+    }
+
+    fn next(&self, pressed: bool, now: Instant) -> (State, [Option<ButtonEvent>; 2]) {
+        let press = |clicks| State::Pressed {
+            since: now,
+            clicks,
+            long: false,
+            next_repeat: now,
+        };
+
+        match (self.state, pressed) {
+            (State::Idle, true) => (press(1), [Some(ButtonEvent::Press), None]),
+            (State::Released { .. }, true) => (press(2), [Some(ButtonEvent::Press), None]),
+            (State::Pressed { long: true, .. }, false) => {
+                (State::Idle, [Some(ButtonEvent::Release), None])
+            }
+            (State::Pressed { clicks: 2, .. }, false) => (
+                State::Idle,
+                [Some(ButtonEvent::Release), Some(ButtonEvent::DoubleClick)],
+            ),
+            (State::Pressed { .. }, false) => match self.timings.double_click {
+                Some(_) => (
+                    State::Released { at: now },
+                    [Some(ButtonEvent::Release), None],
+                ),
+                None => (
+                    State::Idle,
+                    [Some(ButtonEvent::Release), Some(ButtonEvent::Click)],
+                ),
+            },
+            (
+                State::Pressed {
+                    since,
+                    clicks,
+                    long,
+                    next_repeat,
+                },
+                true,
+            ) => {
+                if !long && now >= since + self.timings.long_press {
+                    let next_repeat = now + self.timings.repeat.unwrap_or_default();
+                    let state = State::Pressed {
+                        since,
+                        clicks,
+                        long: true,
+                        next_repeat,
+                    };
+                    (state, [Some(ButtonEvent::LongPress), None])
+                } else if let Some(interval) = self.timings.repeat
+                    && long
+                    && now >= next_repeat
+                {
+                    let state = State::Pressed {
+                        since,
+                        clicks,
+                        long,
+                        next_repeat: next_repeat + interval,
+                    };
+                    (state, [Some(ButtonEvent::Repeat), None])
+                } else {
+                    (self.state, [None, None])
+                }
+            }
+            (State::Released { at }, false) => match self.timings.double_click {
+                Some(window) if now >= at + window => {
+                    (State::Idle, [Some(ButtonEvent::Click), None])
+                }
+                _ => (self.state, [None, None]),
+            },
+            (State::Idle, false) => (State::Idle, [None, None]),
+        }
+    }
+}
+
+/// Watches a button wired to `pin` (active low) and publishes its gestures
+/// to [`BUTTON_EVENTS`], tagged with `id`. Never returns.
 ///
-/// # Parameters
-/// - `pin_gpio`: GPIO pin to monitor
-/// - `id`: Label used for logging
-/// - `signal`: The signal to trigger on a valid press.
-#[embassy_executor::task(pool_size = 3)]
-pub async fn button_task(
-    pin_gpio: AnyPin<'static>,
-    id: &'static str,
-    signal: &'static ButtonSignal,
-) {
-    let config = InputConfig::default().with_pull(Pull::Up);
-    let mut button = Input::new(pin_gpio, config);
+/// Every edge is debounced before the level is sampled; between edges the
+/// loop also wakes at the detector's deadline, so long presses, repeats and
+/// delayed clicks are reported while the level stays put.
+pub async fn watch_button<I: InputSource>(mut pin: I, id: ButtonId, timings: ButtonTimings) -> ! {
+    let mut detector = ButtonDetector::new(timings);
 
     loop {
-        button.wait_for_falling_edge().await;
+        let edge = match detector.deadline() {
+            Some(deadline) => {
+                let woke = select(pin.wait_for_any_edge(), Timer::at(deadline)).await;
+                matches!(woke, Either::First(()))
+            }
+            None => {
+                pin.wait_for_any_edge().await;
+                true
+            }
+        };
 
-        Timer::after(Duration::from_millis(20)).await; // Debounce
+        if edge {
+            Timer::after(timings.debounce).await; // Debounce
+        }
 
-        if button.is_low() {
-            log::debug!("{id} button pressed!");
-            signal.signal(true);
+        for event in detector.update(pin.is_low(), Instant::now()) {
+            log::debug!("{id:?} button: {event:?}");
+            BUTTON_EVENTS.send((id, event)).await;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::VirtualPin;
+    use crate::hal::mock;
+
+    /// Holds the button down for `millis`, then lets it go for as long.
+    async fn press(pin: &VirtualPin, millis: u64) {
+        pin.set_high(false);
+        Timer::after_millis(millis).await;
+        pin.set_high(true);
+        Timer::after_millis(millis.min(40)).await;
+    }
+
+    fn events() -> Vec<(ButtonId, ButtonEvent)> {
+        core::iter::from_fn(|| BUTTON_EVENTS.try_receive().ok()).collect()
+    }
+
+    #[test]
+    fn watch_button_reports_clicks_and_long_presses() {
+        let _lock = mock::lock();
+        events();
+        let pin = VirtualPin::new();
+        let timings = ButtonTimings::new()
+            .with_debounce(Duration::from_millis(5))
+            .with_long_press(Duration::from_millis(100));
+
+        mock::run(watch_button(&pin, ButtonId::Next, timings), async {
+            press(&pin, 30).await;
+            press(&pin, 150).await;
+        });
+
+        use ButtonEvent::*;
+        let expected = [Press, Release, Click, Press, LongPress, Release];
+        assert_eq!(events(), expected.map(|event| (ButtonId::Next, event)));
+    }
+
+    #[test]
+    fn watch_button_waits_out_the_double_click_window() {
+        let _lock = mock::lock();
+        events();
+        let pin = VirtualPin::new();
+        let timings = ButtonTimings::new()
+            .with_debounce(Duration::from_millis(5))
+            .with_double_click(Duration::from_millis(100));
+
+        mock::run(watch_button(&pin, ButtonId::Encoder, timings), async {
+            press(&pin, 20).await;
+            press(&pin, 20).await;
+            press(&pin, 20).await;
+            Timer::after_millis(150).await;
+        });
+
+        use ButtonEvent::*;
+        let expected = [
+            Press,
+            Release,
+            Press,
+            Release,
+            DoubleClick,
+            Press,
+            Release,
+            Click,
+        ];
+        assert_eq!(events(), expected.map(|event| (ButtonId::Encoder, event)));
+    }
+}
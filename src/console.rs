@@ -0,0 +1,999 @@
+//! Line-based command shell on a serial port.
+//!
+//! Each line received is parsed into a [`Request`] and answered with a reply,
+//! either as text for a person at a terminal or as one JSON object per line
+//! for programs (`format json`). Player commands go through
+//! [`PLAYER_COMMANDS`] like the buttons' and the answers come from the
+//! published [`PlayerState`], so the console never touches the player itself.
+//!
+//! Nothing here depends on the port: the board runs [`run_console`] on the
+//! USB-Serial-JTAG port and the simulator on a pipe fed by its script. The
+//! same port carries audio streamed from a computer; its frames, which only
+//! start at the beginning of a line, are told apart from text and handed to a
+//! [`StreamReceiver`].
+
+use core::fmt::{self, Write};
+use core::ops::RangeInclusive;
+use core::str::FromStr;
+
+use embassy_futures::select::{Either, select};
+use embassy_time::{Duration, Timer};
+
+use crate::dsp::{DEFAULT_EQ, EqSettings, MAX_GAIN_DB, ResampleQuality};
+use crate::hal::SerialPort;
+use crate::music::Musics;
+use crate::player::{PLAYER_COMMANDS, PlayerCommand, PlayerState};
+use crate::playlist::PlaybackMode;
+use crate::streaming::StreamReceiver;
+
+/// Longest command line accepted, in bytes.
+const LINE_CAPACITY: usize = 80;
+/// Longest piece of a reply; anything beyond is cut off.
+const REPLY_CAPACITY: usize = 192;
+/// Shown before each command in the human format.
+const PROMPT: &str = "> ";
+/// Longest move accepted by `seek +s`/`-s`: a day, far beyond any track.
+const MAX_SEEK_SECONDS: i32 = 86_400;
+/// Furthest position accepted by `seek`, i.e. `999:59`.
+const MAX_POSITION_SECONDS: u32 = 999 * 60 + 59;
+/// Longest wait for input, so stream credits go out while the port is quiet.
+const STREAM_POLL: Duration = Duration::from_millis(20);
+
+/// Commands with their arguments and what they do, for `help`.
+const COMMANDS: [(&str, &str); 17] = [
+    ("play", "resume playback"),
+    ("pause", "pause playback"),
+    ("toggle", "play or pause"),
+    ("next", "skip to the next track"),
+    ("prev", "restart the track or go to the previous one"),
+    ("track <n>", "play track n of the list"),
+    ("vol <0-100|+n|-n>", "set or change the volume"),
+    (
+        "seek <+s|-s|s|m:ss>",
+        "move by s seconds or go to a position",
+    ),
+    ("mode <off|one|all|shuffle>", "set the playback mode"),
+    (
+        "eq <bass> <mid> <treble>|flat",
+        "set the equalizer gains in dB",
+    ),
+    ("balance <-100-100>", "set the stereo balance"),
+    ("crossfade <ms>", "overlap consecutive tracks, 0 for none"),
+    ("fade <ms>", "fade length around play, pause and skips"),
+    (
+        "quality <linear|low|medium|high>",
+        "set the resampler quality",
+    ),
+    ("status", "show what is playing"),
+    ("list", "list the tracks"),
+    ("format <human|json>", "choose the reply format"),
+];
+
+/// Names of the playback modes on the console.
+const MODES: [(&str, PlaybackMode); 4] = [
+    ("off", PlaybackMode::RepeatOff),
+    ("one", PlaybackMode::RepeatOne),
+    ("all", PlaybackMode::RepeatAll),
+    ("shuffle", PlaybackMode::Shuffle),
+];
+
+/// Names of the resampler qualities on the console.
+const QUALITIES: [(&str, ResampleQuality); 4] = [
+    ("linear", ResampleQuality::Linear),
+    ("low", ResampleQuality::Low),
+    ("medium", ResampleQuality::Medium),
+    ("high", ResampleQuality::High),
+];
+
+/// How replies are written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Text for a terminal, with echo and a prompt.
+    Human,
+    /// One JSON object per line and no echo, for programs.
+    Json,
+}
+
+/// A parsed command line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Request {
+    /// Passed on to the player.
+    Player(PlayerCommand),
+    Status,
+    List,
+    Help,
+    SetFormat(Format),
+}
+
+/// Why a line was rejected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParseError {
+    UnknownCommand,
+    MissingArgument,
+    InvalidArgument,
+    TooManyArguments,
+    /// The line is longer than [`LINE_CAPACITY`].
+    LineTooLong,
+    /// The line is not valid UTF-8.
+    InvalidText,
+}
+
+impl ParseError {
+    pub fn message(&self) -> &'static str {
+        match self {
+            ParseError::UnknownCommand => "unknown command",
+            ParseError::MissingArgument => "missing argument",
+            ParseError::InvalidArgument => "invalid argument",
+            ParseError::TooManyArguments => "too many arguments",
+            ParseError::LineTooLong => "line too long",
+            ParseError::InvalidText => "invalid text",
+        }
+    }
+}
+
+impl Request {
+    /// Parses a command line. Commands and keywords are lowercase, words are
+    /// separated by any amount of whitespace.
+    pub fn parse(line: &str) -> Result<Self, ParseError> {
+        let mut words = line.split_whitespace();
+        let command = words.next().ok_or(ParseError::UnknownCommand)?;
+        let mut arg = || words.next().ok_or(ParseError::MissingArgument);
+
+        let request = match command {
+            "play" => Request::Player(PlayerCommand::Play),
+            "pause" => Request::Player(PlayerCommand::Pause),
+            "toggle" => Request::Player(PlayerCommand::Toggle),
+            "next" => Request::Player(PlayerCommand::Next),
+            "prev" => Request::Player(PlayerCommand::Prev),
+            "track" => {
+                let number: u8 = parse_number(arg()?, 1..=Musics::COUNT as u8)?;
+                Request::Player(PlayerCommand::Select(Musics::from_index(&(number - 1))))
+            }
+            "vol" => {
+                let word = arg()?;
+                Request::Player(if word.starts_with(['+', '-']) {
+                    PlayerCommand::ChangeVolume(parse_number(word, -100..=100)?)
+                } else {
+                    PlayerCommand::SetVolume(parse_number(word, 0..=100)?)
+                })
+            }
+            "seek" => {
+                let word = arg()?;
+                Request::Player(if word.starts_with(['+', '-']) {
+                    PlayerCommand::Seek(parse_number(word, -MAX_SEEK_SECONDS..=MAX_SEEK_SECONDS)?)
+                } else {
+                    PlayerCommand::SeekTo(parse_time(word)? * 1000)
+                })
+            }
+            "mode" => Request::Player(PlayerCommand::SetMode(keyword(arg()?, &MODES)?)),
+            "eq" => {
+                let first = arg()?;
+                let mut settings = DEFAULT_EQ;
+                if first != "flat" {
+                    for (i, band) in settings.iter_mut().enumerate() {
+                        let word = if i == 0 { first } else { arg()? };
+                        band.gain_db = parse_number(word, -MAX_GAIN_DB..=MAX_GAIN_DB)?;
+                    }
+                }
+                Request::Player(PlayerCommand::SetEq(settings))
+            }
+            "balance" => {
+                Request::Player(PlayerCommand::SetBalance(parse_number(arg()?, -100..=100)?))
+            }
+            "crossfade" => Request::Player(PlayerCommand::SetCrossfade(parse_number(
+                arg()?,
+                0..=u16::MAX,
+            )?)),
+            "fade" => Request::Player(PlayerCommand::SetFade(parse_number(arg()?, 0..=u16::MAX)?)),
+            "quality" => Request::Player(PlayerCommand::SetResampleQuality(keyword(
+                arg()?,
+                &QUALITIES,
+            )?)),
+            "status" => Request::Status,
+            "list" => Request::List,
+            "help" => Request::Help,
+            "format" => Request::SetFormat(match arg()? {
+                "human" => Format::Human,
+                "json" => Format::Json,
+                _ => return Err(ParseError::InvalidArgument),
+            }),
+            _ => return Err(ParseError::UnknownCommand),
+        };
+
+        match words.next() {
+            Some(_) => Err(ParseError::TooManyArguments),
+            None => Ok(request),
+        }
+    }
+}
+
+fn parse_number<T: FromStr + PartialOrd>(
+    word: &str,
+    range: RangeInclusive<T>,
+) -> Result<T, ParseError> {
+    word.parse()
+        .ok()
+        .filter(|value| range.contains(value))
+        .ok_or(ParseError::InvalidArgument)
+}
+
+/// Parses a position given as seconds or `m:ss`, up to [`MAX_POSITION_SECONDS`].
+fn parse_time(word: &str) -> Result<u32, ParseError> {
+    match word.split_once(':') {
+        Some((minutes, seconds)) => {
+            let minutes: u32 = parse_number(minutes, 0..=999)?;
+            let seconds: u32 = parse_number(seconds, 0..=59)?;
+            Ok(minutes * 60 + seconds)
+        }
+        None => parse_number(word, 0..=MAX_POSITION_SECONDS),
+    }
+}
+
+fn keyword<T: Copy>(word: &str, table: &[(&str, T)]) -> Result<T, ParseError> {
+    table
+        .iter()
+        .find(|(name, _)| *name == word)
+        .map(|&(_, value)| value)
+        .ok_or(ParseError::InvalidArgument)
+}
+
+fn keyword_of<T: PartialEq>(value: T, table: &[(&'static str, T)]) -> &'static str {
+    table
+        .iter()
+        .find(|(_, v)| *v == value)
+        .map_or("", |(name, _)| name)
+}
+
+/// The shell's session: the line being typed and the reply format.
+pub struct Console {
+    format: Format,
+    line: [u8; LINE_CAPACITY],
+    len: usize,
+    /// Set once the line outgrows the buffer; the rest of it is dropped.
+    overflow: bool,
+    /// Set after a carriage return, so the line feed of a CR LF pair does not
+    /// count as a second, empty line.
+    after_cr: bool,
+}
+
+impl Console {
+    pub fn new() -> Self {
+        Self {
+            format: Format::Human,
+            line: [0; LINE_CAPACITY],
+            len: 0,
+            overflow: false,
+            after_cr: false,
+        }
+    }
+
+    /// Takes one received byte, answering on `port` when it ends a line.
+    ///
+    /// In the human format typed characters are echoed and backspace erases
+    /// the last one, since terminals do not do it on their own.
+    pub async fn receive<P: SerialPort>(&mut self, port: &mut P, byte: u8) {
+        let after_cr = core::mem::replace(&mut self.after_cr, byte == b'\r');
+        match byte {
+            b'\n' if after_cr => {}
+            b'\r' | b'\n' => {
+                self.echo(port, b"\r\n").await;
+                if let Some(request) = self.take_line() {
+                    self.answer(port, request).await;
+                }
+                if self.format == Format::Human {
+                    write_to(port, format_args!("{PROMPT}")).await;
+                }
+            }
+            // Backspace or delete
+            0x08 | 0x7f => {
+                if self.len > 0 && !self.overflow {
+                    self.len -= 1;
+                    self.echo(port, b"\x08 \x08").await;
+                }
+            }
+            // Other control characters, such as the escape sequences of arrow keys
+            byte if byte < b' ' => {}
+            byte => {
+                if self.len < LINE_CAPACITY {
+                    self.line[self.len] = byte;
+                    self.len += 1;
+                    self.echo(port, &[byte]).await;
+                } else {
+                    self.overflow = true;
+                }
+            }
+        }
+    }
+
+    /// Returns `true` when nothing has been typed on the current line.
+    pub fn at_line_start(&self) -> bool {
+        self.len == 0 && !self.overflow
+    }
+
+    /// Ends the line being typed, returning it parsed, or `None` if it was blank.
+    fn take_line(&mut self) -> Option<Result<Request, ParseError>> {
+        let len = core::mem::take(&mut self.len);
+        if core::mem::take(&mut self.overflow) {
+            return Some(Err(ParseError::LineTooLong));
+        }
+        match core::str::from_utf8(&self.line[..len]) {
+            Ok(line) if line.trim().is_empty() => None,
+            Ok(line) => Some(Request::parse(line)),
+            Err(_) => Some(Err(ParseError::InvalidText)),
+        }
+    }
+
+    async fn answer<P: SerialPort>(&mut self, port: &mut P, request: Result<Request, ParseError>) {
+        let state = PlayerState::current();
+        match request {
+            Ok(Request::Player(command)) => {
+                PLAYER_COMMANDS.send(command).await;
+                self.ok(port).await;
+            }
+            Ok(Request::Status) => self.status(port, &state).await,
+            Ok(Request::List) => self.list(port, state.track).await,
+            Ok(Request::Help) => self.help(port).await,
+            Ok(Request::SetFormat(format)) => {
+                self.format = format;
+                self.ok(port).await;
+            }
+            Err(err) => match self.format {
+                Format::Human => write_to(port, format_args!("error: {}\r\n", err.message())).await,
+                Format::Json => {
+                    write_to(
+                        port,
+                        format_args!("{{\"ok\":false,\"error\":\"{}\"}}\r\n", err.message()),
+                    )
+                    .await
+                }
+            },
+        }
+    }
+
+    async fn ok<P: SerialPort>(&self, port: &mut P) {
+        match self.format {
+            Format::Human => write_to(port, format_args!("ok\r\n")).await,
+            Format::Json => write_to(port, format_args!("{{\"ok\":true}}\r\n")).await,
+        }
+    }
+
+    async fn status<P: SerialPort>(&self, port: &mut P, state: &PlayerState) {
+        let track = state.track;
+        let number = track.to_index() + 1;
+        let mode = keyword_of(state.mode, &MODES);
+        match self.format {
+            Format::Human => {
+                let playing = if state.playing { "playing" } else { "paused" };
+                if state.streaming {
+                    write_to(port, format_args!("{playing} serial stream\r\n")).await;
+                } else {
+                    write_to(
+                        port,
+                        format_args!(
+                            "{playing} {number}/{} {}{}\r\n",
+                            Musics::COUNT,
+                            track.title(),
+                            Artist(track.artist()),
+                        ),
+                    )
+                    .await;
+                }
+                write_to(
+                    port,
+                    format_args!(
+                        "{} / {} ({}%), volume {}%, mode {mode}, eq {}\r\n",
+                        Time(state.position_ms),
+                        Time(state.duration_ms),
+                        state.percentage(),
+                        state.volume,
+                        Gains(&state.eq),
+                    ),
+                )
+                .await;
+            }
+            Format::Json => {
+                write_to(
+                    port,
+                    format_args!(
+                        "{{\"ok\":true,\"state\":{{\"track\":{number},\"title\":{},\"artist\":{},",
+                        Json(Some(track.title())),
+                        Json(track.artist()),
+                    ),
+                )
+                .await;
+                write_to(
+                    port,
+                    format_args!(
+                        "\"playing\":{},\"volume\":{},\"mode\":\"{mode}\",\"position_ms\":{},\"duration_ms\":{},\"eq\":[{}],\"streaming\":{}}}}}\r\n",
+                        state.playing,
+                        state.volume,
+                        state.position_ms,
+                        state.duration_ms,
+                        JsonGains(&state.eq),
+                        state.streaming,
+                    ),
+                )
+                .await;
+            }
+        }
+    }
+
+    async fn list<P: SerialPort>(&self, port: &mut P, current: Musics) {
+        if self.format == Format::Json {
+            write_to(port, format_args!("{{\"ok\":true,\"tracks\":[")).await;
+        }
+        for index in 0..Musics::COUNT as u8 {
+            let track = Musics::from_index(&index);
+            let number = index + 1;
+            match self.format {
+                Format::Human => {
+                    write_to(
+                        port,
+                        format_args!(
+                            "{} {number:>2}. {}{}\r\n",
+                            if track == current { '*' } else { ' ' },
+                            track.title(),
+                            Artist(track.artist()),
+                        ),
+                    )
+                    .await
+                }
+                Format::Json => {
+                    write_to(
+                        port,
+                        format_args!(
+                            "{}{{\"track\":{number},\"title\":{},\"artist\":{}}}",
+                            if index > 0 { "," } else { "" },
+                            Json(Some(track.title())),
+                            Json(track.artist()),
+                        ),
+                    )
+                    .await
+                }
+            }
+        }
+        if self.format == Format::Json {
+            write_to(
+                port,
+                format_args!("],\"current\":{}}}\r\n", current.to_index() + 1),
+            )
+            .await;
+        }
+    }
+
+    async fn help<P: SerialPort>(&self, port: &mut P) {
+        match self.format {
+            Format::Human => {
+                for (usage, description) in COMMANDS {
+                    write_to(port, format_args!("{usage:<34} {description}\r\n")).await;
+                }
+            }
+            Format::Json => {
+                write_to(port, format_args!("{{\"ok\":true,\"commands\":[")).await;
+                for (i, (usage, _)) in COMMANDS.iter().enumerate() {
+                    let separator = if i > 0 { "," } else { "" };
+                    write_to(port, format_args!("{separator}{}", Json(Some(usage)))).await;
+                }
+                write_to(port, format_args!("]}}\r\n")).await;
+            }
+        }
+    }
+
+    async fn echo<P: SerialPort>(&self, port: &mut P, bytes: &[u8]) {
+        if self.format == Format::Human {
+            write_bytes(port, bytes).await;
+        }
+    }
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Answers the commands arriving on `port` and receives the audio streamed
+/// on it, forever.
+pub async fn run_console<P: SerialPort>(mut port: P) -> ! {
+    let mut console = Console::new();
+    let mut stream = StreamReceiver::new();
+    let mut buf = [0; 64];
+
+    write_to(&mut port, format_args!("{PROMPT}")).await;
+    loop {
+        match select(port.read(&mut buf), Timer::after(STREAM_POLL)).await {
+            Either::First(Ok(len)) => {
+                for &byte in &buf[..len] {
+                    if stream.accepts(byte, console.at_line_start()) {
+                        stream.receive(byte).await;
+                    } else {
+                        console.receive(&mut port, byte).await;
+                    }
+                }
+            }
+            Either::First(Err(err)) => log::warn!("Console read failed: {err:?}"),
+            Either::Second(()) => {}
+        }
+        stream.send_credit(&mut port).await;
+    }
+}
+
+/// Formats one piece of a reply and sends it.
+async fn write_to<P: SerialPort>(port: &mut P, args: fmt::Arguments<'_>) {
+    let mut text = Text::new();
+    // Only fails when the text is cut off, which still leaves something to send
+    let _ = text.write_fmt(args);
+    write_bytes(port, text.as_bytes()).await;
+}
+
+async fn write_bytes<P: SerialPort>(port: &mut P, bytes: &[u8]) {
+    if let Err(err) = port.write_all(bytes).await {
+        log::warn!("Console write failed: {err:?}");
+    }
+}
+
+/// Fixed buffer a piece of a reply is formatted into.
+struct Text {
+    bytes: [u8; REPLY_CAPACITY],
+    len: usize,
+}
+
+impl Text {
+    fn new() -> Self {
+        Self {
+            bytes: [0; REPLY_CAPACITY],
+            len: 0,
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+impl Write for Text {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = s.len().min(REPLY_CAPACITY - self.len);
+        self.bytes[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        if len < s.len() {
+            Err(fmt::Error)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Track position as `m:ss`.
+struct Time(u32);
+
+impl fmt::Display for Time {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.0 / 1000;
+        write!(f, "{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+/// ` - Artist` after a title, or nothing when unknown.
+struct Artist(Option<&'static str>);
+
+impl fmt::Display for Artist {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(artist) => write!(f, " - {artist}"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Equalizer gains as `+0.0/-3.0/+2.5 dB`.
+struct Gains<'a>(&'a EqSettings);
+
+impl fmt::Display for Gains<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, band) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_char('/')?;
+            }
+            write!(f, "{:+.1}", band.gain_db)?;
+        }
+        f.write_str(" dB")
+    }
+}
+
+/// Equalizer gains as the items of a JSON array.
+struct JsonGains<'a>(&'a EqSettings);
+
+impl fmt::Display for JsonGains<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, band) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_char(',')?;
+            }
+            write!(f, "{:.1}", band.gain_db)?;
+        }
+        Ok(())
+    }
+}
+
+/// A JSON string with the necessary escapes, or `null`.
+struct Json<'a>(Option<&'a str>);
+
+impl fmt::Display for Json<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(text) = self.0 else {
+            return f.write_str("null");
+        };
+        f.write_char('"')?;
+        for c in text.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+                c => f.write_char(c)?,
+            }
+        }
+        f.write_char('"')
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::hal::mock::{self, MockSerial};
+    use crate::player::PLAYER_STATE;
+
+    /// Types `bytes` into `console`, returning what it wrote back.
+    fn type_in(console: &mut Console, bytes: &[u8]) -> String {
+        let port = MockSerial::new();
+        block_on(async {
+            for &byte in bytes {
+                console.receive(&mut &port, byte).await;
+            }
+        });
+        port.take_text()
+    }
+
+    fn json_console() -> Console {
+        let mut console = Console::new();
+        console.format = Format::Json;
+        console
+    }
+
+    /// Takes the commands sent to the player.
+    fn commands() -> Vec<PlayerCommand> {
+        core::iter::from_fn(|| PLAYER_COMMANDS.try_receive().ok()).collect()
+    }
+
+    /// Publishes a known state, clearing any command left over from another test.
+    fn reset(state: PlayerState) {
+        PLAYER_STATE.sender().send(state);
+        commands();
+    }
+
+    fn playing() -> PlayerState {
+        PlayerState {
+            playing: true,
+            volume: 70,
+            mode: PlaybackMode::RepeatAll,
+            position_ms: 83_500,
+            duration_ms: 200_000,
+            ..PlayerState::default()
+        }
+    }
+
+    #[test]
+    fn parses_every_command() {
+        let mut eq = DEFAULT_EQ;
+        for (band, gain_db) in eq.iter_mut().zip([1.0, -2.5, 24.0]) {
+            band.gain_db = gain_db;
+        }
+        let player = Request::Player;
+        let cases = [
+            ("play", player(PlayerCommand::Play)),
+            ("pause", player(PlayerCommand::Pause)),
+            ("toggle", player(PlayerCommand::Toggle)),
+            ("next", player(PlayerCommand::Next)),
+            ("prev", player(PlayerCommand::Prev)),
+            (
+                "track 1",
+                player(PlayerCommand::Select(Musics::from_index(&0))),
+            ),
+            ("  vol   +5 ", player(PlayerCommand::ChangeVolume(5))),
+            ("vol -100", player(PlayerCommand::ChangeVolume(-100))),
+            ("vol 0", player(PlayerCommand::SetVolume(0))),
+            ("seek -86400", player(PlayerCommand::Seek(-86_400))),
+            ("seek +30", player(PlayerCommand::Seek(30))),
+            ("seek 90", player(PlayerCommand::SeekTo(90_000))),
+            ("seek 999:59", player(PlayerCommand::SeekTo(59_999_000))),
+            ("seek 0:05", player(PlayerCommand::SeekTo(5_000))),
+            (
+                "mode shuffle",
+                player(PlayerCommand::SetMode(PlaybackMode::Shuffle)),
+            ),
+            ("eq flat", player(PlayerCommand::SetEq(DEFAULT_EQ))),
+            ("eq 1 -2.5 24", player(PlayerCommand::SetEq(eq))),
+            ("balance -100", player(PlayerCommand::SetBalance(-100))),
+            ("crossfade 0", player(PlayerCommand::SetCrossfade(0))),
+            ("fade 65535", player(PlayerCommand::SetFade(65_535))),
+            (
+                "quality high",
+                player(PlayerCommand::SetResampleQuality(ResampleQuality::High)),
+            ),
+            ("status", Request::Status),
+            ("list", Request::List),
+            ("help", Request::Help),
+            ("format json", Request::SetFormat(Format::Json)),
+        ];
+        for (line, request) in cases {
+            assert_eq!(Request::parse(line), Ok(request), "{line:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        use ParseError::*;
+        let past_the_end = format!("track {}", Musics::COUNT + 1);
+        let cases = [
+            ("", UnknownCommand),
+            ("jump", UnknownCommand),
+            ("PLAY", UnknownCommand),
+            ("play now", TooManyArguments),
+            ("track", MissingArgument),
+            ("track 0", InvalidArgument),
+            (&past_the_end, InvalidArgument),
+            ("vol", MissingArgument),
+            ("vol 101", InvalidArgument),
+            ("vol +101", InvalidArgument),
+            ("vol loud", InvalidArgument),
+            ("seek", MissingArgument),
+            ("seek +86401", InvalidArgument),
+            ("seek -86401", InvalidArgument),
+            ("seek 60000", InvalidArgument),
+            ("seek 1000:00", InvalidArgument),
+            ("seek 1:60", InvalidArgument),
+            ("seek 1:2:3", InvalidArgument),
+            ("seek -1:00", InvalidArgument),
+            ("mode", MissingArgument),
+            ("mode loop", InvalidArgument),
+            ("eq", MissingArgument),
+            ("eq 1 2", MissingArgument),
+            ("eq 25 0 0", InvalidArgument),
+            ("eq flat 1", TooManyArguments),
+            ("eq 1 2 3 4", TooManyArguments),
+            ("balance 101", InvalidArgument),
+            ("crossfade -1", InvalidArgument),
+            ("fade 65536", InvalidArgument),
+            ("quality best", InvalidArgument),
+            ("format xml", InvalidArgument),
+            ("status now", TooManyArguments),
+        ];
+        for (line, error) in cases {
+            assert_eq!(Request::parse(line), Err(error), "{line:?}");
+        }
+    }
+
+    #[test]
+    fn any_line_ending_ends_one_line() {
+        let _lock = mock::lock();
+        reset(PlayerState::default());
+        let mut console = Console::new();
+
+        for ending in ["\r", "\n", "\r\n"] {
+            let reply = type_in(&mut console, format!("play{ending}").as_bytes());
+            assert_eq!(reply, "play\r\nok\r\n> ", "{ending:?}");
+        }
+        // Blank lines only bring a new prompt
+        assert_eq!(type_in(&mut console, b"\r\n\n  \r"), "\r\n> \r\n>   \r\n> ");
+        assert_eq!(commands(), [PlayerCommand::Play; 3]);
+    }
+
+    #[test]
+    fn backspace_erases_the_last_character() {
+        let _lock = mock::lock();
+        reset(PlayerState::default());
+        let mut console = Console::new();
+
+        assert_eq!(type_in(&mut console, b"\x7f"), "");
+        let reply = type_in(&mut console, b"nxe\x08\x08ext\x1b\r");
+        assert_eq!(reply, "nxe\x08 \x08\x08 \x08ext\r\nok\r\n> ");
+        assert_eq!(commands(), [PlayerCommand::Next]);
+    }
+
+    #[test]
+    fn long_and_garbled_lines_are_rejected() {
+        let _lock = mock::lock();
+        reset(PlayerState::default());
+        let mut console = Console::new();
+
+        let mut line = [b'a'; LINE_CAPACITY + 5].to_vec();
+        line.push(b'\r');
+        let reply = type_in(&mut console, &line);
+        let echo = "a".repeat(LINE_CAPACITY);
+        assert_eq!(reply, format!("{echo}\r\nerror: line too long\r\n> "));
+
+        // Backspace cannot bring an overflowing line back
+        line.splice(LINE_CAPACITY + 1.., *b"\x08\x08\r");
+        assert!(type_in(&mut console, &line).ends_with("error: line too long\r\n> "));
+
+        let reply = type_in(&mut console, b"vol \xC3(\r");
+        assert!(
+            reply.ends_with("\r\nerror: invalid text\r\n> "),
+            "{reply:?}"
+        );
+        // The next line starts afresh
+        assert_eq!(type_in(&mut console, b"pause\r"), "pause\r\nok\r\n> ");
+        assert_eq!(commands(), [PlayerCommand::Pause]);
+    }
+
+    #[test]
+    fn human_replies() {
+        let _lock = mock::lock();
+        reset(playing());
+        let mut console = Console::new();
+        let track = Musics::from_index(&0);
+
+        let reply = type_in(&mut console, b"status\r");
+        let expected = format!(
+            "status\r\nplaying 1/{} {}{}\r\n1:23 / 3:20 (41%), volume 70%, mode all, eq +0.0/+0.0/+0.0 dB\r\n> ",
+            Musics::COUNT,
+            track.title(),
+            Artist(track.artist()),
+        );
+        assert_eq!(reply, expected);
+
+        reset(PlayerState {
+            streaming: true,
+            position_ms: 12_345,
+            duration_ms: 0,
+            ..playing()
+        });
+        let reply = type_in(&mut console, b"status\r");
+        let expected = "status\r\nplaying serial stream\r\n0:12 / 0:00 (0%), volume 70%, mode all, eq +0.0/+0.0/+0.0 dB\r\n> ";
+        assert_eq!(reply, expected);
+
+        let reply = type_in(&mut console, b"list\r");
+        let lines: Vec<&str> = reply.split("\r\n").collect();
+        assert_eq!(lines.len(), Musics::COUNT + 2);
+        assert_eq!(
+            lines[1],
+            format!("*  1. {}{}", track.title(), Artist(track.artist()))
+        );
+        assert!(
+            lines[2..=Musics::COUNT]
+                .iter()
+                .all(|line| line.starts_with("  "))
+        );
+
+        let reply = type_in(&mut console, b"help\r");
+        assert_eq!(reply.lines().count(), COMMANDS.len() + 2);
+        assert!(reply.contains("\r\nplay                               resume playback\r\n"));
+
+        let reply = type_in(&mut console, b"vol 200\r");
+        assert_eq!(reply, "vol 200\r\nerror: invalid argument\r\n> ");
+        assert!(commands().is_empty());
+    }
+
+    #[test]
+    fn json_replies() {
+        let _lock = mock::lock();
+        reset(playing());
+        let mut console = Console::new();
+        let track = Musics::from_index(&0);
+
+        // Switching turns the echo and the prompt off, starting with the reply itself
+        let reply = type_in(&mut console, b"format json\r");
+        assert_eq!(reply, "format json\r\n{\"ok\":true}\r\n");
+
+        let reply = type_in(&mut console, b"status\r");
+        let expected = format!(
+            "{{\"ok\":true,\"state\":{{\"track\":1,\"title\":{},\"artist\":{},\"playing\":true,\"volume\":70,\"mode\":\"all\",\"position_ms\":83500,\"duration_ms\":200000,\"eq\":[0.0,0.0,0.0],\"streaming\":false}}}}\r\n",
+            Json(Some(track.title())),
+            Json(track.artist()),
+        );
+        assert_eq!(reply, expected);
+
+        let reply = type_in(&mut console, b"list\r");
+        assert!(reply.starts_with(&format!(
+            "{{\"ok\":true,\"tracks\":[{{\"track\":1,\"title\":{},\"artist\":{}}},",
+            Json(Some(track.title())),
+            Json(track.artist()),
+        )));
+        assert!(reply.ends_with("],\"current\":1}\r\n"));
+        assert_eq!(reply.matches("{\"track\":").count(), Musics::COUNT);
+
+        let reply = type_in(&mut console, b"help\r");
+        assert!(reply.starts_with("{\"ok\":true,\"commands\":[\"play\",\"pause\","));
+        assert!(reply.ends_with(",\"format <human|json>\"]}\r\n"));
+
+        assert_eq!(type_in(&mut console, b"next\r"), "{\"ok\":true}\r\n");
+        let reply = type_in(&mut json_console(), b"seek 1:60\r");
+        assert_eq!(reply, "{\"ok\":false,\"error\":\"invalid argument\"}\r\n");
+        let reply = type_in(&mut json_console(), b"bogus\r");
+        assert_eq!(reply, "{\"ok\":false,\"error\":\"unknown command\"}\r\n");
+        assert_eq!(commands(), [PlayerCommand::Next]);
+    }
+
+    #[test]
+    fn json_strings_are_escaped() {
+        let text = format!("{}", Json(Some("say \"hi\"\\\n")));
+        assert_eq!(text, r#""say \"hi\"\\\u000a""#);
+        assert_eq!(format!("{}", Json(None)), "null");
+    }
+
+    #[test]
+    fn run_console_answers_the_port() {
+        let _lock = mock::lock();
+        reset(playing());
+        let port = MockSerial::new();
+
+        let output = mock::run(run_console(&port), async {
+            let mut output = String::new();
+            mock::wait_until("the prompt", || {
+                output += &port.take_text();
+                output == "> "
+            })
+            .await;
+            port.send(b"vol +5\r\nstatus\r\n");
+            mock::wait_until("the reply", || {
+                output += &port.take_text();
+                output.matches("> ").count() == 3
+            })
+            .await;
+            output
+        });
+
+        assert!(output.starts_with("> vol +5\r\nok\r\n> status\r\nplaying 1/"));
+        assert_eq!(commands(), [PlayerCommand::ChangeVolume(5)]);
+    }
+
+    /// Offers `byte` to `stream` the way [`run_console`] does, returning whether it took it.
+    fn offer(stream: &mut StreamReceiver, byte: u8, line_start: bool) -> bool {
+        let accepted = stream.accepts(byte, line_start);
+        if accepted {
+            block_on(stream.receive(byte));
+        }
+        accepted
+    }
+
+    #[test]
+    fn frames_only_start_at_the_start_of_a_line() {
+        let mut stream = StreamReceiver::new();
+        // The second byte of "ť" or "¥" typed mid-line
+        assert!(!offer(&mut stream, 0xA5, false));
+        assert!(offer(&mut stream, 0xA5, true));
+        assert!(offer(&mut stream, 0x5A, false));
+
+        // Without the rest of the sync word, the next byte is text again
+        let mut stream = StreamReceiver::new();
+        assert!(offer(&mut stream, 0xA5, true));
+        assert!(!offer(&mut stream, b'v', false));
+        assert!(!offer(&mut stream, b'o', false));
+    }
+
+    #[test]
+    fn run_console_keeps_text_with_the_sync_byte() {
+        let _lock = mock::lock();
+        reset(playing());
+        let port = MockSerial::new();
+
+        let output = mock::run(run_console(&port), async {
+            let mut output = String::new();
+            port.send("eq ť¥\r\nvol +5\r\n".as_bytes());
+            mock::wait_until("the replies", || {
+                output += &port.take_text();
+                output.matches("> ").count() == 3
+            })
+            .await;
+            output
+        });
+
+        assert_eq!(
+            output,
+            "> eq ť¥\r\nerror: invalid argument\r\n> vol +5\r\nok\r\n> "
+        );
+        assert_eq!(commands(), [PlayerCommand::ChangeVolume(5)]);
+    }
+}
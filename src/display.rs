@@ -1,141 +1,323 @@
-use core::sync::atomic::Ordering;
-use display_interface_i2c::I2CInterface;
-use embassy_time::{Duration, Timer};
+use core::sync::atomic::{AtomicU8, Ordering};
+use embassy_futures::select::{Either, select};
+use embassy_time::{Duration, Instant, Timer};
 use embedded_graphics::{
     image::Image,
-    mono_font::{MonoTextStyle, ascii::FONT_7X13_BOLD},
+    mono_font::{MonoTextStyle, ascii::FONT_4X6},
     pixelcolor::BinaryColor,
     prelude::*,
     primitives::{PrimitiveStyleBuilder, Rectangle},
-    text::Text,
+    text::{Alignment, Text},
 };
-use esp_hal::{Async, i2c::master::I2c};
-use oled_async::{displays::sh1106, mode::GraphicsMode};
 use tinybmp::Bmp;
 
-use crate::assets::{
-    NEXT_BYTES, PAUSE_BYTES, PLAY_BYTES, PREV_BYTES, SOUND_ICON_BYTES, SOUND_WAVE_BYTES,
-};
-use crate::audio::{CURRENT_MUSIC_INDEX, CURRENT_PERCENTAGE, IS_PLAYING, VOLUME};
-use crate::music::Musics;
-
-/// Type alias for the SH1106 OLED display using I2C and Async mode.
-pub type OledDisplay = GraphicsMode<sh1106::Sh1106_128_64, I2CInterface<I2c<'static, Async>>>;
-
-/// Main task for UI rendering.
-#[embassy_executor::task]
-pub async fn display_task(mut display: OledDisplay) {
-    display.init().await.unwrap();
-    display.flush().await.unwrap();
-
-    let style = MonoTextStyle::new(&FONT_7X13_BOLD, BinaryColor::On);
-    // Load animated sound wave GIF from static assets
-    let wave_gif = tinygif::Gif::<BinaryColor>::from_slice(SOUND_WAVE_BYTES).unwrap();
-    let mut wave_iter = wave_gif.frames();
-    let mut current_frame = wave_iter.next().unwrap();
-    loop {
-        display.clear();
-
-        // --- 1. Animation Logic ---
-        // Increment GIF frame only if audio is playing
-        if IS_PLAYING.load(Ordering::Relaxed) {
-            match wave_iter.next() {
-                Some(frame) => {
-                    current_frame = frame;
-                }
-                None => {
-                    wave_iter = wave_gif.frames();
-                    current_frame = wave_iter.next().unwrap();
-                }
+use crate::assets::{NEXT_BYTES, PAUSE_BYTES, PLAY_BYTES, PREV_BYTES, SOUND_ICON_BYTES};
+use crate::audio::SPECTRUM;
+use crate::dsp::SpectrumBands;
+use crate::hal::Panel;
+use crate::menu::{MENU_INPUT, MENU_OPEN, Menu, MenuAction, MenuInput};
+use crate::music::{ARTIST_FONT, Musics, TITLE_AREA_WIDTH, TITLE_FONT};
+use crate::player::{PLAYER_COMMANDS, PlayerCommand, PlayerState};
+
+/// Panel brightness (contrast register, 0-255), set from the menu.
+pub static BRIGHTNESS: AtomicU8 = AtomicU8::new(128);
+
+/// Scroll speed of titles wider than the title area, in pixels per second.
+const MARQUEE_SPEED: u64 = 20;
+/// Blank space between a scrolling title and its next repetition, in pixels.
+const MARQUEE_GAP: i32 = 24;
+/// Time the start of a scrolling title stays still before each pass, in milliseconds.
+const MARQUEE_HOLD_MS: u64 = 1500;
+/// Shown in place of the track title while audio streams in over the serial port.
+const STREAM_TITLE: &str = "Serial stream";
+
+/// Everything shown on the 128x64 screen, independent of the panel driver.
+///
+/// Draws the player screen, or the settings [`Menu`] while it is open, onto
+/// any monochrome [`DrawTarget`]: the SH1106 on the board, a framebuffer in
+/// the simulator.
+pub struct Ui {
+    menu: Menu,
+    /// The marquee restarts from the beginning whenever the track changes
+    shown_music: Option<Musics>,
+    title_since: Instant,
+}
+
+impl Ui {
+    pub fn new() -> Self {
+        Self {
+            menu: Menu::new(BRIGHTNESS.load(Ordering::Relaxed)),
+            shown_music: None,
+            title_since: Instant::now(),
+        }
+    }
+
+    /// Feeds an input to the menu and carries out the resulting action.
+    /// Returns the new brightness when it changed, for the caller to apply to the panel.
+    pub async fn handle(&mut self, input: MenuInput) -> Option<u8> {
+        let state = PlayerState::current();
+
+        let brightness = match self.menu.handle(input, &state) {
+            Some(MenuAction::PlayTrack(music)) => {
+                PLAYER_COMMANDS.send(PlayerCommand::Select(music)).await;
+                None
+            }
+            Some(MenuAction::SetEq(settings)) => {
+                PLAYER_COMMANDS.send(PlayerCommand::SetEq(settings)).await;
+                None
             }
+            Some(MenuAction::SetMode(mode)) => {
+                PLAYER_COMMANDS.send(PlayerCommand::SetMode(mode)).await;
+                None
+            }
+            Some(MenuAction::SetBrightness(level)) => {
+                BRIGHTNESS.store(level, Ordering::Relaxed);
+                Some(level)
+            }
+            None => None,
+        };
+        MENU_OPEN.store(self.menu.is_open(), Ordering::Relaxed);
+        brightness
+    }
+
+    /// Draws the current frame onto a cleared `target`.
+    pub fn draw<D>(&mut self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        let state = PlayerState::current();
+
+        if self.shown_music != Some(state.track) {
+            self.shown_music = Some(state.track);
+            self.title_since = Instant::now();
         }
 
-        // --- 2. Track Title ---
-        let curr_music = Musics::from_index(&CURRENT_MUSIC_INDEX.load(Ordering::Relaxed));
-        Text::new(curr_music.title(), curr_music.title_pos(), style)
-            .draw(&mut display)
-            .unwrap();
-
-        // --- 3. Sound Visualizer ---
-        // Renders the current git frame for a moving effect
-        let (x, y) = (23, 22);
-        for offset in [42, 21, 0] {
-            current_frame
-                .draw(&mut display.translated(Point::new(x + offset, y)))
-                .unwrap();
+        if self.menu.is_open() {
+            self.menu.draw(target, state.track, state.mode)
+        } else {
+            draw_player(target, &state, self.title_since)
         }
+    }
 
-        // --- 4. Control Icons (BMP) ---
-        // Next & Previous
-        Image::new(
-            &Bmp::from_slice(NEXT_BYTES).unwrap(),
-            Point::new(x + 68, y + 4),
-        )
-        .draw(&mut display)
-        .unwrap();
-        Image::new(
-            &Bmp::from_slice(PREV_BYTES).unwrap(),
-            Point::new(x - 18, y + 4),
-        )
-        .draw(&mut display)
-        .unwrap();
+    /// Time until the next frame: fast refresh for the spectrum, slow refresh when idle.
+    pub fn frame_interval(&self) -> Duration {
+        if PlayerState::current().playing && !self.menu.is_open() {
+            Duration::from_millis(40)
+        } else {
+            Duration::from_millis(100)
+        }
+    }
+}
 
-        // Play/Pause toggle icon
-        Image::new(
-            &Bmp::from_slice(get_play_pause_icon()).unwrap(),
-            Point::new(6, 52),
-        )
-        .draw(&mut display)
-        .unwrap();
-
-        // --- 5. Gauges ---
-        // Playback progress (Horizontal)
-        draw_progress_bar(
-            &mut display,
-            CURRENT_PERCENTAGE.load(Ordering::Relaxed),
-            Point::new(20, 52),
-            Size::new(80, 10),
-            Orientation::Horizontal,
-        )
-        .unwrap();
-
-        // Volume level (Vertical)
-        draw_progress_bar(
-            &mut display,
-            VOLUME.load(Ordering::Relaxed),
-            Point::new(115, 3),
-            Size::new(10, 45),
-            Orientation::Vertical,
-        )
-        .unwrap();
+impl Default for Ui {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-        // Volume icon
-        Image::new(
-            &Bmp::from_slice(SOUND_ICON_BYTES).unwrap(),
-            Point::new(115, 52),
-        )
-        .draw(&mut display)
-        .unwrap();
+/// Keeps `panel` showing the [`Ui`] and applies the menu input. Never returns.
+///
+/// Menu input wakes the loop immediately so navigation does not wait for the next frame.
+pub async fn render_ui<P: Panel>(mut panel: P) -> ! {
+    apply_brightness(&mut panel, BRIGHTNESS.load(Ordering::Relaxed)).await;
+
+    let mut ui = Ui::new();
+    let mut input = None;
+
+    loop {
+        // Apply the input that woke us, plus any other queued up
+        while let Some(event) = input.take().or_else(|| MENU_INPUT.try_receive().ok()) {
+            if let Some(level) = ui.handle(event).await {
+                apply_brightness(&mut panel, level).await;
+            }
+        }
+
+        panel.clear(BinaryColor::Off).unwrap();
+        ui.draw(&mut panel).unwrap();
 
         // Send buffer to the physical display
-        display.flush().await.unwrap();
+        panel.flush().await.unwrap();
 
-        // Frame rate control: Fast refresh for animation, slow refresh when idle
-        if IS_PLAYING.load(Ordering::Relaxed) {
-            let delay = (current_frame.delay_centis as u64) * 3;
-            Timer::after(Duration::from_millis(delay.max(10))).await;
-        } else {
-            Timer::after(Duration::from_millis(100)).await;
+        let frame = ui.frame_interval();
+        if let Either::Second(event) = select(Timer::after(frame), MENU_INPUT.receive()).await {
+            input = Some(event);
         }
     }
 }
 
-fn get_play_pause_icon() -> &'static [u8] {
-    if IS_PLAYING.load(Ordering::Relaxed) {
-        PAUSE_BYTES
+async fn apply_brightness<P: Panel>(panel: &mut P, level: u8) {
+    if let Err(err) = panel.set_brightness(level).await {
+        log::warn!("Failed to set brightness: {err:?}");
+    }
+}
+
+/// Draws the main player screen: title, spectrum, controls and gauges.
+fn draw_player<D>(
+    display: &mut D,
+    state: &PlayerState,
+    title_since: Instant,
+) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let style = MonoTextStyle::new(&TITLE_FONT, BinaryColor::On);
+    let artist_style = MonoTextStyle::new(&ARTIST_FONT, BinaryColor::On);
+    let small_style = MonoTextStyle::new(&FONT_4X6, BinaryColor::On);
+
+    // --- 1. Track Title and Artist ---
+    let curr_music = state.track;
+    let title_pos = curr_music.title_pos();
+    if state.streaming {
+        Text::with_alignment(
+            STREAM_TITLE,
+            Point::new(TITLE_AREA_WIDTH / 2, title_pos.y),
+            style,
+            Alignment::Center,
+        )
+        .draw(display)?;
+    } else if curr_music.title_overflows() {
+        // Two copies scrolling left, clipped so they never run over the volume gauge
+        let offset = marquee_offset(title_since.elapsed().as_millis(), curr_music.title_width());
+        let title_area = Rectangle::new(
+            Point::new(0, title_pos.y - TITLE_FONT.baseline as i32),
+            Size::new(TITLE_AREA_WIDTH as u32, TITLE_FONT.character_size.height),
+        );
+        let mut clipped = display.clipped(&title_area);
+        for x in [-offset, curr_music.title_width() + MARQUEE_GAP - offset] {
+            Text::new(curr_music.title(), Point::new(x, title_pos.y), style).draw(&mut clipped)?;
+        }
     } else {
-        PLAY_BYTES
+        Text::new(curr_music.title(), title_pos, style).draw(display)?;
+    }
+    if !state.streaming
+        && let (Some(artist), Some(artist_pos)) = (curr_music.artist(), curr_music.artist_pos())
+    {
+        Text::new(artist, artist_pos, artist_style).draw(display)?;
+    }
+
+    // --- 2. Spectrum Analyzer ---
+    // Bars computed by the audio task from the samples sent to the DAC
+    let (x, y) = (23, 22);
+    draw_spectrum(
+        display,
+        &SPECTRUM.load(),
+        Point::new(x, y),
+        Size::new(64, 26),
+    )?;
+
+    // --- 3. Control Icons (BMP) ---
+    // Next & Previous
+    Image::new(
+        &Bmp::from_slice(NEXT_BYTES).unwrap(),
+        Point::new(x + 68, y + 4),
+    )
+    .draw(display)?;
+    Image::new(
+        &Bmp::from_slice(PREV_BYTES).unwrap(),
+        Point::new(x - 18, y + 4),
+    )
+    .draw(display)?;
+
+    // Playback mode, under the Next icon
+    Text::new(state.mode.label(), Point::new(x + 70, y + 26), small_style).draw(display)?;
+
+    // Play/Pause toggle icon
+    Image::new(
+        &Bmp::from_slice(if state.playing {
+            PAUSE_BYTES
+        } else {
+            PLAY_BYTES
+        })
+        .unwrap(),
+        Point::new(6, 52),
+    )
+    .draw(display)?;
+
+    // --- 4. Gauges ---
+    // Elapsed and remaining time above the progress bar; a stream has no end to count down to
+    let (position, duration) = (state.position_ms, state.duration_ms);
+    let mut buf = [0u8; 6];
+    Text::new(
+        format_time(position, false, &mut buf),
+        Point::new(20, 55),
+        small_style,
+    )
+    .draw(display)?;
+    if !state.streaming {
+        Text::with_alignment(
+            format_time(duration.saturating_sub(position), true, &mut buf),
+            Point::new(100, 55),
+            small_style,
+            Alignment::Right,
+        )
+        .draw(display)?;
+    }
+
+    // Playback progress (Horizontal)
+    draw_progress_bar(
+        display,
+        state.percentage(),
+        Point::new(20, 57),
+        Size::new(80, 6),
+        Orientation::Horizontal,
+    )?;
+
+    // Volume level (Vertical)
+    draw_progress_bar(
+        display,
+        state.volume,
+        Point::new(115, 3),
+        Size::new(10, 45),
+        Orientation::Vertical,
+    )?;
+
+    // Volume icon
+    Image::new(
+        &Bmp::from_slice(SOUND_ICON_BYTES).unwrap(),
+        Point::new(115, 52),
+    )
+    .draw(display)?;
+
+    Ok(())
+}
+
+/// Horizontal scroll of a marquee title of `width` pixels, `elapsed_ms` after it appeared.
+///
+/// Each pass holds the start of the title still for [`MARQUEE_HOLD_MS`], then
+/// scrolls until the following copy is back at the left edge. Titles that fit
+/// the title area never move.
+fn marquee_offset(elapsed_ms: u64, width: i32) -> i32 {
+    if width <= TITLE_AREA_WIDTH {
+        return 0;
     }
+    let cycle = (width + MARQUEE_GAP) as u64;
+    let scroll_ms = cycle * 1000 / MARQUEE_SPEED;
+    let t = elapsed_ms % (MARQUEE_HOLD_MS + scroll_ms);
+    (t.saturating_sub(MARQUEE_HOLD_MS) * MARQUEE_SPEED / 1000) as i32
+}
+
+/// Formats a time as `mm:ss` into `buf`, with a leading `-` for remaining time.
+/// Times past `99:59` show as `99:59`, to keep the width fixed.
+fn format_time(ms: u32, remaining: bool, buf: &mut [u8; 6]) -> &str {
+    let seconds = (ms / 1000).min(99 * 60 + 59);
+    let minutes = seconds / 60;
+    let seconds = seconds % 60;
+    let digit = |n: u32| b'0' + n as u8;
+
+    let start = if remaining {
+        buf[0] = b'-';
+        0
+    } else {
+        1
+    };
+    buf[1..].copy_from_slice(&[
+        digit(minutes / 10),
+        digit(minutes % 10),
+        b':',
+        digit(seconds / 10),
+        digit(seconds % 10),
+    ]);
+    core::str::from_utf8(&buf[start..]).unwrap()
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -144,9 +326,38 @@ pub enum Orientation {
     Vertical,
 }
 
+/// Draws the spectrum as vertical bars growing from the bottom of the area.
+/// Each band gets an equal slice of the width, with a 1px gap between bars.
+fn draw_spectrum<D>(
+    target: &mut D,
+    bands: &SpectrumBands,
+    position: Point,
+    size: Size,
+) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let fill_style = PrimitiveStyleBuilder::new()
+        .fill_color(BinaryColor::On)
+        .build();
+
+    let slot = size.width / bands.len() as u32;
+    for (i, &level) in bands.iter().enumerate() {
+        // Keep a 1px floor so silent bands still read as a baseline
+        let height = ((size.height * level.min(100) as u32) / 100).max(1);
+        let bar_position =
+            position + Point::new((i as u32 * slot) as i32, (size.height - height) as i32);
+        Rectangle::new(bar_position, Size::new(slot - 1, height))
+            .into_styled(fill_style)
+            .draw(target)?;
+    }
+
+    Ok(())
+}
+
 /// Draws a stylized progress bar.
 /// Supports both Horizontal (fill from left) and Vertical (fill from bottom) orientations.
-fn draw_progress_bar<D>(
+pub fn draw_progress_bar<D>(
     target: &mut D,
     progress: u8,
     position: Point,
@@ -200,3 +411,159 @@ where
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsp::{DEFAULT_EQ, EQ_BANDS};
+    use crate::hal::mock::{self, MockPanel};
+    use crate::player::PLAYER_STATE;
+    use crate::playlist::PlaybackMode;
+
+    /// Starts from the default player state with the menu closed and no input queued.
+    fn reset() {
+        PLAYER_STATE.sender().send(PlayerState::default());
+        while MENU_INPUT.try_receive().is_ok() {}
+        MENU_OPEN.store(false, Ordering::Relaxed);
+        BRIGHTNESS.store(128, Ordering::Relaxed);
+    }
+
+    fn time(ms: u32, remaining: bool) -> String {
+        format_time(ms, remaining, &mut [0; 6]).to_string()
+    }
+
+    #[test]
+    fn times_show_as_minutes_and_seconds() {
+        assert_eq!(time(0, false), "00:00");
+        assert_eq!(time(999, false), "00:00");
+        assert_eq!(time(61_000, false), "01:01");
+        assert_eq!(time(59 * 60_000 + 59_999, true), "-59:59");
+        // An hour or more keeps counting in minutes, up to 99:59
+        assert_eq!(time(3_600_000, false), "60:00");
+        assert_eq!(time(99 * 60_000 + 59_000, false), "99:59");
+        assert_eq!(time(100 * 60_000 + 30_000, false), "99:59");
+        assert_eq!(time(u32::MAX, true), "-99:59");
+    }
+
+    #[test]
+    fn a_zero_length_track_counts_nothing_down() {
+        let state = PlayerState::default();
+        assert_eq!(state.duration_ms, 0);
+        let remaining = state.duration_ms.saturating_sub(state.position_ms);
+        assert_eq!(time(remaining, true), "-00:00");
+        assert_eq!(state.percentage(), 0);
+    }
+
+    #[test]
+    fn marquee_holds_then_scrolls_one_cycle() {
+        let width = TITLE_AREA_WIDTH + 40;
+        let cycle = width + MARQUEE_GAP;
+        let scroll_ms = cycle as u64 * 1000 / MARQUEE_SPEED;
+
+        assert_eq!(marquee_offset(0, width), 0);
+        assert_eq!(marquee_offset(MARQUEE_HOLD_MS, width), 0);
+        assert_eq!(
+            marquee_offset(MARQUEE_HOLD_MS + 1000, width),
+            MARQUEE_SPEED as i32
+        );
+        assert_eq!(
+            marquee_offset(MARQUEE_HOLD_MS + scroll_ms - 1, width),
+            cycle - 1
+        );
+        // The next pass starts over, held at the start
+        let period = MARQUEE_HOLD_MS + scroll_ms;
+        assert_eq!(marquee_offset(period, width), 0);
+        assert_eq!(marquee_offset(period + MARQUEE_HOLD_MS + 500, width), 10);
+    }
+
+    #[test]
+    fn titles_that_fit_do_not_scroll() {
+        for elapsed_ms in [0, MARQUEE_HOLD_MS + 1000, 60_000] {
+            assert_eq!(marquee_offset(elapsed_ms, 30), 0);
+            assert_eq!(marquee_offset(elapsed_ms, TITLE_AREA_WIDTH), 0);
+        }
+    }
+
+    #[test]
+    fn render_ui_applies_the_brightness_and_keeps_drawing() {
+        let _lock = mock::lock();
+        reset();
+        let panel = MockPanel::new();
+
+        mock::run(render_ui(&panel), async {
+            mock::wait_until("a few frames", || panel.flushes() >= 3).await;
+        });
+
+        assert_eq!(panel.brightness(), Some(128));
+        assert!(panel.lit(Rectangle::new(Point::zero(), Size::new(128, 64))) > 0);
+    }
+
+    #[test]
+    fn render_ui_shows_the_menu_and_applies_its_brightness() {
+        let _lock = mock::lock();
+        reset();
+        let panel = MockPanel::new();
+
+        // The main menu page, drawn directly
+        let expected = MockPanel::new();
+        let mut ui = Ui::new();
+        embassy_futures::block_on(async {
+            ui.handle(MenuInput::Toggle).await;
+            ui.draw(&mut &expected).unwrap();
+            (&expected).flush().await.unwrap();
+        });
+        MENU_OPEN.store(false, Ordering::Relaxed);
+
+        mock::run(render_ui(&panel), async {
+            MENU_INPUT.send(MenuInput::Toggle).await;
+            mock::wait_until("the menu", || panel.frame() == expected.frame()).await;
+            assert!(MENU_OPEN.load(Ordering::Relaxed));
+
+            // Down to "Brightness", open it and turn it up a step
+            for input in [MenuInput::Increment; 3] {
+                MENU_INPUT.send(input).await;
+            }
+            MENU_INPUT.send(MenuInput::Select).await;
+            MENU_INPUT.send(MenuInput::Increment).await;
+            mock::wait_until("the new brightness", || panel.brightness() == Some(144)).await;
+        });
+
+        assert_eq!(BRIGHTNESS.load(Ordering::Relaxed), 144);
+        BRIGHTNESS.store(128, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn menu_actions_become_player_commands() {
+        let _lock = mock::lock();
+        reset();
+        while PLAYER_COMMANDS.try_receive().is_ok() {}
+        let mut ui = Ui::new();
+        let state = PlayerState::default();
+
+        let commands = embassy_futures::block_on(async {
+            use MenuInput::*;
+            // Equalizer: "Reset", below the bands
+            let mut inputs = vec![Toggle, Increment, Select];
+            inputs.extend([Increment; EQ_BANDS]);
+            // Mode: the one after the current; tracks: likewise, which closes the menu
+            inputs.extend([Select, Back, Increment, Select, Increment, Select, Back]);
+            inputs.extend([Decrement, Decrement, Select, Increment, Select]);
+            for input in inputs {
+                ui.handle(input).await;
+            }
+            core::iter::from_fn(|| PLAYER_COMMANDS.try_receive().ok()).collect::<Vec<_>>()
+        });
+
+        let mode = PlaybackMode::from_index(state.mode.to_index() + 1);
+        let track = state.track.next();
+        assert_eq!(
+            commands,
+            [
+                PlayerCommand::SetEq(DEFAULT_EQ),
+                PlayerCommand::SetMode(mode),
+                PlayerCommand::Select(track),
+            ]
+        );
+        assert!(!MENU_OPEN.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,337 @@
+//! IMA/DVI ADPCM codec, as stored in WAV files (format tag `0x0011`).
+//!
+//! Each 16-bit sample is coded as a 4-bit step relative to a running
+//! prediction, giving roughly 4:1 compression. Data is split into blocks that
+//! start with a 4-byte header per channel (initial predictor and step index),
+//! followed by 4-byte groups of eight nibbles, interleaved per channel.
+//!
+//! This file only depends on `core` and is also compiled into `build.rs` and
+//! `pds-sender`, so the encoder used to compress the assets and the streams
+//! always matches the decoder. Whole tracks are only encoded on the host, by
+//! [`encode_pcm_to_adpcm`]; the firmware has no allocator.
+
+/// Quantizer step sizes indexed by the step index.
+const STEP_TABLE: [i32; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66,
+    73, 80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408, 449,
+    494, 544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066, 2272,
+    2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630, 9493,
+    10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794, 32767,
+];
+
+/// Step index adjustment for each coded nibble.
+const INDEX_TABLE: [i8; 16] = [-1, -1, -1, -1, 2, 4, 6, 8, -1, -1, -1, -1, 2, 4, 6, 8];
+
+/// Size of the per-channel block header, in bytes.
+pub const HEADER_SIZE: usize = 4;
+/// Block size per channel written by [`encode_pcm_to_adpcm`]. 256 bytes hold
+/// 505 frames (~46 ms at 11025 Hz).
+pub const BLOCK_SIZE_PER_CHANNEL: usize = 256;
+
+/// Predictor state of one channel.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AdpcmChannel {
+    predictor: i16,
+    step_index: u8,
+}
+
+impl AdpcmChannel {
+    pub const fn new() -> Self {
+        Self {
+            predictor: 0,
+            step_index: 0,
+        }
+    }
+
+    /// Loads the state stored in a block header. Returns the first sample of the block.
+    pub fn load_header(&mut self, header: &[u8]) -> i16 {
+        self.predictor = i16::from_le_bytes([header[0], header[1]]);
+        self.step_index = header[2].min(88);
+        self.predictor
+    }
+
+    /// Writes the current state as a block header.
+    pub fn write_header(&self, header: &mut [u8]) {
+        header[0..2].copy_from_slice(&self.predictor.to_le_bytes());
+        header[2] = self.step_index;
+        header[3] = 0;
+    }
+
+    /// Decodes one 4-bit code into a sample.
+    pub fn decode(&mut self, nibble: u8) -> i16 {
+        let step = STEP_TABLE[self.step_index as usize];
+
+        let mut diff = step >> 3;
+        if nibble & 4 != 0 {
+            diff += step;
+        }
+        if nibble & 2 != 0 {
+            diff += step >> 1;
+        }
+        if nibble & 1 != 0 {
+            diff += step >> 2;
+        }
+
+        let predictor = if nibble & 8 != 0 {
+            self.predictor as i32 - diff
+        } else {
+            self.predictor as i32 + diff
+        };
+        self.predictor = predictor.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+
+        let index = self.step_index as i32 + INDEX_TABLE[(nibble & 0x0F) as usize] as i32;
+        self.step_index = index.clamp(0, 88) as u8;
+
+        self.predictor
+    }
+
+    /// Encodes one sample into a 4-bit code, updating the state exactly as the decoder will.
+    pub fn encode(&mut self, sample: i16) -> u8 {
+        let mut step = STEP_TABLE[self.step_index as usize];
+        let mut diff = sample as i32 - self.predictor as i32;
+
+        let mut nibble = 0;
+        if diff < 0 {
+            nibble = 8;
+            diff = -diff;
+        }
+        if diff >= step {
+            nibble |= 4;
+            diff -= step;
+        }
+        step >>= 1;
+        if diff >= step {
+            nibble |= 2;
+            diff -= step;
+        }
+        step >>= 1;
+        if diff >= step {
+            nibble |= 1;
+        }
+
+        self.decode(nibble);
+        nibble
+    }
+}
+
+/// Number of frames stored in a full block.
+pub const fn frames_per_block(block_align: usize, channels: usize) -> usize {
+    if channels == 0 || block_align < HEADER_SIZE * channels {
+        return 0;
+    }
+    (block_align - HEADER_SIZE * channels) * 2 / channels + 1
+}
+
+/// Byte offset, within a block, of the nibble holding frame `frame` of `channel`.
+///
+/// Returns the offset and whether the sample sits in the high nibble.
+/// Frame 0 lives in the header and has no nibble.
+pub const fn nibble_position(frame: usize, channel: usize, channels: usize) -> (usize, bool) {
+    let index = frame - 1;
+    let offset =
+        HEADER_SIZE * channels + (index / 8) * 4 * channels + channel * 4 + (index % 8) / 2;
+    (offset, index % 2 == 1)
+}
+
+/// Decodes frame `frame` of `block` into `out`, one sample per channel in `state`.
+///
+/// Frames must be decoded in order: frame 0 loads the state from the block
+/// header and the following ones step on from it. Returns `false`, leaving
+/// `out` untouched, if the block is too short to hold the frame.
+pub fn decode_frame(
+    block: &[u8],
+    frame: usize,
+    state: &mut [AdpcmChannel],
+    out: &mut [i16],
+) -> bool {
+    let channels = state.len();
+    if frame == 0 {
+        if block.len() < HEADER_SIZE * channels {
+            return false;
+        }
+        for (channel, (slot, sample)) in state.iter_mut().zip(out.iter_mut()).enumerate() {
+            *sample = slot.load_header(&block[channel * HEADER_SIZE..]);
+        }
+    } else {
+        let (last, _) = nibble_position(frame, channels - 1, channels);
+        if last >= block.len() {
+            return false;
+        }
+        for (channel, (slot, sample)) in state.iter_mut().zip(out.iter_mut()).enumerate() {
+            let (offset, high) = nibble_position(frame, channel, channels);
+            let byte = block[offset];
+            *sample = slot.decode(if high { byte >> 4 } else { byte & 0x0F });
+        }
+    }
+    true
+}
+
+/// Encodes one block of interleaved frames.
+///
+/// `frames` must hold exactly `frames_per_block(out.len(), channels) * channels`
+/// samples and `state` one entry per channel.
+pub fn encode_block(frames: &[i16], channels: usize, state: &mut [AdpcmChannel], out: &mut [u8]) {
+    out.fill(0);
+
+    for (channel, slot) in state.iter_mut().enumerate().take(channels) {
+        // The first frame is stored verbatim in the header.
+        slot.predictor = frames[channel];
+        slot.write_header(&mut out[channel * HEADER_SIZE..(channel + 1) * HEADER_SIZE]);
+    }
+
+    let count = frames.len() / channels;
+    for frame in 1..count {
+        for (channel, slot) in state.iter_mut().enumerate().take(channels) {
+            let nibble = slot.encode(frames[frame * channels + channel]);
+            let (offset, high) = nibble_position(frame, channel, channels);
+            out[offset] |= if high { nibble << 4 } else { nibble };
+        }
+    }
+}
+
+#[cfg(not(target_os = "none"))]
+pub use host::encode_pcm_to_adpcm;
+
+/// Encoding of whole tracks, which needs `alloc`.
+#[cfg(not(target_os = "none"))]
+mod host {
+    extern crate alloc;
+
+    use alloc::vec::Vec;
+
+    use super::super::wav::AudioFormat;
+    use super::{AdpcmChannel, BLOCK_SIZE_PER_CHANNEL, encode_block, frames_per_block};
+
+    /// Compresses interleaved 16-bit PCM in `format` to IMA ADPCM blocks of
+    /// [`BLOCK_SIZE_PER_CHANNEL`] per channel, padding the last block with silence
+    /// so every block has the same size. Returns the format of the result.
+    pub fn encode_pcm_to_adpcm(format: AudioFormat, samples: &[i16]) -> (AudioFormat, Vec<u8>) {
+        let channels = format.channels as usize;
+        let block_align = BLOCK_SIZE_PER_CHANNEL * channels;
+        let block_samples = frames_per_block(block_align, channels) * channels;
+
+        let mut state = [AdpcmChannel::new(); 2];
+        let mut data = Vec::with_capacity(samples.len().div_ceil(block_samples) * block_align);
+        let mut padded = Vec::with_capacity(block_samples);
+        for frames in samples.chunks(block_samples) {
+            padded.clear();
+            padded.extend_from_slice(frames);
+            padded.resize(block_samples, 0);
+
+            let start = data.len();
+            data.resize(start + block_align, 0);
+            encode_block(&padded, channels, &mut state, &mut data[start..]);
+        }
+
+        let format =
+            AudioFormat::ima_adpcm(format.sample_rate, format.channels, block_align as u16);
+        (format, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::wav::AudioFormat;
+    use super::*;
+
+    /// Decodes a whole block of interleaved frames.
+    fn decode_block(block: &[u8], channels: usize) -> Vec<i16> {
+        let mut state = vec![AdpcmChannel::new(); channels];
+        let mut frame = vec![0; channels];
+        let mut samples = Vec::new();
+        for index in 0.. {
+            if !decode_frame(block, index, &mut state, &mut frame) {
+                return samples;
+            }
+            samples.extend_from_slice(&frame);
+        }
+        unreachable!()
+    }
+
+    /// A stereo test signal: a triangle wave with a 16-frame period on the left,
+    /// a slow sawtooth on the right.
+    fn signal(frames: usize) -> Vec<i16> {
+        (0..frames as i16)
+            .flat_map(|i| {
+                let triangle = (8 - (i % 16 - 8).abs()) * 3000 - 12_000;
+                [triangle, i % 300 * 97 - 4000]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn block_golden_bytes() {
+        let input = signal(frames_per_block(40, 2));
+        let mut block = [0u8; 40];
+        encode_block(&input, 2, &mut [AdpcmChannel::new(); 2], &mut block);
+        // Headers hold the first frame (-12000, -4000) and step index 0
+        #[rustfmt::skip]
+        assert_eq!(block, [
+            32, 209, 0, 0, 96, 240, 0, 0,
+            119, 119, 119, 119, 119, 119, 22, 17, 55, 169, 170, 170, 33, 34, 34, 51,
+            51, 67, 67, 66, 52, 67, 66, 50, 187, 187, 188, 172, 67, 51, 67, 67,
+        ]);
+    }
+
+    #[test]
+    fn round_trip_tracks_the_input() {
+        let frames = frames_per_block(256, 2);
+        let input = signal(frames);
+        let mut block = [0u8; 256];
+        encode_block(&input, 2, &mut [AdpcmChannel::new(); 2], &mut block);
+
+        let output = decode_block(&block, 2);
+        assert_eq!(output.len(), input.len());
+        // The header frame is exact; after the step size adapts the error stays small
+        assert_eq!(output[..2], input[..2]);
+        for (index, (&decoded, &original)) in output.iter().zip(&input).enumerate().skip(32) {
+            let error = (decoded as i32 - original as i32).abs();
+            assert!(error < 1200, "sample {index}: {decoded} vs {original}");
+        }
+    }
+
+    #[test]
+    fn encoding_a_track_pads_the_last_block() {
+        let input = signal(505 + 10);
+        let (format, data) = encode_pcm_to_adpcm(AudioFormat::pcm(22050, 2, 16), &input);
+        assert_eq!(format, AudioFormat::ima_adpcm(22050, 2, 512));
+        assert_eq!(data.len(), 2 * 512);
+
+        // The same blocks, with the state carried over, as encoding them one by one
+        let mut state = [AdpcmChannel::new(); 2];
+        let mut block = [0u8; 512];
+        encode_block(&input[..1010], 2, &mut state, &mut block);
+        assert_eq!(data[..512], block);
+        let mut last = input[1010..].to_vec();
+        last.resize(1010, 0);
+        encode_block(&last, 2, &mut state, &mut block);
+        assert_eq!(data[512..], block);
+
+        // The padding decodes to silence once the step size has adapted
+        let output = decode_block(&data[512..], 2);
+        assert_eq!(output[..2], input[1010..1012]);
+        assert!(output[2 * 100..].iter().all(|&sample| sample == 0));
+    }
+
+    #[test]
+    fn decoding_saturates_at_full_scale() {
+        let mut channel = AdpcmChannel::new();
+        channel.load_header(&[0xFF, 0x7F, 88, 0]);
+        // Largest positive step from full scale
+        assert_eq!(channel.decode(0x7), i16::MAX);
+        channel.load_header(&[0x00, 0x80, 88, 0]);
+        assert_eq!(channel.decode(0xF), i16::MIN);
+    }
+
+    #[test]
+    fn short_blocks_stop_decoding() {
+        assert!(!decode_frame(
+            &[0; 3],
+            0,
+            &mut [AdpcmChannel::new()],
+            &mut [0]
+        ));
+        // Header plus one byte: frames 1 and 2 only
+        assert_eq!(decode_block(&[0x10, 0, 0, 0, 0x21], 1).len(), 3);
+    }
+}
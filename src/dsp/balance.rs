@@ -0,0 +1,118 @@
+use super::fixed::{Q31, Q31_ONE, scale};
+use super::{AudioProcessor, CHANNELS};
+
+/// Stereo balance control.
+///
+/// Centre leaves both channels untouched; moving towards one side
+/// progressively attenuates the opposite channel, down to silence at the end stop.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Balance {
+    balance: i8,
+    gains: [Q31; CHANNELS],
+}
+
+impl Balance {
+    /// Balance value of a fully left position.
+    pub const LEFT: i8 = -100;
+    /// Balance value of a fully right position.
+    pub const RIGHT: i8 = 100;
+
+    pub const fn new() -> Self {
+        Self {
+            balance: 0,
+            gains: [Q31_ONE; CHANNELS],
+        }
+    }
+
+    /// Sets the balance, from -100 (left only) through 0 (centre) to 100 (right only).
+    pub fn set_balance(&mut self, balance: i8) {
+        let balance = balance.clamp(Self::LEFT, Self::RIGHT);
+        self.balance = balance;
+
+        let attenuation = |amount: i64| (Q31_ONE as i64 * (100 - amount) / 100) as Q31;
+        self.gains = match balance {
+            b if b < 0 => [Q31_ONE, attenuation(-b as i64)],
+            b => [attenuation(b as i64), Q31_ONE],
+        };
+    }
+
+    pub fn balance(&self) -> i8 {
+        self.balance
+    }
+
+    fn is_centered(&self) -> bool {
+        self.balance == 0
+    }
+}
+
+impl Default for Balance {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AudioProcessor for Balance {
+    fn process(&mut self, samples: &mut [i16]) {
+        if self.is_centered() {
+            return;
+        }
+
+        for frame in samples.chunks_exact_mut(CHANNELS) {
+            for (sample, &gain) in frame.iter_mut().zip(&self.gains) {
+                *sample = scale(*sample, gain);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stereo frames with distinct left and right samples, full scale included.
+    const INPUT: [i16; 8] = [1000, -2000, i16::MAX, i16::MIN, -3000, 4000, 123, -456];
+
+    fn balanced(balance: i8) -> [i16; 8] {
+        let mut balancer = Balance::new();
+        balancer.set_balance(balance);
+        let mut samples = INPUT;
+        balancer.process(&mut samples);
+        samples
+    }
+
+    #[test]
+    fn centre_leaves_both_channels_untouched() {
+        assert_eq!(balanced(0), INPUT);
+        assert_eq!(Balance::default(), Balance::new());
+    }
+
+    #[test]
+    fn end_stops_silence_the_opposite_channel() {
+        let left = balanced(Balance::LEFT);
+        let right = balanced(Balance::RIGHT);
+        for (i, &sample) in INPUT.iter().enumerate() {
+            let (kept, silenced) = if i % 2 == 0 {
+                (left, right)
+            } else {
+                (right, left)
+            };
+            assert_eq!(kept[i], sample, "{kept:?}");
+            assert_eq!(silenced[i], 0, "{silenced:?}");
+        }
+
+        // Out of range values stop at the ends
+        let mut balance = Balance::new();
+        balance.set_balance(i8::MIN);
+        assert_eq!(balance.balance(), Balance::LEFT);
+        balance.set_balance(i8::MAX);
+        assert_eq!(balance.balance(), Balance::RIGHT);
+    }
+
+    #[test]
+    fn halfway_halves_the_opposite_channel() {
+        let samples = balanced(50);
+        assert_eq!(samples[0], 500);
+        assert_eq!(samples[1], -2000);
+        assert_eq!(balanced(-50)[1], -1000);
+    }
+}
@@ -0,0 +1,228 @@
+use core::f32::consts::PI;
+
+use super::fixed::{fixed_from_f32, round_shift, saturate_i32};
+
+/// Lowest centre/corner frequency accepted by the designer, in Hz.
+const MIN_FREQUENCY: f32 = 10.0;
+/// Highest centre/corner frequency as a fraction of the sample rate.
+/// Keeps the filter away from Nyquist, where the bilinear transform warps badly.
+const MAX_FREQUENCY_RATIO: f32 = 0.45;
+const MIN_Q: f32 = 0.1;
+const MAX_Q: f32 = 20.0;
+/// Gain range accepted by the designer, in dB.
+pub const MAX_GAIN_DB: f32 = 24.0;
+/// Fractional bits of the fixed-point coefficients (Q6.26).
+/// Leaves enough integer headroom for the largest shelf coefficients at [`MAX_GAIN_DB`].
+const COEFF_FRAC_BITS: u32 = 26;
+
+/// Response shape of a single equalizer band.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterKind {
+    /// Bell-shaped boost/cut around the centre frequency.
+    Peaking,
+    /// Boost/cut below the corner frequency.
+    LowShelf,
+    /// Boost/cut above the corner frequency.
+    HighShelf,
+}
+
+/// User-facing parameters of one equalizer band.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BandConfig {
+    pub kind: FilterKind,
+    /// Centre (peaking) or corner (shelving) frequency in Hz.
+    pub frequency: f32,
+    /// Quality factor; higher values give a narrower band.
+    pub q: f32,
+    /// Boost (positive) or cut (negative) in dB.
+    pub gain_db: f32,
+}
+
+impl BandConfig {
+    pub const fn new(kind: FilterKind, frequency: f32, q: f32, gain_db: f32) -> Self {
+        Self {
+            kind,
+            frequency,
+            q,
+            gain_db,
+        }
+    }
+
+    /// Returns `true` when the band has no audible effect and can be skipped.
+    pub fn is_flat(&self) -> bool {
+        self.gain_db == 0.0
+    }
+}
+
+/// Second-order IIR section (Direct Form I) with coefficients normalised by `a0`.
+///
+/// Coefficients follow the RBJ "Audio EQ Cookbook" formulas. They are designed
+/// in floating point and quantised to Q6.26; filtering itself is pure integer
+/// math with a 64-bit accumulator.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Biquad {
+    b0: i32,
+    b1: i32,
+    b2: i32,
+    a1: i32,
+    a2: i32,
+    x1: i32,
+    x2: i32,
+    y1: i32,
+    y2: i32,
+}
+
+impl Biquad {
+    /// A filter that passes the signal through unchanged.
+    pub const fn identity() -> Self {
+        Self {
+            b0: 1 << COEFF_FRAC_BITS,
+            b1: 0,
+            b2: 0,
+            a1: 0,
+            a2: 0,
+            x1: 0,
+            x2: 0,
+            y1: 0,
+            y2: 0,
+        }
+    }
+
+    /// Designs a filter for `config` at `sample_rate`.
+    ///
+    /// Out-of-range parameters are clamped so the result is always stable;
+    /// a zero sample rate yields the identity filter.
+    pub fn design(config: &BandConfig, sample_rate: u32) -> Self {
+        if sample_rate == 0 || config.is_flat() {
+            return Self::identity();
+        }
+
+        let fs = sample_rate as f32;
+        let frequency = config
+            .frequency
+            .clamp(MIN_FREQUENCY, fs * MAX_FREQUENCY_RATIO);
+        let q = config.q.clamp(MIN_Q, MAX_Q);
+        let gain_db = config.gain_db.clamp(-MAX_GAIN_DB, MAX_GAIN_DB);
+
+        let a = libm::powf(10.0, gain_db / 40.0);
+        let w0 = 2.0 * PI * frequency / fs;
+        let cos_w0 = libm::cosf(w0);
+        let alpha = libm::sinf(w0) / (2.0 * q);
+        let sqrt_a_alpha = 2.0 * libm::sqrtf(a) * alpha;
+
+        let (b0, b1, b2, a0, a1, a2) = match config.kind {
+            FilterKind::Peaking => (
+                1.0 + alpha * a,
+                -2.0 * cos_w0,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos_w0,
+                1.0 - alpha / a,
+            ),
+            FilterKind::LowShelf => (
+                a * ((a + 1.0) - (a - 1.0) * cos_w0 + sqrt_a_alpha),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos_w0),
+                a * ((a + 1.0) - (a - 1.0) * cos_w0 - sqrt_a_alpha),
+                (a + 1.0) + (a - 1.0) * cos_w0 + sqrt_a_alpha,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos_w0),
+                (a + 1.0) + (a - 1.0) * cos_w0 - sqrt_a_alpha,
+            ),
+            FilterKind::HighShelf => (
+                a * ((a + 1.0) + (a - 1.0) * cos_w0 + sqrt_a_alpha),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos_w0),
+                a * ((a + 1.0) + (a - 1.0) * cos_w0 - sqrt_a_alpha),
+                (a + 1.0) - (a - 1.0) * cos_w0 + sqrt_a_alpha,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos_w0),
+                (a + 1.0) - (a - 1.0) * cos_w0 - sqrt_a_alpha,
+            ),
+        };
+
+        Self {
+            b0: fixed_from_f32(b0 / a0, COEFF_FRAC_BITS),
+            b1: fixed_from_f32(b1 / a0, COEFF_FRAC_BITS),
+            b2: fixed_from_f32(b2 / a0, COEFF_FRAC_BITS),
+            a1: fixed_from_f32(a1 / a0, COEFF_FRAC_BITS),
+            a2: fixed_from_f32(a2 / a0, COEFF_FRAC_BITS),
+            ..Self::identity()
+        }
+    }
+
+    /// Replaces the coefficients while keeping the filter history,
+    /// so parameter tweaks during playback do not produce a discontinuity.
+    pub fn update(&mut self, other: &Biquad) {
+        self.b0 = other.b0;
+        self.b1 = other.b1;
+        self.b2 = other.b2;
+        self.a1 = other.a1;
+        self.a2 = other.a2;
+    }
+
+    /// Filters a single sample.
+    ///
+    /// Input and output are kept in 32 bits so cascaded sections can exceed
+    /// the `i16` range in between; saturation happens once at the end of the chain.
+    pub fn run(&mut self, x: i32) -> i32 {
+        let acc = self.b0 as i64 * x as i64
+            + self.b1 as i64 * self.x1 as i64
+            + self.b2 as i64 * self.x2 as i64
+            - self.a1 as i64 * self.y1 as i64
+            - self.a2 as i64 * self.y2 as i64;
+        let y = saturate_i32(round_shift(acc, COEFF_FRAC_BITS));
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+
+    /// Clears the filter history.
+    pub fn reset(&mut self) {
+        self.x1 = 0;
+        self.x2 = 0;
+        self.y1 = 0;
+        self.y2 = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_filters_pass_the_signal_through() {
+        let input: Vec<i32> = (0..500)
+            .map(|i| (i * 7919) % 65_536 - 32_768)
+            .chain([i16::MAX as i32, i16::MIN as i32, 0, 1, -1])
+            .collect();
+
+        for kind in [
+            FilterKind::Peaking,
+            FilterKind::LowShelf,
+            FilterKind::HighShelf,
+        ] {
+            let config = BandConfig::new(kind, 1000.0, 0.7, 0.0);
+            let mut filter = Biquad::design(&config, 11025);
+            assert_eq!(filter, Biquad::identity());
+
+            let output: Vec<i32> = input.iter().map(|&x| filter.run(x)).collect();
+            assert_eq!(output, input);
+        }
+    }
+
+    #[test]
+    fn a_boost_at_the_centre_frequency_raises_the_level() {
+        let config = BandConfig::new(FilterKind::Peaking, 1000.0, 1.0, 6.0);
+        let mut filter = Biquad::design(&config, 8000);
+        // A 1 kHz sine at 8 kHz, past the filter's settling time
+        let peak = (0..800)
+            .map(|n| {
+                let x = 10_000.0 * libm::sinf(2.0 * PI * 1000.0 * n as f32 / 8000.0);
+                filter.run(x as i32)
+            })
+            .skip(400)
+            .max()
+            .unwrap();
+        // +6 dB is about twice the amplitude
+        assert!((19_500..=20_500).contains(&peak), "{peak}");
+    }
+}
@@ -0,0 +1,141 @@
+use core::f32::consts::FRAC_PI_2;
+
+use super::fixed::{Q31, Q31_ONE, add_sat, q31_from_f32, round_shift, scale};
+use super::{CHANNELS, PcmStream, ResampleQuality, Resampler};
+
+/// Segments of the precomputed quarter-sine curve.
+const CURVE_SEGMENTS: usize = 64;
+/// Fractional bits of the position inside a curve segment.
+const CURVE_FRAC_BITS: u32 = 16;
+
+/// Equal-power crossfade between the end of one track and the start of the next.
+///
+/// The outgoing track follows a cosine curve and the incoming one a sine
+/// curve, so the summed power stays constant across the transition. The
+/// incoming track is read through its own [`Resampler`], since both tracks
+/// may be recorded at different rates.
+#[derive(Debug, Clone)]
+pub struct Crossfade {
+    sample_rate: u32,
+    duration_ms: u16,
+    /// Length of the transition in progress, in output frames.
+    length: usize,
+    /// Output frames mixed so far.
+    position: usize,
+    active: bool,
+    /// `sin(x * pi/2)` for `x` in `[0, 1]`, in Q31.
+    curve: [Q31; CURVE_SEGMENTS + 1],
+    resampler: Resampler,
+}
+
+impl Crossfade {
+    /// Creates a crossfade of `duration_ms`; zero disables the overlap.
+    pub fn new(duration_ms: u16, sample_rate: u32) -> Self {
+        let mut curve = [0; CURVE_SEGMENTS + 1];
+        for (i, point) in curve.iter_mut().enumerate() {
+            let x = i as f32 / CURVE_SEGMENTS as f32;
+            *point = q31_from_f32(libm::sinf(x * FRAC_PI_2));
+        }
+        // Lands exactly on unity so the incoming track ends at full level.
+        curve[CURVE_SEGMENTS] = Q31_ONE;
+
+        Self {
+            sample_rate,
+            duration_ms,
+            length: 0,
+            position: 0,
+            active: false,
+            curve,
+            resampler: Resampler::new(ResampleQuality::Medium, sample_rate, sample_rate),
+        }
+    }
+
+    pub fn duration_ms(&self) -> u16 {
+        self.duration_ms
+    }
+
+    /// Sets the overlap used by the next transition; one in progress is not affected.
+    pub fn set_duration_ms(&mut self, duration_ms: u16) {
+        self.duration_ms = duration_ms;
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+    }
+
+    /// Configured overlap, in output frames.
+    pub fn frames(&self) -> usize {
+        (self.duration_ms as u64 * self.sample_rate as u64 / 1000) as usize
+    }
+
+    /// Returns `true` while both tracks are being mixed.
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Starts a transition of `length` output frames towards `incoming`.
+    ///
+    /// `quality` should match the main resampler, whose role this one takes
+    /// over once the transition completes.
+    pub fn begin(&mut self, incoming: &PcmStream, length: usize, quality: ResampleQuality) {
+        self.resampler.set_quality(quality);
+        self.resampler
+            .set_rates(incoming.format().sample_rate, self.sample_rate);
+        self.resampler.reset();
+        self.length = length.max(1);
+        self.position = 0;
+        self.active = true;
+    }
+
+    /// Reads the next interleaved samples of the incoming track at the output rate.
+    pub fn read(&mut self, incoming: &mut PcmStream, out: &mut [i16]) -> usize {
+        self.resampler.read(incoming, out)
+    }
+
+    /// Mixes `incoming` into `outgoing` in place, advancing the transition.
+    pub fn mix(&mut self, outgoing: &mut [i16], incoming: &[i16]) {
+        for (out, inc) in outgoing
+            .chunks_exact_mut(CHANNELS)
+            .zip(incoming.chunks_exact(CHANNELS))
+        {
+            let remaining = self.length.saturating_sub(self.position);
+            let fade_in = self.gain_at(self.position);
+            let fade_out = self.gain_at(remaining);
+            for (a, &b) in out.iter_mut().zip(inc) {
+                *a = add_sat(scale(*a, fade_out), scale(b, fade_in));
+            }
+            self.position += 1;
+        }
+    }
+
+    /// Returns `true` once the outgoing track has faded out completely.
+    pub fn is_complete(&self) -> bool {
+        self.position >= self.length
+    }
+
+    /// Ends the transition and hands over the incoming track's resampler.
+    pub fn finish(&mut self, resampler: &mut Resampler) {
+        core::mem::swap(&mut self.resampler, resampler);
+        self.active = false;
+    }
+
+    /// Abandons any transition in progress, e.g. when the user skips.
+    pub fn reset(&mut self) {
+        self.active = false;
+        self.position = 0;
+        self.length = 0;
+        self.resampler.reset();
+    }
+
+    /// Sine curve evaluated `frames` into the transition, interpolated between table points.
+    fn gain_at(&self, frames: usize) -> Q31 {
+        if frames >= self.length {
+            return Q31_ONE;
+        }
+        let x = ((frames as u64 * CURVE_SEGMENTS as u64) << CURVE_FRAC_BITS) / self.length as u64;
+        let index = (x >> CURVE_FRAC_BITS) as usize;
+        let frac = (x & ((1 << CURVE_FRAC_BITS) - 1)) as i64;
+        let (a, b) = (self.curve[index] as i64, self.curve[index + 1] as i64);
+        (a + round_shift((b - a) * frac, CURVE_FRAC_BITS)) as Q31
+    }
+}
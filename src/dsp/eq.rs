@@ -0,0 +1,182 @@
+use super::biquad::{BandConfig, Biquad, FilterKind};
+use super::fixed::saturate;
+use super::{AudioProcessor, CHANNELS};
+
+/// Number of bands in the parametric equalizer.
+pub const EQ_BANDS: usize = 3;
+
+/// Settings for every band of the equalizer.
+pub type EqSettings = [BandConfig; EQ_BANDS];
+
+/// Flat default: bass shelf, mid bell and treble shelf, all at 0 dB.
+pub const DEFAULT_EQ: EqSettings = [
+    BandConfig::new(FilterKind::LowShelf, 200.0, 0.707, 0.0),
+    BandConfig::new(FilterKind::Peaking, 1000.0, 1.0, 0.0),
+    BandConfig::new(FilterKind::HighShelf, 3000.0, 0.707, 0.0),
+];
+
+/// Multi-band parametric equalizer built from cascaded biquads.
+///
+/// Both channels share the same settings but keep separate filter history.
+#[derive(Debug, Clone)]
+pub struct Equalizer {
+    sample_rate: u32,
+    settings: EqSettings,
+    filters: [[Biquad; EQ_BANDS]; CHANNELS],
+}
+
+impl Equalizer {
+    pub fn new(sample_rate: u32) -> Self {
+        let mut eq = Self {
+            sample_rate,
+            settings: DEFAULT_EQ,
+            filters: [[Biquad::identity(); EQ_BANDS]; CHANNELS],
+        };
+        eq.redesign();
+        eq
+    }
+
+    pub fn settings(&self) -> &EqSettings {
+        &self.settings
+    }
+
+    /// Replaces the configuration of every band.
+    pub fn set_bands(&mut self, settings: EqSettings) {
+        self.settings = settings;
+        for (index, band) in settings.iter().enumerate() {
+            self.update_band(index, band);
+        }
+    }
+
+    /// Replaces the configuration of a single band. Out-of-range indices are ignored.
+    pub fn set_band(&mut self, index: usize, band: BandConfig) {
+        if let Some(slot) = self.settings.get_mut(index) {
+            *slot = band;
+            self.update_band(index, &band);
+        }
+    }
+
+    /// Redesigns one band on every channel, keeping the filter history.
+    fn update_band(&mut self, index: usize, band: &BandConfig) {
+        let design = Biquad::design(band, self.sample_rate);
+        for filters in self.filters.iter_mut() {
+            filters[index].update(&design);
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Recomputes all coefficients for a new sample rate.
+    ///
+    /// Filter history is cleared because the stored samples belong to the old
+    /// time base and would otherwise be fed through the new coefficients.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        if sample_rate != self.sample_rate {
+            self.sample_rate = sample_rate;
+            self.redesign();
+        }
+    }
+
+    fn redesign(&mut self) {
+        for (index, band) in self.settings.iter().enumerate() {
+            let design = Biquad::design(band, self.sample_rate);
+            for filters in self.filters.iter_mut() {
+                filters[index] = design;
+            }
+        }
+    }
+}
+
+impl AudioProcessor for Equalizer {
+    fn process(&mut self, samples: &mut [i16]) {
+        if self.settings.iter().all(BandConfig::is_flat) {
+            return;
+        }
+
+        for frame in samples.chunks_exact_mut(CHANNELS) {
+            for (sample, filters) in frame.iter_mut().zip(self.filters.iter_mut()) {
+                let mut value = *sample as i32;
+                for (filter, band) in filters.iter_mut().zip(&self.settings) {
+                    if !band.is_flat() {
+                        value = filter.run(value);
+                    }
+                }
+                // Clipping peaks stay at full scale instead of wrapping around.
+                *sample = saturate(value);
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        for filter in self.filters.iter_mut().flatten() {
+            filter.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 11025;
+
+    /// Interleaved stereo with a unit impulse on the left channel only.
+    fn impulse(frames: usize, amplitude: i16) -> Vec<i16> {
+        let mut samples = vec![0; frames * CHANNELS];
+        samples[0] = amplitude;
+        samples
+    }
+
+    #[test]
+    fn flat_settings_are_transparent() {
+        let mut eq = Equalizer::new(RATE);
+        let mut samples = [i16::MIN, i16::MAX, 1234, -4321, 0, 1, -1, 99];
+        let input = samples;
+        eq.process(&mut samples);
+        assert_eq!(samples, input);
+    }
+
+    #[test]
+    fn peaking_band_impulse_response() {
+        let mut eq = Equalizer::new(RATE);
+        eq.set_band(1, BandConfig::new(FilterKind::Peaking, 1000.0, 1.0, 6.0));
+
+        let mut samples = impulse(8, 8192);
+        eq.process(&mut samples);
+        let (left, right): (Vec<i16>, Vec<i16>) = samples
+            .chunks(CHANNELS)
+            .map(|frame| (frame[0], frame[1]))
+            .unzip();
+        assert_eq!(left, [9499, 1848, 418, -664, -1223, -1278, -976, -512]);
+        // Channels keep their own history
+        assert_eq!(right, [0; 8]);
+    }
+
+    #[test]
+    fn boost_clips_at_full_scale() {
+        let mut eq = Equalizer::new(RATE);
+        eq.set_band(0, BandConfig::new(FilterKind::LowShelf, 200.0, 0.707, 12.0));
+
+        let mut samples = [20_000; 512];
+        eq.process(&mut samples);
+        assert!(samples.iter().all(|&sample| sample > 0));
+        assert_eq!(samples[500..], [i16::MAX; 12]);
+    }
+
+    #[test]
+    fn reset_clears_the_history() {
+        let mut eq = Equalizer::new(RATE);
+        eq.set_band(
+            2,
+            BandConfig::new(FilterKind::HighShelf, 3000.0, 0.707, -6.0),
+        );
+
+        eq.process(&mut impulse(4, i16::MAX));
+        eq.reset();
+        let mut samples = [0; 8];
+        eq.process(&mut samples);
+        assert_eq!(samples, [0; 8]);
+    }
+}
@@ -0,0 +1,173 @@
+use super::fixed::{Q31, Q31_ONE, scale};
+use super::{AudioProcessor, CHANNELS};
+
+/// Transport fade applied at the end of the signal chain.
+///
+/// Starting, stopping or switching the stream abruptly leaves a step in the
+/// waveform that the DAC reproduces as a click. The player instead fades out,
+/// performs the transition on silence and fades back in. Ramps are linear in
+/// amplitude and last `duration_ms` from one end to the other.
+///
+/// Loading a track resets the pipeline but not the fade, which belongs to the
+/// transport rather than to the track.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fade {
+    sample_rate: u32,
+    duration_ms: u16,
+    level: Q31,
+    target: Q31,
+}
+
+impl Fade {
+    /// Creates a fade stage at full level, i.e. passing samples through.
+    pub const fn new(duration_ms: u16, sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            duration_ms,
+            level: Q31_ONE,
+            target: Q31_ONE,
+        }
+    }
+
+    pub fn duration_ms(&self) -> u16 {
+        self.duration_ms
+    }
+
+    /// Sets the ramp length; a ramp in progress continues at the new speed.
+    pub fn set_duration_ms(&mut self, duration_ms: u16) {
+        self.duration_ms = duration_ms;
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+    }
+
+    /// Ramps up to full level from wherever the fade currently is.
+    pub fn fade_in(&mut self) {
+        self.target = Q31_ONE;
+    }
+
+    /// Ramps down to silence from wherever the fade currently is.
+    pub fn fade_out(&mut self) {
+        self.target = 0;
+    }
+
+    /// Drops to silence immediately, e.g. when the stream has already ended.
+    pub fn mute(&mut self) {
+        self.level = 0;
+        self.target = 0;
+    }
+
+    /// Returns `true` once a fade-out has fully reached silence.
+    pub fn is_silent(&self) -> bool {
+        self.level == 0 && self.target == 0
+    }
+
+    /// Level change per frame.
+    fn step(&self) -> Q31 {
+        let frames = self.duration_ms as u64 * self.sample_rate as u64 / 1000;
+        // A zero-length fade jumps straight to the target.
+        (Q31_ONE as u64)
+            .checked_div(frames)
+            .map_or(Q31_ONE, |step| step.max(1) as Q31)
+    }
+}
+
+impl AudioProcessor for Fade {
+    fn process(&mut self, samples: &mut [i16]) {
+        if self.level == Q31_ONE && self.target == Q31_ONE {
+            return;
+        }
+
+        let step = self.step();
+        for frame in samples.chunks_exact_mut(CHANNELS) {
+            self.level = if self.level < self.target {
+                self.level.saturating_add(step).min(self.target)
+            } else {
+                self.level.saturating_sub(step).max(self.target)
+            };
+            for sample in frame.iter_mut() {
+                *sample = scale(*sample, self.level);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 11025;
+    /// Frames in a 10 ms ramp at [`RATE`].
+    const RAMP_FRAMES: usize = 110;
+
+    /// Runs `frames` stereo frames of a constant signal through `fade`,
+    /// returning the left channel.
+    fn run(fade: &mut Fade, frames: usize) -> Vec<i16> {
+        let mut samples = vec![20_000; frames * CHANNELS];
+        fade.process(&mut samples);
+        samples
+            .chunks_exact(CHANNELS)
+            .map(|frame| frame[0])
+            .collect()
+    }
+
+    #[test]
+    fn fade_out_falls_steadily_to_silence() {
+        let mut fade = Fade::new(10, RATE);
+        fade.fade_out();
+        let mut output = run(&mut fade, RAMP_FRAMES - 1);
+        assert!(fade.level > 0 && !fade.is_silent());
+        output.extend(run(&mut fade, RAMP_FRAMES));
+
+        assert!(
+            output.windows(2).all(|pair| pair[1] <= pair[0]),
+            "{output:?}"
+        );
+        // The level lands on zero, not just close to it
+        assert_eq!(fade.level, 0);
+        assert!(fade.is_silent());
+        assert!(output[RAMP_FRAMES + 1..].iter().all(|&sample| sample == 0));
+    }
+
+    #[test]
+    fn fade_in_rises_steadily_to_full_level() {
+        let mut fade = Fade::new(10, RATE);
+        fade.mute();
+        fade.fade_in();
+        let output = run(&mut fade, 2 * RAMP_FRAMES);
+
+        assert!(
+            output.windows(2).all(|pair| pair[1] >= pair[0]),
+            "{output:?}"
+        );
+        assert!(output[0] > 0 && output[0] < 500);
+        // Exactly unity at the end, and passed through from then on
+        let full_from = output.iter().position(|&sample| sample == 20_000).unwrap();
+        assert!(full_from <= RAMP_FRAMES + 1);
+        assert!(output[full_from..].iter().all(|&sample| sample == 20_000));
+        assert_eq!(fade, Fade::new(10, RATE));
+    }
+
+    #[test]
+    fn a_reversed_ramp_continues_from_where_it_was() {
+        let mut fade = Fade::new(10, RATE);
+        fade.fade_out();
+        let down = run(&mut fade, RAMP_FRAMES / 2);
+        fade.fade_in();
+        let up = run(&mut fade, RAMP_FRAMES);
+
+        let turn = *down.last().unwrap();
+        assert!(up[0] > turn && up[0] - turn < 500);
+        assert_eq!(*up.last().unwrap(), 20_000);
+    }
+
+    #[test]
+    fn a_zero_length_fade_jumps_to_the_target() {
+        let mut fade = Fade::new(0, RATE);
+        fade.fade_out();
+        assert_eq!(run(&mut fade, 3), [0, 0, 0]);
+        fade.fade_in();
+        assert_eq!(run(&mut fade, 3), [20_000; 3]);
+    }
+}
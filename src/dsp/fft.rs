@@ -0,0 +1,123 @@
+use core::f32::consts::PI;
+
+use super::fixed::{Q15, q15_from_f32, round_shift};
+
+/// Number of points of the transform.
+pub const FFT_SIZE: usize = 256;
+const FFT_LOG2: u32 = FFT_SIZE.trailing_zeros();
+
+/// Radix-2 decimation-in-time FFT in fixed point.
+///
+/// Twiddle factors are Q15 and every butterfly stage halves its output, so
+/// the result is the DFT scaled by `1 / FFT_SIZE` and can never overflow.
+#[derive(Debug, Clone)]
+pub struct Fft {
+    /// `(cos, -sin)` of `2πk/N` for the first half of the circle.
+    twiddles: [(Q15, Q15); FFT_SIZE / 2],
+}
+
+impl Fft {
+    pub fn new() -> Self {
+        let mut twiddles = [(0, 0); FFT_SIZE / 2];
+        for (k, twiddle) in twiddles.iter_mut().enumerate() {
+            let angle = 2.0 * PI * k as f32 / FFT_SIZE as f32;
+            *twiddle = (
+                q15_from_f32(libm::cosf(angle)),
+                q15_from_f32(-libm::sinf(angle)),
+            );
+        }
+        Self { twiddles }
+    }
+
+    /// Transforms `re`/`im` in place.
+    pub fn process(&self, re: &mut [i32; FFT_SIZE], im: &mut [i32; FFT_SIZE]) {
+        // Bit-reversal permutation.
+        for i in 0..FFT_SIZE {
+            let j = i.reverse_bits() >> (usize::BITS - FFT_LOG2);
+            if j > i {
+                re.swap(i, j);
+                im.swap(i, j);
+            }
+        }
+
+        let mut size = 2;
+        while size <= FFT_SIZE {
+            let half = size / 2;
+            let step = FFT_SIZE / size;
+            for k in 0..half {
+                let (w_re, w_im) = self.twiddles[k * step];
+                let (w_re, w_im) = (w_re as i64, w_im as i64);
+                for top in (k..FFT_SIZE).step_by(size) {
+                    let bottom = top + half;
+                    let (x_re, x_im) = (re[bottom] as i64, im[bottom] as i64);
+                    let t_re = round_shift(x_re * w_re - x_im * w_im, 15);
+                    let t_im = round_shift(x_re * w_im + x_im * w_re, 15);
+                    let (u_re, u_im) = (re[top] as i64, im[top] as i64);
+
+                    re[top] = round_shift(u_re + t_re, 1) as i32;
+                    im[top] = round_shift(u_im + t_im, 1) as i32;
+                    re[bottom] = round_shift(u_re - t_re, 1) as i32;
+                    im[bottom] = round_shift(u_im - t_im, 1) as i32;
+                }
+            }
+            size *= 2;
+        }
+    }
+}
+
+impl Default for Fft {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Magnitude of every bin up to Nyquist.
+    fn magnitudes(re: &[i32; FFT_SIZE], im: &[i32; FFT_SIZE]) -> Vec<f32> {
+        (0..=FFT_SIZE / 2)
+            .map(|k| libm::hypotf(re[k] as f32, im[k] as f32))
+            .collect()
+    }
+
+    #[test]
+    fn a_sine_lands_in_its_bin() {
+        let fft = Fft::new();
+        for bin in [1, 10, 64, 127] {
+            let mut re = [0; FFT_SIZE];
+            let mut im = [0; FFT_SIZE];
+            for (n, x) in re.iter_mut().enumerate() {
+                let phase = 2.0 * PI * (bin * n) as f32 / FFT_SIZE as f32;
+                *x = (16384.0 * libm::sinf(phase)) as i32;
+            }
+            fft.process(&mut re, &mut im);
+
+            // Half the amplitude in the bin and its mirror image, after the 1/N scaling
+            let bins = magnitudes(&re, &im);
+            assert!(
+                (bins[bin] - 8192.0).abs() < 16.0,
+                "bin {bin}: {}",
+                bins[bin]
+            );
+            for (k, &magnitude) in bins.iter().enumerate() {
+                if k != bin {
+                    assert!(
+                        magnitude < 8.0,
+                        "bin {k} of a sine in bin {bin}: {magnitude}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn silence_stays_silent() {
+        let mut re = [0; FFT_SIZE];
+        let mut im = [0; FFT_SIZE];
+        Fft::new().process(&mut re, &mut im);
+        assert_eq!(re, [0; FFT_SIZE]);
+        assert_eq!(im, [0; FFT_SIZE]);
+    }
+}
@@ -0,0 +1,145 @@
+//! Fixed-point sample arithmetic.
+//!
+//! All run-time sample math goes through these helpers so results are
+//! bit-exact on every platform: products are rounded half-up instead of
+//! truncated and every narrowing step saturates instead of wrapping around.
+//! Floating point is only used at design time (computing coefficients).
+
+/// Signed Q1.15 value: `i16::MAX` is just below 1.0, `i16::MIN` is -1.0.
+pub type Q15 = i16;
+/// Signed Q1.31 value: `i32::MAX` is just below 1.0, `i32::MIN` is -1.0.
+pub type Q31 = i32;
+
+/// Number of fractional bits of a [`Q15`].
+pub const Q15_FRAC_BITS: u32 = 15;
+/// Number of fractional bits of a [`Q31`].
+pub const Q31_FRAC_BITS: u32 = 31;
+
+/// Largest value representable as [`Q31`], used as "unity" gain.
+pub const Q31_ONE: Q31 = i32::MAX;
+
+/// Clamps a 32-bit intermediate into the `i16` sample range.
+pub const fn saturate(x: i32) -> i16 {
+    if x > i16::MAX as i32 {
+        i16::MAX
+    } else if x < i16::MIN as i32 {
+        i16::MIN
+    } else {
+        x as i16
+    }
+}
+
+/// Clamps a 64-bit accumulator into the `i32` range.
+pub const fn saturate_i32(x: i64) -> i32 {
+    if x > i32::MAX as i64 {
+        i32::MAX
+    } else if x < i32::MIN as i64 {
+        i32::MIN
+    } else {
+        x as i32
+    }
+}
+
+/// Arithmetic right shift with round-half-up.
+pub const fn round_shift(x: i64, shift: u32) -> i64 {
+    if shift == 0 {
+        x
+    } else {
+        (x + (1 << (shift - 1))) >> shift
+    }
+}
+
+/// Saturating addition of two samples.
+pub const fn add_sat(a: i16, b: i16) -> i16 {
+    a.saturating_add(b)
+}
+
+/// Saturating subtraction of two samples.
+pub const fn sub_sat(a: i16, b: i16) -> i16 {
+    a.saturating_sub(b)
+}
+
+/// Rounded, saturating Q15 multiply.
+pub const fn mul_q15(a: Q15, b: Q15) -> Q15 {
+    saturate(round_shift(a as i64 * b as i64, Q15_FRAC_BITS) as i32)
+}
+
+/// Rounded, saturating Q31 multiply.
+pub const fn mul_q31(a: Q31, b: Q31) -> Q31 {
+    saturate_i32(round_shift(a as i64 * b as i64, Q31_FRAC_BITS))
+}
+
+/// Scales a sample by a Q31 factor, rounding and saturating the result.
+pub const fn scale(sample: i16, gain: Q31) -> i16 {
+    saturate(round_shift(sample as i64 * gain as i64, Q31_FRAC_BITS) as i32)
+}
+
+/// Converts a float in `[-1.0, 1.0]` to Q15, saturating out-of-range values.
+pub fn q15_from_f32(x: f32) -> Q15 {
+    // Float to int casts saturate and map NaN to zero.
+    saturate(libm::roundf(x * (1u32 << Q15_FRAC_BITS) as f32) as i32)
+}
+
+/// Converts a float in `[-1.0, 1.0]` to Q31, saturating out-of-range values.
+pub fn q31_from_f32(x: f32) -> Q31 {
+    libm::round(x as f64 * (1u64 << Q31_FRAC_BITS) as f64) as i32
+}
+
+/// Converts a float to a signed fixed-point value with `frac_bits` fractional bits.
+pub fn fixed_from_f32(x: f32, frac_bits: u32) -> i32 {
+    libm::round(x as f64 * (1u64 << frac_bits) as f64) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saturate_clamps_instead_of_wrapping() {
+        assert_eq!(saturate(40_000), i16::MAX);
+        assert_eq!(saturate(-40_000), i16::MIN);
+        assert_eq!(saturate(-1234), -1234);
+        assert_eq!(saturate_i32(i64::MAX), i32::MAX);
+        assert_eq!(saturate_i32(i64::MIN), i32::MIN);
+    }
+
+    #[test]
+    fn round_shift_rounds_half_up() {
+        let shifted = [-7, -6, -5, -3, -2, 2, 3, 5, 6, 7].map(|x| round_shift(x, 2));
+        assert_eq!(shifted, [-2, -1, -1, -1, 0, 1, 1, 1, 2, 2]);
+        assert_eq!(round_shift(-3, 1), -1);
+        assert_eq!(round_shift(3, 1), 2);
+        assert_eq!(round_shift(-3, 0), -3);
+    }
+
+    #[test]
+    fn products_round_and_saturate() {
+        assert_eq!(mul_q15(16384, 16384), 8192);
+        assert_eq!(mul_q15(3, 16384), 2);
+        assert_eq!(mul_q15(-3, 16384), -1);
+        assert_eq!(mul_q15(i16::MIN, i16::MIN), i16::MAX);
+        assert_eq!(mul_q31(i32::MIN, i32::MIN), i32::MAX);
+        assert_eq!(mul_q31(1 << 30, 1 << 30), 1 << 29);
+    }
+
+    #[test]
+    fn scale_keeps_unity_exact() {
+        for sample in [i16::MIN, -1000, -1, 0, 1, 1000, i16::MAX] {
+            assert_eq!(scale(sample, Q31_ONE), sample);
+        }
+        assert_eq!(scale(3, 1 << 30), 2);
+        assert_eq!(scale(-3, 1 << 30), -1);
+        assert_eq!(scale(i16::MIN, i32::MIN), i16::MAX);
+    }
+
+    #[test]
+    fn float_conversions_saturate() {
+        assert_eq!(q15_from_f32(0.5), 16384);
+        assert_eq!(q15_from_f32(1.0), i16::MAX);
+        assert_eq!(q15_from_f32(-1.0), i16::MIN);
+        assert_eq!(q15_from_f32(f32::NAN), 0);
+        assert_eq!(q31_from_f32(1.0), Q31_ONE);
+        assert_eq!(q31_from_f32(-2.0), i32::MIN);
+        assert_eq!(fixed_from_f32(-1.5, 4), -24);
+    }
+}
@@ -0,0 +1,129 @@
+use super::fixed::{Q31, q31_from_f32, scale};
+use super::{AudioProcessor, CHANNELS};
+
+/// Attenuation applied at the lowest non-zero volume step, in dB.
+pub const MIN_VOLUME_DB: f32 = -60.0;
+
+/// Converts a volume level (0-100%) into a linear Q31 gain factor.
+///
+/// Levels are mapped linearly onto [`MIN_VOLUME_DB`]..0 dB so each encoder
+/// step is perceived as roughly the same loudness change; 0% is a true mute.
+pub fn volume_to_gain(volume: u8) -> Q31 {
+    match volume.min(100) {
+        0 => 0,
+        v => q31_from_f32(db_to_gain(MIN_VOLUME_DB * (1.0 - v as f32 / 100.0))),
+    }
+}
+
+/// Converts a level in dB into a linear amplitude factor.
+pub fn db_to_gain(db: f32) -> f32 {
+    libm::powf(10.0, db / 20.0)
+}
+
+/// Software volume control applied to 16-bit PCM samples.
+///
+/// Changes are not applied abruptly: the gain is interpolated sample by
+/// sample across the next processed block, which avoids zipper noise while
+/// the encoder is being turned.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gain {
+    volume: u8,
+    target: Q31,
+    current: Q31,
+}
+
+impl Gain {
+    /// Creates a gain stage that starts directly at `volume`, without a ramp.
+    pub fn new(volume: u8) -> Self {
+        let volume = volume.min(100);
+        let gain = volume_to_gain(volume);
+        Self {
+            volume,
+            target: gain,
+            current: gain,
+        }
+    }
+
+    /// Sets the volume level (0-100%). The new gain is reached by the end of
+    /// the next processed block.
+    pub fn set_volume(&mut self, volume: u8) {
+        let volume = volume.min(100);
+        if volume != self.volume {
+            self.volume = volume;
+            self.target = volume_to_gain(volume);
+        }
+    }
+
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+}
+
+impl Default for Gain {
+    fn default() -> Self {
+        Self::new(100)
+    }
+}
+
+impl AudioProcessor for Gain {
+    fn process(&mut self, samples: &mut [i16]) {
+        if samples.is_empty() {
+            return;
+        }
+
+        let delta = self.target as i64 - self.current as i64;
+        let frames = samples.len().div_ceil(CHANNELS) as i64;
+        let start = self.current as i64;
+        for (i, frame) in samples.chunks_mut(CHANNELS).enumerate() {
+            // Computed from the start point rather than accumulated, so the
+            // ramp lands exactly on the target at the last frame. Both
+            // channels of a frame share the same gain.
+            let gain = (start + delta * (i as i64 + 1) / frames) as Q31;
+            for sample in frame.iter_mut() {
+                *sample = scale(*sample, gain);
+            }
+        }
+        self.current = self.target;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsp::fixed::Q31_ONE;
+
+    #[test]
+    fn volume_maps_onto_the_db_range() {
+        assert_eq!(volume_to_gain(0), 0);
+        assert_eq!(volume_to_gain(100), Q31_ONE);
+        assert_eq!(volume_to_gain(200), Q31_ONE);
+        // -30 dB
+        assert_eq!(volume_to_gain(50), 67_909_392);
+    }
+
+    #[test]
+    fn full_volume_is_transparent() {
+        let mut samples = [i16::MIN, -12345, -1, 0, 1, 12345, i16::MAX, 7];
+        let input = samples;
+        Gain::new(100).process(&mut samples);
+        assert_eq!(samples, input);
+    }
+
+    #[test]
+    fn volume_changes_ramp_over_one_block() {
+        let mut gain = Gain::new(100);
+        gain.set_volume(40);
+
+        let mut samples = [20_000, -20_000].repeat(4);
+        gain.process(&mut samples);
+        // -36 dB (317/20000) reached on the last frame
+        assert_eq!(
+            samples,
+            [15079, -15079, 10158, -10158, 5238, -5238, 317, -317]
+        );
+
+        let mut samples = [20_000, -20_000].repeat(2);
+        gain.process(&mut samples);
+        assert_eq!(samples, [317, -317, 317, -317]);
+    }
+}
@@ -0,0 +1,355 @@
+//! Hardware-independent audio processing core.
+//!
+//! Nothing in this module touches `esp-hal`: samples come in as plain `i16`
+//! slices and leave the same way, so the whole signal path can be driven from
+//! `audio_task` on the target or from a test harness on a host machine.
+
+pub mod adpcm;
+mod balance;
+mod biquad;
+mod crossfade;
+mod eq;
+mod fade;
+mod fft;
+pub mod fixed;
+mod gain;
+mod resample;
+mod spectrum;
+mod stream;
+pub mod wav;
+
+pub use balance::Balance;
+pub use biquad::{BandConfig, Biquad, FilterKind, MAX_GAIN_DB};
+pub use crossfade::Crossfade;
+pub use eq::{DEFAULT_EQ, EQ_BANDS, EqSettings, Equalizer};
+pub use fade::Fade;
+pub use fft::{FFT_SIZE, Fft};
+pub use gain::{Gain, MIN_VOLUME_DB, db_to_gain, volume_to_gain};
+pub use resample::{MAX_TAPS, ResampleQuality, Resampler};
+pub use spectrum::{SPECTRUM_BANDS, SpectrumAnalyzer, SpectrumBands, SpectrumSnapshot};
+pub use stream::PcmStream;
+pub use wav::{AudioFormat, Encoding, WavError};
+
+/// Number of output channels. Buffers hold interleaved left/right samples.
+pub const CHANNELS: usize = 2;
+/// Size in bytes of one output frame (one 16-bit sample per channel).
+pub const FRAME_BYTES: usize = CHANNELS * 2;
+/// Size in bytes of each block pushed to the I2S DMA buffer.
+pub const CHUNK_BYTES: usize = 512;
+/// Number of 16-bit samples contained in one [`CHUNK_BYTES`] block.
+pub const CHUNK_SAMPLES: usize = CHUNK_BYTES / 2;
+
+/// A single stage of the signal chain.
+///
+/// Stages operate in place on interleaved stereo: the buffer holds the input
+/// samples on entry and the processed samples on return.
+pub trait AudioProcessor {
+    /// Processes a block of samples.
+    fn process(&mut self, samples: &mut [i16]);
+
+    /// Clears any internal state, e.g. when a new track is loaded.
+    fn reset(&mut self) {}
+}
+
+/// Where the signal chain pulls its samples from: a track in flash
+/// ([`PcmStream`]) or audio arriving while it plays.
+pub trait SampleSource {
+    /// Layout of the source's samples, for the resampler to convert from the right rate.
+    fn format(&self) -> AudioFormat;
+
+    /// Decodes up to `out.len() / CHANNELS` frames into `out` as interleaved
+    /// stereo, returning how many samples were written. Returns `0` when no
+    /// samples are available.
+    fn read(&mut self, out: &mut [i16]) -> usize;
+}
+
+/// The complete signal chain applied between the track data and the DAC.
+pub struct Pipeline {
+    /// Output sample rate, in Hz.
+    sample_rate: u32,
+    /// Converts each track from its own rate to the output rate.
+    pub resampler: Resampler,
+    /// Parametric equalizer, applied before the volume so its headroom is preserved.
+    pub eq: Equalizer,
+    /// Software volume stage.
+    pub gain: Gain,
+    /// Left/right balance.
+    pub balance: Balance,
+    /// Click-free ramps around play, pause and track changes.
+    pub fade: Fade,
+    /// Taps the final output for the spectrum view; does not modify samples.
+    pub analyzer: SpectrumAnalyzer,
+    /// Overlaps the end of a track with the start of the next one.
+    pub crossfade: Crossfade,
+}
+
+impl Pipeline {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            resampler: Resampler::new(ResampleQuality::Medium, sample_rate, sample_rate),
+            eq: Equalizer::new(sample_rate),
+            gain: Gain::default(),
+            balance: Balance::new(),
+            fade: Fade::new(0, sample_rate),
+            analyzer: SpectrumAnalyzer::new(),
+            crossfade: Crossfade::new(0, sample_rate),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Propagates an output sample rate change to every rate-dependent stage.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        let input_rate = self.resampler.input_rate();
+        self.resampler.set_rates(input_rate, sample_rate);
+        self.eq.set_sample_rate(sample_rate);
+        self.crossfade.set_sample_rate(sample_rate);
+        self.fade.set_sample_rate(sample_rate);
+    }
+
+    /// Prepares the chain for a newly loaded stream: matches the resampler to
+    /// the track's rate and clears every stage's history.
+    pub fn start_track<S: SampleSource>(&mut self, stream: &S) {
+        self.resampler
+            .set_rates(stream.format().sample_rate, self.sample_rate);
+        self.reset();
+    }
+
+    /// Switches to the next track without clearing the processing stages, so
+    /// back-to-back tracks play without a gap or a filter transient.
+    pub fn continue_track(&mut self, stream: &PcmStream) {
+        self.crossfade.reset();
+        self.resampler
+            .set_rates(stream.format().sample_rate, self.sample_rate);
+        self.resampler.reset();
+    }
+
+    /// Output frames left in `stream`, measured at the output rate.
+    pub fn remaining_frames(&self, stream: &PcmStream) -> usize {
+        let rate = stream.format().sample_rate;
+        if rate == 0 {
+            return 0;
+        }
+        let frames = stream.total_frames() - stream.frame_position();
+        (frames as u64 * self.sample_rate as u64 / rate as u64) as usize
+    }
+
+    /// Returns `true` once `stream` is close enough to its end for the
+    /// crossfade to start. Always `false` when the crossfade is disabled.
+    pub fn crossfade_due(&self, stream: &PcmStream) -> bool {
+        let frames = self.crossfade.frames();
+        frames > 0 && !self.crossfade.is_active() && self.remaining_frames(stream) <= frames
+    }
+
+    /// Starts mixing `incoming` over the rest of `outgoing`.
+    pub fn begin_crossfade(&mut self, outgoing: &PcmStream, incoming: &PcmStream) {
+        let length = self.crossfade.frames().min(self.remaining_frames(outgoing));
+        self.crossfade
+            .begin(incoming, length, self.resampler.quality());
+    }
+
+    /// Returns `true` once `stream` and every buffered sample have been played.
+    pub fn is_finished(&self, stream: &PcmStream) -> bool {
+        stream.is_finished() && self.resampler.is_drained()
+    }
+
+    /// Pulls the next block from `stream`, runs it through every stage and
+    /// writes little-endian PCM into `out`.
+    ///
+    /// Returns the number of bytes written, which is `0` once the stream is exhausted.
+    pub fn fill<S: SampleSource>(&mut self, stream: &mut S, out: &mut [u8]) -> usize {
+        let mut samples = [0i16; CHUNK_SAMPLES];
+        let max = (out.len() / FRAME_BYTES * CHANNELS).min(CHUNK_SAMPLES);
+        let count = self.resampler.read(stream, &mut samples[..max]);
+        self.output(&mut samples[..count], out)
+    }
+
+    /// Like [`fill`](Self::fill), but mixes the tail of `outgoing` with the
+    /// head of `incoming` along the crossfade curve.
+    ///
+    /// Once the transition completes `incoming` becomes the current track:
+    /// [`Crossfade::is_active`] turns `false` and the caller should replace
+    /// `outgoing` with it.
+    pub fn fill_crossfade(
+        &mut self,
+        outgoing: &mut PcmStream,
+        incoming: &mut PcmStream,
+        out: &mut [u8],
+    ) -> usize {
+        let mut samples = [0i16; CHUNK_SAMPLES];
+        let mut head = [0i16; CHUNK_SAMPLES];
+        let max = (out.len() / FRAME_BYTES * CHANNELS).min(CHUNK_SAMPLES);
+        // A track that runs out early is padded with the silence already in the buffer.
+        let tail = self.resampler.read(outgoing, &mut samples[..max]);
+        let count = tail.max(self.crossfade.read(incoming, &mut head[..max]));
+
+        self.crossfade.mix(&mut samples[..count], &head[..count]);
+        if self.crossfade.is_complete() || self.is_finished(outgoing) {
+            self.crossfade.finish(&mut self.resampler);
+        }
+        self.output(&mut samples[..count], out)
+    }
+
+    /// Runs `samples` through every stage and writes them as little-endian PCM.
+    fn output(&mut self, samples: &mut [i16], out: &mut [u8]) -> usize {
+        self.process(samples);
+        self.analyzer.push(samples);
+
+        for (bytes, sample) in out.chunks_exact_mut(2).zip(samples.iter()) {
+            bytes.copy_from_slice(&sample.to_le_bytes());
+        }
+        samples.len() * 2
+    }
+}
+
+impl AudioProcessor for Pipeline {
+    fn process(&mut self, samples: &mut [i16]) {
+        self.eq.process(samples);
+        self.gain.process(samples);
+        self.balance.process(samples);
+        self.fade.process(samples);
+    }
+
+    fn reset(&mut self) {
+        self.resampler.reset();
+        self.eq.reset();
+        self.gain.reset();
+        self.balance.reset();
+        self.analyzer.reset();
+        self.crossfade.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A stream over `samples` as 16-bit PCM, leaked to get the `'static`
+    /// lifetime of an asset in flash.
+    pub fn pcm_stream(samples: &[i16], sample_rate: u32, channels: u16) -> PcmStream {
+        let bytes: Vec<u8> = samples
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        PcmStream::new(bytes.leak(), AudioFormat::pcm(sample_rate, channels, 16))
+    }
+
+    /// `samples` compressed to IMA ADPCM in a WAV file, as the build script
+    /// embeds tracks.
+    fn adpcm_stream(samples: &[i16], sample_rate: u32, channels: u16) -> PcmStream {
+        let format = AudioFormat::pcm(sample_rate, channels, 16);
+        let (format, data) = adpcm::encode_pcm_to_adpcm(format, samples);
+        let frames = (samples.len() / channels as usize) as u32;
+        let file = wav::tests::wav_file(format, frames, &data);
+        PcmStream::from_asset(file.leak()).unwrap()
+    }
+
+    /// Runs `stream` through `pipeline` until it ends, returning the size of
+    /// every chunk and the samples.
+    fn drain(pipeline: &mut Pipeline, stream: &mut PcmStream) -> (Vec<usize>, Vec<i16>) {
+        let mut sizes = Vec::new();
+        let mut samples = Vec::new();
+        loop {
+            let mut chunk = [0u8; CHUNK_BYTES];
+            let len = pipeline.fill(stream, &mut chunk);
+            if len == 0 {
+                return (sizes, samples);
+            }
+            sizes.push(len);
+            samples.extend(
+                chunk[..len]
+                    .chunks_exact(2)
+                    .map(|bytes| i16::from_le_bytes([bytes[0], bytes[1]])),
+            );
+        }
+    }
+
+    /// Interleaved stereo with a distinct value in every sample.
+    fn signal(frames: usize) -> Vec<i16> {
+        (0..frames as i32 * 2)
+            .map(|i| ((i * 7919) % 60_001 - 30_000) as i16)
+            .collect()
+    }
+
+    #[test]
+    fn default_pipeline_is_bit_exact() {
+        let input = signal(300);
+        let mut stream = pcm_stream(&input, 11025, 2);
+        let mut pipeline = Pipeline::new(11025);
+        pipeline.start_track(&stream);
+
+        let (sizes, output) = drain(&mut pipeline, &mut stream);
+        assert_eq!(sizes, [CHUNK_BYTES, CHUNK_BYTES, 176]);
+        assert_eq!(output, input);
+        assert!(pipeline.is_finished(&stream));
+    }
+
+    #[test]
+    fn last_chunk_holds_whole_frames_only() {
+        // Five frames and a stray byte
+        let bytes: &'static [u8] = vec![1u8; 5 * 4 + 1].leak();
+        let mut stream = PcmStream::new(bytes, AudioFormat::pcm(11025, 2, 16));
+        let mut pipeline = Pipeline::new(11025);
+        pipeline.start_track(&stream);
+
+        let mut chunk = [0u8; 12];
+        assert_eq!(pipeline.fill(&mut stream, &mut chunk), 12);
+        assert_eq!(pipeline.fill(&mut stream, &mut chunk), 8);
+        assert_eq!(pipeline.fill(&mut stream, &mut chunk), 0);
+        assert!(stream.is_finished());
+    }
+
+    #[test]
+    fn processed_pipeline_golden_output() {
+        let mut stream = pcm_stream(&signal(24), 22050, 2);
+        let mut pipeline = Pipeline::new(11025);
+        pipeline.start_track(&stream);
+        pipeline
+            .eq
+            .set_band(1, BandConfig::new(FilterKind::Peaking, 1000.0, 1.0, 6.0));
+        pipeline.gain = Gain::new(80);
+        pipeline.balance.set_balance(50);
+
+        // Resampled, equalized, at 80% volume and panned to the right
+        let (_, output) = drain(&mut pipeline, &mut stream);
+        #[rustfmt::skip]
+        assert_eq!(output, [
+            0, 0, 2, 3, -43, -62, 267, 408, -1391, -2184, -2110, -1740, -326, 2154,
+            -1279, 225, 601, 4047, -346, 1091, 1381, -3125, 215, -383, 1897, -2835,
+        ]);
+    }
+
+    #[test]
+    fn padded_tracks_play_back_to_back_without_silence() {
+        // Neither track fills its last block, which the encoder pads with silence
+        let first = signal(700);
+        let second = signal(333);
+        let mut stream = adpcm_stream(&first, 11025, 2);
+        let mut pipeline = Pipeline::new(11025);
+        pipeline.start_track(&stream);
+
+        let (_, mut output) = drain(&mut pipeline, &mut stream);
+        assert!(pipeline.is_finished(&stream));
+        stream = adpcm_stream(&second, 11025, 2);
+        pipeline.continue_track(&stream);
+        output.extend(drain(&mut pipeline, &mut stream).1);
+
+        assert_eq!(output.len(), first.len() + second.len());
+        let join = first.len();
+        assert!(output[join - 8..join + 8].iter().all(|&sample| sample != 0));
+    }
+
+    #[test]
+    fn mono_tracks_play_on_both_channels() {
+        let mut stream = pcm_stream(&[100, -200, 300], 11025, 1);
+        let mut pipeline = Pipeline::new(11025);
+        pipeline.start_track(&stream);
+
+        let (_, output) = drain(&mut pipeline, &mut stream);
+        assert_eq!(output, [100, 100, -200, -200, 300, 300]);
+    }
+}
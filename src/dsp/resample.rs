@@ -0,0 +1,335 @@
+use core::f32::consts::PI;
+
+use super::fixed::{fixed_from_f32, round_shift, saturate};
+use super::{CHANNELS, SampleSource};
+
+/// Longest filter supported, in input samples.
+pub const MAX_TAPS: usize = 32;
+/// Number of fractional positions with a precomputed filter.
+const PHASES: usize = 32;
+const PHASE_BITS: u32 = PHASES.trailing_zeros();
+/// Fractional bits of the read position, in input samples.
+const FRAC_BITS: u32 = 16;
+const FRAC_ONE: u32 = 1 << FRAC_BITS;
+/// Fractional bits of the filter coefficients.
+const COEFF_FRAC_BITS: u32 = 15;
+/// Cutoff relative to the lower Nyquist frequency, leaving room for the transition band.
+const CUTOFF: f32 = 0.92;
+/// Frames pulled from the source per refill.
+const INPUT_BLOCK: usize = 64;
+
+/// Trade-off between CPU time and fidelity of the sample-rate conversion.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResampleQuality {
+    /// Linear interpolation between two samples. Cheapest, audible aliasing.
+    Linear,
+    /// 8-tap windowed sinc.
+    Low,
+    /// 16-tap windowed sinc.
+    Medium,
+    /// 32-tap windowed sinc.
+    High,
+}
+
+impl ResampleQuality {
+    /// Filter length, in input samples.
+    pub const fn taps(self) -> usize {
+        match self {
+            ResampleQuality::Linear => 2,
+            ResampleQuality::Low => 8,
+            ResampleQuality::Medium => 16,
+            ResampleQuality::High => MAX_TAPS,
+        }
+    }
+}
+
+/// Polyphase windowed-sinc sample-rate converter.
+///
+/// Pulls samples at the track's rate from a [`SampleSource`] and produces them at
+/// the output rate, so tracks recorded at any rate play at the correct pitch.
+/// When both rates match the stream is read directly.
+#[derive(Debug, Clone)]
+pub struct Resampler {
+    quality: ResampleQuality,
+    input_rate: u32,
+    output_rate: u32,
+    /// Input samples advanced per output sample, in Q16.
+    step: u32,
+    /// Read position between the two centre taps, in Q16.
+    frac: u32,
+    /// Blackman-windowed sinc kernels, one per fractional phase (Q15).
+    coeffs: [[i32; MAX_TAPS]; PHASES],
+    /// Last `taps` input samples of each channel, stored twice so the window
+    /// is always contiguous.
+    history: [[i16; 2 * MAX_TAPS]; CHANNELS],
+    head: usize,
+    /// Interleaved frames pulled from the source but not consumed yet.
+    input: [i16; INPUT_BLOCK * CHANNELS],
+    input_pos: usize,
+    input_len: usize,
+}
+
+impl Resampler {
+    pub fn new(quality: ResampleQuality, input_rate: u32, output_rate: u32) -> Self {
+        let mut resampler = Self {
+            quality,
+            input_rate,
+            output_rate,
+            step: FRAC_ONE,
+            frac: 0,
+            coeffs: [[0; MAX_TAPS]; PHASES],
+            history: [[0; 2 * MAX_TAPS]; CHANNELS],
+            head: 0,
+            input: [0; INPUT_BLOCK * CHANNELS],
+            input_pos: 0,
+            input_len: 0,
+        };
+        resampler.redesign();
+        resampler
+    }
+
+    pub fn quality(&self) -> ResampleQuality {
+        self.quality
+    }
+
+    pub fn set_quality(&mut self, quality: ResampleQuality) {
+        if quality != self.quality {
+            self.quality = quality;
+            self.redesign();
+        }
+    }
+
+    pub fn input_rate(&self) -> u32 {
+        self.input_rate
+    }
+
+    /// Configures the conversion ratio, e.g. when a new track is loaded.
+    pub fn set_rates(&mut self, input_rate: u32, output_rate: u32) {
+        if input_rate != self.input_rate || output_rate != self.output_rate {
+            self.input_rate = input_rate;
+            self.output_rate = output_rate;
+            self.redesign();
+        }
+    }
+
+    /// Returns `true` when input and output rates match and samples pass through untouched.
+    pub fn is_bypassed(&self) -> bool {
+        self.input_rate == self.output_rate || self.input_rate == 0 || self.output_rate == 0
+    }
+
+    /// Returns `true` once no buffered input is left.
+    pub fn is_drained(&self) -> bool {
+        self.input_pos >= self.input_len
+    }
+
+    /// Produces interleaved frames at the output rate into `out`, returning
+    /// how many samples were written.
+    pub fn read<S: SampleSource>(&mut self, stream: &mut S, out: &mut [i16]) -> usize {
+        if self.is_bypassed() {
+            return stream.read(out);
+        }
+
+        let mut count = 0;
+        for frame in out.chunks_exact_mut(CHANNELS) {
+            while self.frac >= FRAC_ONE {
+                let Some(input) = self.next_input(stream) else {
+                    return count;
+                };
+                self.push(input);
+                self.frac -= FRAC_ONE;
+            }
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                *sample = self.interpolate(channel);
+            }
+            count += CHANNELS;
+            self.frac += self.step;
+        }
+        count
+    }
+
+    /// Clears the filter history and any buffered input.
+    pub fn reset(&mut self) {
+        self.frac = 0;
+        self.history = [[0; 2 * MAX_TAPS]; CHANNELS];
+        self.head = 0;
+        self.input_pos = 0;
+        self.input_len = 0;
+    }
+
+    fn next_input<S: SampleSource>(&mut self, stream: &mut S) -> Option<[i16; CHANNELS]> {
+        if self.input_pos >= self.input_len {
+            self.input_len = stream.read(&mut self.input);
+            self.input_pos = 0;
+            if self.input_len == 0 {
+                return None;
+            }
+        }
+        let mut frame = [0; CHANNELS];
+        frame.copy_from_slice(&self.input[self.input_pos..self.input_pos + CHANNELS]);
+        self.input_pos += CHANNELS;
+        Some(frame)
+    }
+
+    fn push(&mut self, frame: [i16; CHANNELS]) {
+        let taps = self.quality.taps();
+        for (history, sample) in self.history.iter_mut().zip(frame) {
+            history[self.head] = sample;
+            history[self.head + taps] = sample;
+        }
+        self.head = (self.head + 1) % taps;
+    }
+
+    fn interpolate(&self, channel: usize) -> i16 {
+        let taps = self.quality.taps();
+        let window = &self.history[channel][self.head..self.head + taps];
+
+        if self.quality == ResampleQuality::Linear {
+            let (a, b) = (window[0] as i64, window[1] as i64);
+            return saturate((a + round_shift((b - a) * self.frac as i64, FRAC_BITS)) as i32);
+        }
+
+        let phase = (self.frac >> (FRAC_BITS - PHASE_BITS)) as usize;
+        let acc: i64 = window
+            .iter()
+            .zip(&self.coeffs[phase])
+            .map(|(&x, &c)| x as i64 * c as i64)
+            .sum();
+        saturate(round_shift(acc, COEFF_FRAC_BITS) as i32)
+    }
+
+    /// Recomputes the step and the kernels, then clears the state.
+    fn redesign(&mut self) {
+        self.reset();
+        if self.is_bypassed() {
+            return;
+        }
+
+        self.step = (((self.input_rate as u64) << FRAC_BITS) / self.output_rate as u64) as u32;
+
+        if self.quality == ResampleQuality::Linear {
+            return;
+        }
+
+        // When decimating, the cutoff drops to the output Nyquist frequency.
+        let ratio = (self.output_rate as f32 / self.input_rate as f32).min(1.0);
+        let cutoff = CUTOFF * ratio;
+        let taps = self.quality.taps();
+        let half = (taps / 2) as f32;
+
+        for (phase, kernel) in self.coeffs.iter_mut().enumerate() {
+            let frac = phase as f32 / PHASES as f32;
+            let mut weights = [0f32; MAX_TAPS];
+            let mut sum = 0.0;
+            for (k, weight) in weights.iter_mut().enumerate().take(taps) {
+                // Distance from the interpolated point, which sits `frac`
+                // after the last of the older half of the window.
+                let d = k as f32 - (half - 1.0) - frac;
+                *weight = cutoff * sinc(cutoff * d) * blackman(d / half);
+                sum += *weight;
+            }
+
+            // Normalise each phase to unity DC gain.
+            kernel.fill(0);
+            for (coeff, weight) in kernel.iter_mut().zip(&weights[..taps]) {
+                *coeff = fixed_from_f32(weight / sum, COEFF_FRAC_BITS);
+            }
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 {
+        1.0
+    } else {
+        libm::sinf(PI * x) / (PI * x)
+    }
+}
+
+/// Blackman window over `x` in `[-1, 1]`.
+fn blackman(x: f32) -> f32 {
+    if x.abs() >= 1.0 {
+        0.0
+    } else {
+        0.42 + 0.5 * libm::cosf(PI * x) + 0.08 * libm::cosf(2.0 * PI * x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsp::tests::pcm_stream;
+
+    /// Reads everything `resampler` produces from `samples` (interleaved stereo).
+    fn resample(resampler: &mut Resampler, samples: &[i16], input_rate: u32) -> Vec<i16> {
+        let mut stream = pcm_stream(samples, input_rate, 2);
+        let mut output = Vec::new();
+        let mut block = [0; 14];
+        loop {
+            match resampler.read(&mut stream, &mut block) {
+                0 => return output,
+                count => output.extend_from_slice(&block[..count]),
+            }
+        }
+    }
+
+    /// Interleaved stereo ramp, with the right channel inverted.
+    fn ramp(frames: i16, step: i16) -> Vec<i16> {
+        (0..frames).flat_map(|i| [i * step, -i * step]).collect()
+    }
+
+    #[test]
+    fn matching_rates_pass_samples_through() {
+        let mut resampler = Resampler::new(ResampleQuality::High, 11025, 11025);
+        assert!(resampler.is_bypassed());
+        let input = ramp(100, 300);
+        assert_eq!(resample(&mut resampler, &input, 11025), input);
+    }
+
+    #[test]
+    fn linear_upsampling_interpolates_between_samples() {
+        let mut resampler = Resampler::new(ResampleQuality::Linear, 11025, 22050);
+        let output = resample(&mut resampler, &ramp(4, 1001), 11025);
+        // Two frames in, one out at half way, after the history fills; halves round up
+        #[rustfmt::skip]
+        assert_eq!(output, [
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            501, -500, 1001, -1001, 1502, -1501, 2002, -2002, 2503, -2502,
+        ]);
+    }
+
+    #[test]
+    fn sinc_downsampling_golden_output() {
+        let mut resampler = Resampler::new(ResampleQuality::Medium, 22050, 11025);
+        let input: Vec<i16> = (0..40)
+            .flat_map(|i: i16| [if i % 8 < 4 { 12000 } else { -12000 }, 5000])
+            .collect();
+        let output = resample(&mut resampler, &input, 22050);
+        // The square wave overshoots at its edges; the constant right channel settles exactly
+        #[rustfmt::skip]
+        assert_eq!(output, [
+            0, 0, -5, -2, 130, 54, -673, -285, 2980, 1350, 13671, 5131, 5806, 5011,
+            -14010, 4990, -5832, 5000, 14034, 5000, 5832, 5000, -14034, 5000, -5832, 5000,
+            14034, 5000, 5832, 5000, -14034, 5000, -5832, 5000, 14034, 5000, 5832, 5000,
+            -14034, 5000, -5832, 5000,
+        ]);
+        assert!(resampler.is_drained());
+    }
+
+    #[test]
+    fn sinc_filters_keep_unity_dc_gain() {
+        for quality in [
+            ResampleQuality::Low,
+            ResampleQuality::Medium,
+            ResampleQuality::High,
+        ] {
+            let mut resampler = Resampler::new(quality, 44100, 11025);
+            let output = resample(&mut resampler, &[-20_000, 20_000].repeat(400), 44100);
+            assert_eq!(output.len(), 202);
+            // Once the history is full, every phase sums to one
+            for frame in output[2 * MAX_TAPS..].chunks(CHANNELS) {
+                assert!((frame[0] + 20_000).abs() <= 1, "{quality:?}: {frame:?}");
+                assert!((frame[1] - 20_000).abs() <= 1, "{quality:?}: {frame:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use core::f32::consts::PI;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use super::CHANNELS;
+use super::fft::{FFT_SIZE, Fft};
+use super::fixed::{Q15, mul_q15, q15_from_f32};
+
+/// Number of bars in the spectrum view.
+pub const SPECTRUM_BANDS: usize = 16;
+
+/// Bar levels of one spectrum frame, each 0-100.
+pub type SpectrumBands = [u8; SPECTRUM_BANDS];
+
+/// Level drop per analysis frame, so bars fall smoothly instead of flickering.
+const DECAY: u8 = 6;
+/// Dynamic range shown by the bars, in units of 1/256 of a power octave (~3 dB).
+const RANGE_LOG2_Q8: i32 = 18 << 8;
+/// Power of a full-scale sine after windowing and the FFT's `1/N` scaling, as log2 in Q8.
+const FULL_SCALE_LOG2_Q8: i32 = 25 << 8;
+
+/// Lock-free holder of the latest spectrum, written by the audio task and
+/// read by the UI. Each band is an independent atomic, so a reader may see a
+/// mix of two consecutive frames, which is invisible on a bar graph.
+pub struct SpectrumSnapshot {
+    bands: [AtomicU8; SPECTRUM_BANDS],
+}
+
+impl SpectrumSnapshot {
+    pub const fn new() -> Self {
+        Self {
+            bands: [const { AtomicU8::new(0) }; SPECTRUM_BANDS],
+        }
+    }
+
+    pub fn publish(&self, bands: &SpectrumBands) {
+        for (slot, &level) in self.bands.iter().zip(bands) {
+            slot.store(level, Ordering::Relaxed);
+        }
+    }
+
+    pub fn load(&self) -> SpectrumBands {
+        let mut bands = [0; SPECTRUM_BANDS];
+        for (level, slot) in bands.iter_mut().zip(&self.bands) {
+            *level = slot.load(Ordering::Relaxed);
+        }
+        bands
+    }
+
+    pub fn clear(&self) {
+        self.publish(&[0; SPECTRUM_BANDS]);
+    }
+}
+
+impl Default for SpectrumSnapshot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Collects output samples and turns every [`FFT_SIZE`] of them into bar levels.
+///
+/// Bands are spaced logarithmically, matching how pitch is perceived, and
+/// levels are on a dB scale.
+#[derive(Debug, Clone)]
+pub struct SpectrumAnalyzer {
+    fft: Fft,
+    /// Hann window (Q15).
+    window: [Q15; FFT_SIZE],
+    /// First FFT bin of each band; the last entry closes the final band.
+    edges: [u8; SPECTRUM_BANDS + 1],
+    buffer: [i16; FFT_SIZE],
+    filled: usize,
+    bands: SpectrumBands,
+    updated: bool,
+}
+
+impl SpectrumAnalyzer {
+    pub fn new() -> Self {
+        let mut window = [0; FFT_SIZE];
+        for (i, w) in window.iter_mut().enumerate() {
+            let phase = 2.0 * PI * i as f32 / FFT_SIZE as f32;
+            *w = q15_from_f32(0.5 - 0.5 * libm::cosf(phase));
+        }
+
+        // Log-spaced edges from bin 1 up to Nyquist, at least one bin wide.
+        let bins = (FFT_SIZE / 2) as f32;
+        let mut edges = [0u8; SPECTRUM_BANDS + 1];
+        edges[0] = 1;
+        for band in 1..=SPECTRUM_BANDS {
+            let edge = libm::powf(bins, band as f32 / SPECTRUM_BANDS as f32) as u8;
+            edges[band] = edge.max(edges[band - 1] + 1);
+        }
+
+        Self {
+            fft: Fft::new(),
+            window,
+            edges,
+            buffer: [0; FFT_SIZE],
+            filled: 0,
+            bands: [0; SPECTRUM_BANDS],
+            updated: false,
+        }
+    }
+
+    /// Feeds interleaved stereo samples; channels are averaged before analysis.
+    pub fn push(&mut self, samples: &[i16]) {
+        for frame in samples.chunks_exact(CHANNELS) {
+            let sum: i32 = frame.iter().map(|&s| s as i32).sum();
+            self.buffer[self.filled] = (sum / CHANNELS as i32) as i16;
+            self.filled += 1;
+            if self.filled == FFT_SIZE {
+                self.analyze();
+                self.filled = 0;
+            }
+        }
+    }
+
+    /// Returns the bar levels if a new frame was analysed since the last call.
+    pub fn take_bands(&mut self) -> Option<SpectrumBands> {
+        if self.updated {
+            self.updated = false;
+            Some(self.bands)
+        } else {
+            None
+        }
+    }
+
+    /// Forgets buffered samples and drops every bar to zero.
+    pub fn reset(&mut self) {
+        self.filled = 0;
+        self.bands = [0; SPECTRUM_BANDS];
+        self.updated = true;
+    }
+
+    fn analyze(&mut self) {
+        let mut re = [0i32; FFT_SIZE];
+        let mut im = [0i32; FFT_SIZE];
+        for ((x, &sample), &w) in re.iter_mut().zip(&self.buffer).zip(&self.window) {
+            *x = mul_q15(sample, w) as i32;
+        }
+
+        self.fft.process(&mut re, &mut im);
+
+        for (band, level) in self.bands.iter_mut().enumerate() {
+            let bins = self.edges[band] as usize..self.edges[band + 1] as usize;
+            let power = bins
+                .map(|k| {
+                    let (r, i) = (re[k] as i64, im[k] as i64);
+                    (r * r + i * i) as u64
+                })
+                .max()
+                .unwrap_or(0);
+
+            let floor = FULL_SCALE_LOG2_Q8 - RANGE_LOG2_Q8;
+            let scaled = (log2_q8(power) - floor).clamp(0, RANGE_LOG2_Q8);
+            let target = (scaled * 100 / RANGE_LOG2_Q8) as u8;
+            *level = target.max(level.saturating_sub(DECAY));
+        }
+        self.updated = true;
+    }
+}
+
+impl Default for SpectrumAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Integer log2 in Q8, with the fraction linearly interpolated from the
+/// bits following the leading one.
+fn log2_q8(x: u64) -> i32 {
+    if x == 0 {
+        return 0;
+    }
+    let int = 63 - x.leading_zeros();
+    let frac = if int >= 8 {
+        (x >> (int - 8)) & 0xFF
+    } else {
+        (x << (8 - int)) & 0xFF
+    };
+    ((int << 8) | frac as u32) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One analysis frame of a stereo sine centred on FFT bin `bin`.
+    fn sine(bin: usize, amplitude: f32) -> Vec<i16> {
+        (0..FFT_SIZE)
+            .flat_map(|n| {
+                let phase = 2.0 * PI * (bin * n) as f32 / FFT_SIZE as f32;
+                let sample = (amplitude * libm::sinf(phase)) as i16;
+                [sample; CHANNELS]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_sine_lights_the_band_holding_its_frequency() {
+        for bin in [2, 12, 40, 100] {
+            let mut analyzer = SpectrumAnalyzer::new();
+            analyzer.push(&sine(bin, 32000.0));
+            let bands = analyzer.take_bands().unwrap();
+
+            let band = analyzer
+                .edges
+                .windows(2)
+                .position(|edge| (edge[0] as usize..edge[1] as usize).contains(&bin))
+                .unwrap();
+            let loudest = (0..SPECTRUM_BANDS).max_by_key(|&b| bands[b]).unwrap();
+            assert_eq!(loudest, band, "bin {bin}: {bands:?}");
+            assert!(bands[band] >= 90, "bin {bin}: {bands:?}");
+            // The window only leaks into the neighbouring bands
+            for (b, &level) in bands.iter().enumerate() {
+                if b.abs_diff(band) > 1 {
+                    assert_eq!(level, 0, "bin {bin}: {bands:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn silence_gives_empty_bars() {
+        let mut analyzer = SpectrumAnalyzer::new();
+        assert_eq!(analyzer.take_bands(), None);
+        analyzer.push(&[0; FFT_SIZE * CHANNELS]);
+        assert_eq!(analyzer.take_bands(), Some([0; SPECTRUM_BANDS]));
+        assert_eq!(analyzer.take_bands(), None);
+    }
+
+    #[test]
+    fn bars_fall_gradually_once_the_sound_stops() {
+        let mut analyzer = SpectrumAnalyzer::new();
+        analyzer.push(&sine(12, 32000.0));
+        let loud = analyzer.take_bands().unwrap();
+        analyzer.push(&[0; FFT_SIZE * CHANNELS]);
+        let falling = analyzer.take_bands().unwrap();
+        for (&before, &after) in loud.iter().zip(&falling) {
+            assert_eq!(after, before.saturating_sub(DECAY));
+        }
+
+        analyzer.reset();
+        assert_eq!(analyzer.take_bands(), Some([0; SPECTRUM_BANDS]));
+    }
+}
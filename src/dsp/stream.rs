@@ -0,0 +1,387 @@
+use super::adpcm::{self, AdpcmChannel};
+use super::fixed::saturate;
+use super::wav::{self, AudioFormat, Encoding, WavError};
+use super::{CHANNELS, SampleSource};
+
+/// Reader over a block of audio stored in flash.
+///
+/// Keeps track of the playback position so callers only have to ask for the
+/// next block of samples and check for the end of the track. Whatever the
+/// source layout (PCM or IMA ADPCM, mono or stereo), samples come out as
+/// interleaved stereo 16-bit; mono tracks are copied to both channels.
+#[derive(Debug, Clone)]
+pub struct PcmStream {
+    data: &'static [u8],
+    format: AudioFormat,
+    /// Byte offset of the current frame (PCM) or block (ADPCM).
+    offset: usize,
+    /// Next frame to decode within the current ADPCM block.
+    block_frame: usize,
+    adpcm: [AdpcmChannel; CHANNELS],
+    /// Track length when shorter than the data, which is padded to whole ADPCM blocks.
+    frames: Option<usize>,
+}
+
+impl PcmStream {
+    /// Creates a stream over headerless samples in the given format.
+    pub const fn new(data: &'static [u8], format: AudioFormat) -> Self {
+        Self {
+            data,
+            format,
+            offset: 0,
+            block_frame: 0,
+            adpcm: [AdpcmChannel::new(); CHANNELS],
+            frames: None,
+        }
+    }
+
+    /// Ends the track after `frames` frames, ignoring the padding that follows.
+    pub const fn with_frames(mut self, frames: usize) -> Self {
+        self.frames = Some(frames);
+        self
+    }
+
+    /// Creates a stream from an embedded asset.
+    ///
+    /// Files with a RIFF/WAVE header are parsed and played in the format the
+    /// header describes, and end where its `fact` chunk says; anything else is
+    /// treated as [`AudioFormat::RAW_DEFAULT`].
+    pub fn from_asset(bytes: &'static [u8]) -> Result<Self, WavError> {
+        if wav::is_wav(bytes) {
+            let wav = wav::parse(bytes)?;
+            let stream = Self::new(wav.data, wav.format);
+            Ok(match wav.frames {
+                Some(frames) => stream.with_frames(frames as usize),
+                None => stream,
+            })
+        } else {
+            Ok(Self::new(bytes, AudioFormat::RAW_DEFAULT))
+        }
+    }
+
+    pub fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// Decodes up to `out.len() / CHANNELS` frames into `out` as interleaved
+    /// stereo, returning how many samples were written.
+    pub fn read(&mut self, out: &mut [i16]) -> usize {
+        match self.format.encoding {
+            Encoding::Pcm => self.read_pcm(out),
+            Encoding::ImaAdpcm => self.read_adpcm(out),
+        }
+    }
+
+    fn read_pcm(&mut self, out: &mut [i16]) -> usize {
+        let frame_size = self.format.frame_size();
+        let width = self.format.bytes_per_sample();
+        let channels = self.format.channels as usize;
+        let remaining = &self.data[self.offset..];
+        let left = self.total_frames().saturating_sub(self.frame_position());
+        let mut count = 0;
+        for (output, frame) in out
+            .chunks_exact_mut(CHANNELS)
+            .zip(remaining.chunks_exact(frame_size).take(left))
+        {
+            let mut frame_samples = [0i16; CHANNELS];
+            for (slot, bytes) in frame_samples.iter_mut().zip(frame.chunks_exact(width)) {
+                *slot = decode_sample(bytes);
+            }
+            write_frame(output, &frame_samples[..channels]);
+            count += 1;
+        }
+        self.offset += count * frame_size;
+        count * CHANNELS
+    }
+
+    fn read_adpcm(&mut self, out: &mut [i16]) -> usize {
+        let channels = self.format.channels as usize;
+        let block_align = self.format.block_align as usize;
+        let frames_per_block = self.format.frames_per_block();
+
+        let mut count = 0;
+        while count + CHANNELS <= out.len() && !self.is_finished() {
+            let end = (self.offset + block_align).min(self.data.len());
+            let block = &self.data[self.offset..end];
+            let mut frame_samples = [0i16; CHANNELS];
+
+            if !adpcm::decode_frame(
+                block,
+                self.block_frame,
+                &mut self.adpcm[..channels],
+                &mut frame_samples[..channels],
+            ) {
+                // Trailing garbage too short to hold a header, or the end of
+                // a short final block: nothing left to decode.
+                self.offset = self.data.len();
+                break;
+            }
+
+            write_frame(
+                &mut out[count..count + CHANNELS],
+                &frame_samples[..channels],
+            );
+            count += CHANNELS;
+
+            self.block_frame += 1;
+            if self.block_frame == frames_per_block {
+                self.block_frame = 0;
+                self.offset = end;
+            }
+        }
+        count
+    }
+
+    /// Rewinds the stream to the first sample.
+    pub fn restart(&mut self) {
+        self.offset = 0;
+        self.block_frame = 0;
+    }
+
+    /// Total number of frames in the track.
+    pub fn total_frames(&self) -> usize {
+        let stored = self.stored_frames();
+        self.frames.map_or(stored, |frames| frames.min(stored))
+    }
+
+    /// Number of frames the data holds, padding included.
+    fn stored_frames(&self) -> usize {
+        match self.format.encoding {
+            Encoding::Pcm => self.data.len() / self.format.frame_size(),
+            Encoding::ImaAdpcm => {
+                let block_align = self.format.block_align as usize;
+                let channels = self.format.channels as usize;
+                let full = self.data.len() / block_align * self.format.frames_per_block();
+                // A short final block holds its header frame plus two frames per byte and channel.
+                let tail = self.data.len() % block_align;
+                let header = adpcm::HEADER_SIZE * channels;
+                let partial = if tail >= header {
+                    (tail - header) * 2 / channels + 1
+                } else {
+                    0
+                };
+                full + partial
+            }
+        }
+    }
+
+    /// Track length in milliseconds, derived from the data size and sample rate.
+    pub fn duration_ms(&self) -> u32 {
+        self.frames_to_ms(self.total_frames())
+    }
+
+    /// Time of the next frame to be read, in milliseconds.
+    pub fn position_ms(&self) -> u32 {
+        self.frames_to_ms(self.frame_position())
+    }
+
+    fn frames_to_ms(&self, frames: usize) -> u32 {
+        match self.format.sample_rate {
+            0 => 0,
+            rate => (frames as u64 * 1000 / rate as u64) as u32,
+        }
+    }
+
+    /// Index of the next frame to be read.
+    pub fn frame_position(&self) -> usize {
+        match self.format.encoding {
+            Encoding::Pcm => self.offset / self.format.frame_size(),
+            Encoding::ImaAdpcm => {
+                let block = self.offset / self.format.block_align as usize;
+                (block * self.format.frames_per_block() + self.block_frame).min(self.total_frames())
+            }
+        }
+    }
+
+    /// Moves the read position to `frame`, clamped to the end of the track.
+    ///
+    /// The position is always aligned to a point the decoder can restart
+    /// from: any frame for PCM, the start of the enclosing block for ADPCM.
+    /// Returns the frame actually reached.
+    pub fn seek(&mut self, frame: usize) -> usize {
+        let frame = frame.min(self.total_frames());
+        match self.format.encoding {
+            Encoding::Pcm => {
+                self.offset = frame * self.format.frame_size();
+            }
+            Encoding::ImaAdpcm => {
+                let block = frame / self.format.frames_per_block();
+                self.offset = (block * self.format.block_align as usize).min(self.data.len());
+                self.block_frame = 0;
+            }
+        }
+        self.frame_position()
+    }
+
+    /// Moves the read position to the frame at `ms`, e.g. to resume a saved position.
+    pub fn seek_ms(&mut self, ms: u32) -> usize {
+        let frame = ms as u64 * self.format.sample_rate as u64 / 1000;
+        self.seek(frame as usize)
+    }
+
+    /// Moves the read position by `seconds` (negative rewinds), clamped to the track bounds.
+    pub fn seek_by_seconds(&mut self, seconds: i32) -> usize {
+        let delta = seconds as i64 * self.format.sample_rate as i64;
+        let target = (self.frame_position() as i64 + delta).max(0);
+        self.seek(target as usize)
+    }
+
+    /// Returns `true` once there are no complete frames left to read.
+    pub fn is_finished(&self) -> bool {
+        match self.format.encoding {
+            Encoding::Pcm => self.frame_position() >= self.total_frames(),
+            Encoding::ImaAdpcm => {
+                self.offset >= self.data.len() || self.frame_position() >= self.total_frames()
+            }
+        }
+    }
+
+    /// Current read position in bytes.
+    pub fn position(&self) -> usize {
+        self.offset
+    }
+
+    /// Total length of the track in bytes.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Playback progress (0-100%).
+    pub fn percentage(&self) -> u8 {
+        match self.total_frames() {
+            0 => 0,
+            total => (self.frame_position() * 100 / total) as u8,
+        }
+    }
+}
+
+impl SampleSource for PcmStream {
+    fn format(&self) -> AudioFormat {
+        PcmStream::format(self)
+    }
+
+    fn read(&mut self, out: &mut [i16]) -> usize {
+        PcmStream::read(self, out)
+    }
+}
+
+/// Writes one decoded frame as stereo, duplicating mono sources on both channels.
+fn write_frame(out: &mut [i16], samples: &[i16]) {
+    match *samples {
+        [mono] => out.fill(mono),
+        _ => out.copy_from_slice(samples),
+    }
+}
+
+/// Converts one little-endian PCM sample of 1, 2 or 3 bytes to 16 bits.
+fn decode_sample(bytes: &[u8]) -> i16 {
+    match *bytes {
+        // 8-bit WAV samples are unsigned with a 128 midpoint.
+        [b] => ((b as i16) - 128) << 8,
+        [lo, hi] => i16::from_le_bytes([lo, hi]),
+        // Drop the least significant byte, rounding to nearest.
+        [lo, mid, hi] => {
+            let value = i32::from_le_bytes([0, lo, mid, hi]) >> 8;
+            saturate((value + 0x80) >> 8)
+        }
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dsp::adpcm::frames_per_block;
+    use crate::dsp::tests::pcm_stream;
+
+    /// Reads `stream` to the end in blocks of `samples`, returning the size of each read.
+    fn read_sizes(stream: &mut PcmStream, samples: usize) -> Vec<usize> {
+        let mut out = vec![0; samples];
+        core::iter::from_fn(|| Some(stream.read(&mut out)))
+            .take_while(|&count| count > 0)
+            .collect()
+    }
+
+    #[test]
+    fn reads_stop_at_the_end_of_the_track() {
+        let mut stream = pcm_stream(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10], 11025, 2);
+        assert_eq!(read_sizes(&mut stream, 4), [4, 4, 2]);
+        assert!(stream.is_finished());
+        assert_eq!(stream.read(&mut [0; 4]), 0);
+
+        stream.restart();
+        // Odd buffers only take whole frames
+        assert_eq!(read_sizes(&mut stream, 5), [4, 4, 2]);
+    }
+
+    #[test]
+    fn decodes_8_and_24_bit_samples() {
+        let mut stream = PcmStream::new(&[0, 128, 255], AudioFormat::pcm(8000, 1, 8));
+        let mut out = [0; 6];
+        assert_eq!(stream.read(&mut out), 6);
+        assert_eq!(out, [-32768, -32768, 0, 0, 32512, 32512]);
+
+        // 0x123480 rounds up, 0xFFFF7F rounds down to -1
+        let bytes = &[0x80, 0x34, 0x12, 0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F];
+        let mut stream = PcmStream::new(bytes, AudioFormat::pcm(8000, 1, 24));
+        assert_eq!(stream.read(&mut out), 6);
+        assert_eq!(out, [0x1235, 0x1235, -1, -1, i16::MAX, i16::MAX]);
+    }
+
+    #[test]
+    fn adpcm_counts_and_decodes_a_short_final_block() {
+        // One full 36-byte mono block (65 frames) and a tail holding 9 frames
+        let format = AudioFormat::ima_adpcm(8000, 1, 36);
+        let mut data = vec![0u8; 36 + 8];
+        data[..2].copy_from_slice(&1000i16.to_le_bytes());
+        data[36..38].copy_from_slice(&(-1000i16).to_le_bytes());
+        let mut stream = PcmStream::new(data.leak(), format);
+
+        assert_eq!(frames_per_block(36, 1), 65);
+        assert_eq!(stream.total_frames(), 74);
+        assert_eq!(read_sizes(&mut stream, 2 * 50), [100, 48]);
+        assert_eq!(stream.frame_position(), 74);
+        assert_eq!(stream.seek(70), 65);
+        let mut out = [0; 2];
+        stream.read(&mut out);
+        assert_eq!(out, [-1000, -1000]);
+    }
+
+    #[test]
+    fn the_fact_chunk_ends_a_padded_track() {
+        // Two 36-byte mono blocks of 65 frames, of which only 100 are audio
+        let format = AudioFormat::ima_adpcm(8000, 1, 36);
+        let file = wav::tests::wav_file(format, 100, &[0; 72]);
+        let mut stream = PcmStream::from_asset(file.leak()).unwrap();
+
+        assert_eq!(stream.total_frames(), 100);
+        assert_eq!(stream.duration_ms(), 12);
+        assert_eq!(read_sizes(&mut stream, 2 * 64), [128, 72]);
+        assert!(stream.is_finished());
+        assert_eq!(stream.percentage(), 100);
+        assert_eq!(stream.seek(130), 65);
+
+        // A length past the data is ignored
+        let file = wav::tests::wav_file(format, 500, &[0; 72]);
+        assert_eq!(
+            PcmStream::from_asset(file.leak()).unwrap().total_frames(),
+            130
+        );
+    }
+
+    #[test]
+    fn seeking_is_clamped_to_the_track() {
+        let mut stream = pcm_stream(&[0; 2 * 11025 * 3], 11025, 2);
+        assert_eq!(stream.duration_ms(), 3000);
+        assert_eq!(stream.seek_by_seconds(2), 22050);
+        assert_eq!(stream.seek_by_seconds(5), 33075);
+        assert!(stream.is_finished());
+        assert_eq!(stream.seek_by_seconds(-10), 0);
+        assert_eq!(stream.seek_ms(1500), 16537);
+        assert_eq!(stream.position_ms(), 1499);
+    }
+}
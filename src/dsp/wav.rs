@@ -0,0 +1,500 @@
+//! Minimal RIFF/WAVE parser for tracks stored in flash.
+//!
+//! Integer PCM and IMA ADPCM are supported. The parser borrows from the input,
+//! so the sample data and tag strings point straight into the embedded asset.
+
+use super::adpcm;
+
+/// How samples are coded in the data chunk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    /// Uncompressed integer PCM.
+    Pcm,
+    /// IMA/DVI ADPCM, 4 bits per sample.
+    ImaAdpcm,
+}
+
+/// Description of how samples are laid out in a stream.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioFormat {
+    pub encoding: Encoding,
+    /// Frames per second, in Hz.
+    pub sample_rate: u32,
+    /// 1 for mono, 2 for interleaved stereo.
+    pub channels: u16,
+    /// 8 (unsigned), 16 or 24 (signed, little-endian) for PCM; 4 for ADPCM.
+    pub bits_per_sample: u16,
+    /// Size of the smallest independently decodable unit, in bytes.
+    /// One frame for PCM, one block for ADPCM.
+    pub block_align: u16,
+}
+
+impl AudioFormat {
+    /// Format assumed for headerless `.raw` assets (`ffmpeg -ar 11025 -ac 1 -f s16le`).
+    pub const RAW_DEFAULT: AudioFormat = AudioFormat::pcm(11025, 1, 16);
+
+    /// Describes an uncompressed PCM stream.
+    pub const fn pcm(sample_rate: u32, channels: u16, bits_per_sample: u16) -> Self {
+        Self {
+            encoding: Encoding::Pcm,
+            sample_rate,
+            channels,
+            bits_per_sample,
+            block_align: channels * (bits_per_sample / 8),
+        }
+    }
+
+    /// Describes an IMA ADPCM stream split into blocks of `block_align` bytes.
+    pub const fn ima_adpcm(sample_rate: u32, channels: u16, block_align: u16) -> Self {
+        Self {
+            encoding: Encoding::ImaAdpcm,
+            sample_rate,
+            channels,
+            bits_per_sample: 4,
+            block_align,
+        }
+    }
+
+    /// Size of one PCM sample, in bytes.
+    pub const fn bytes_per_sample(&self) -> usize {
+        (self.bits_per_sample / 8) as usize
+    }
+
+    /// Size of one PCM frame (one sample for every channel), in bytes.
+    pub const fn frame_size(&self) -> usize {
+        self.bytes_per_sample() * self.channels as usize
+    }
+
+    /// Number of frames decoded from one block of [`Self::block_align`] bytes.
+    pub const fn frames_per_block(&self) -> usize {
+        match self.encoding {
+            Encoding::Pcm => 1,
+            Encoding::ImaAdpcm => {
+                adpcm::frames_per_block(self.block_align as usize, self.channels as usize)
+            }
+        }
+    }
+}
+
+/// Reasons a WAV file may be rejected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WavError {
+    /// The file does not start with a `RIFF....WAVE` header.
+    NotWave,
+    /// A chunk header or body extends past the end of the file.
+    Truncated,
+    /// No `fmt ` chunk was found before the `data` chunk.
+    MissingFormat,
+    /// No `data` chunk was found.
+    MissingData,
+    /// The `fmt ` chunk describes something other than integer PCM or IMA ADPCM.
+    UnsupportedEncoding(u16),
+    UnsupportedBitDepth(u16),
+    UnsupportedChannels(u16),
+    /// The `fmt ` chunk gives a sample rate of zero.
+    InvalidSampleRate,
+}
+
+/// A parsed WAV file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Wav<'a> {
+    pub format: AudioFormat,
+    /// Raw sample bytes from the `data` chunk.
+    pub data: &'a [u8],
+    /// Length in frames from the `fact` chunk. Compressed data may end in a
+    /// block padded past it.
+    pub frames: Option<u32>,
+    /// `INAM` tag from the `LIST/INFO` chunk.
+    pub title: Option<&'a str>,
+    /// `IART` tag from the `LIST/INFO` chunk.
+    pub artist: Option<&'a str>,
+}
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_IMA_ADPCM: u16 = 0x0011;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Returns `true` if `bytes` starts with a RIFF/WAVE header.
+pub fn is_wav(bytes: &[u8]) -> bool {
+    bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WAVE"
+}
+
+/// Parses a complete WAV file.
+pub fn parse(bytes: &[u8]) -> Result<Wav<'_>, WavError> {
+    if !is_wav(bytes) {
+        return Err(WavError::NotWave);
+    }
+
+    let mut format = None;
+    let mut data = None;
+    let mut frames = None;
+    let mut title = None;
+    let mut artist = None;
+
+    for chunk in Chunks::new(&bytes[12..]) {
+        let (id, body) = chunk?;
+        match id {
+            b"fmt " => format = Some(parse_format(body)?),
+            b"data" if format.is_none() => return Err(WavError::MissingFormat),
+            b"data" => data = Some(body),
+            b"fact" if body.len() >= 4 => frames = Some(u32_at(body, 0)),
+            b"LIST" if body.starts_with(b"INFO") => {
+                for tag in Chunks::new(&body[4..]) {
+                    let (tag_id, value) = tag?;
+                    match tag_id {
+                        b"INAM" => title = tag_str(value),
+                        b"IART" => artist = tag_str(value),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(Wav {
+        format: format.ok_or(WavError::MissingFormat)?,
+        data: data.ok_or(WavError::MissingData)?,
+        frames,
+        title,
+        artist,
+    })
+}
+
+fn parse_format(body: &[u8]) -> Result<AudioFormat, WavError> {
+    if body.len() < 16 {
+        return Err(WavError::Truncated);
+    }
+
+    let mut encoding = u16_at(body, 0);
+    if encoding == WAVE_FORMAT_EXTENSIBLE && body.len() >= 26 {
+        // The first two bytes of the sub-format GUID hold the real encoding.
+        encoding = u16_at(body, 24);
+    }
+
+    let channels = u16_at(body, 2);
+    let sample_rate = u32_at(body, 4);
+    let block_align = u16_at(body, 12);
+    let bits_per_sample = u16_at(body, 14);
+    if !matches!(channels, 1 | 2) {
+        return Err(WavError::UnsupportedChannels(channels));
+    }
+    if sample_rate == 0 {
+        return Err(WavError::InvalidSampleRate);
+    }
+
+    match encoding {
+        WAVE_FORMAT_PCM => {
+            if !matches!(bits_per_sample, 8 | 16 | 24) {
+                return Err(WavError::UnsupportedBitDepth(bits_per_sample));
+            }
+            Ok(AudioFormat::pcm(sample_rate, channels, bits_per_sample))
+        }
+        WAVE_FORMAT_IMA_ADPCM => {
+            let format = AudioFormat::ima_adpcm(sample_rate, channels, block_align);
+            if bits_per_sample != 4 {
+                return Err(WavError::UnsupportedBitDepth(bits_per_sample));
+            }
+            if format.frames_per_block() == 0 {
+                return Err(WavError::Truncated);
+            }
+            Ok(format)
+        }
+        _ => Err(WavError::UnsupportedEncoding(encoding)),
+    }
+}
+
+/// Trims the NUL padding off an INFO string; non-UTF-8 tags are ignored.
+fn tag_str(value: &[u8]) -> Option<&str> {
+    let end = value.iter().position(|&b| b == 0).unwrap_or(value.len());
+    core::str::from_utf8(&value[..end])
+        .ok()
+        .filter(|s| !s.is_empty())
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+/// Iterator over the `(id, body)` pairs of a sequence of RIFF chunks.
+struct Chunks<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Chunks<'a> {
+    fn new(rest: &'a [u8]) -> Self {
+        Self { rest }
+    }
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = Result<(&'a [u8; 4], &'a [u8]), WavError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rest.len() < 8 {
+            return None;
+        }
+
+        let (header, rest) = self.rest.split_at(8);
+        let id: &[u8; 4] = header[0..4].try_into().unwrap();
+        let mut size = u32_at(header, 4) as usize;
+        if size > rest.len() && id == b"data" {
+            // Streaming writers leave the data size unset; take what is there.
+            size = rest.len();
+        }
+        if size > rest.len() {
+            self.rest = &[];
+            return Some(Err(WavError::Truncated));
+        }
+
+        let body = &rest[..size];
+        // Chunk bodies are padded to an even length.
+        let padded = (size + (size & 1)).min(rest.len());
+        self.rest = &rest[padded..];
+        Some(Ok((id, body)))
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    /// A RIFF/WAVE file holding `chunks`, each padded to an even length.
+    pub fn riff(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut body = b"WAVE".to_vec();
+        for (id, chunk) in chunks {
+            body.extend_from_slice(*id);
+            body.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            body.extend_from_slice(chunk);
+            if chunk.len() % 2 == 1 {
+                body.push(0);
+            }
+        }
+        let mut file = b"RIFF".to_vec();
+        file.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        file.extend_from_slice(&body);
+        file
+    }
+
+    /// Body of a 16-byte `fmt ` chunk.
+    pub fn fmt(
+        encoding: u16,
+        channels: u16,
+        sample_rate: u32,
+        block_align: u16,
+        bits: u16,
+    ) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&encoding.to_le_bytes());
+        body.extend_from_slice(&channels.to_le_bytes());
+        body.extend_from_slice(&sample_rate.to_le_bytes());
+        let byte_rate = sample_rate * block_align as u32;
+        body.extend_from_slice(&byte_rate.to_le_bytes());
+        body.extend_from_slice(&block_align.to_le_bytes());
+        body.extend_from_slice(&bits.to_le_bytes());
+        body
+    }
+
+    /// A file holding `data` in `format`, `frames` long according to its `fact` chunk.
+    pub fn wav_file(format: AudioFormat, frames: u32, data: &[u8]) -> Vec<u8> {
+        let encoding = match format.encoding {
+            Encoding::Pcm => WAVE_FORMAT_PCM,
+            Encoding::ImaAdpcm => WAVE_FORMAT_IMA_ADPCM,
+        };
+        let header = fmt(
+            encoding,
+            format.channels,
+            format.sample_rate,
+            format.block_align,
+            format.bits_per_sample,
+        );
+        riff(&[
+            (b"fmt ", &header),
+            (b"fact", &frames.to_le_bytes()),
+            (b"data", data),
+        ])
+    }
+
+    fn pcm16_stereo() -> Vec<u8> {
+        fmt(WAVE_FORMAT_PCM, 2, 44_100, 4, 16)
+    }
+
+    #[test]
+    fn reads_tags_before_the_data() {
+        let info = [
+            b"INFO".as_slice(),
+            b"INAM",
+            &[6, 0, 0, 0],
+            b"Intro\0",
+            b"IART",
+            &[4, 0, 0, 0],
+            b"Band",
+        ]
+        .concat();
+        let file = riff(&[
+            (b"fmt ", &pcm16_stereo()),
+            (b"LIST", &info),
+            (b"data", &[1, 2, 3, 4]),
+        ]);
+
+        let wav = parse(&file).unwrap();
+        assert_eq!(wav.format, AudioFormat::pcm(44_100, 2, 16));
+        assert_eq!(wav.data, [1, 2, 3, 4]);
+        assert_eq!(wav.title, Some("Intro"));
+        assert_eq!(wav.artist, Some("Band"));
+        assert!(is_wav(&file));
+    }
+
+    #[test]
+    fn reads_the_length_from_the_fact_chunk() {
+        let format = AudioFormat::ima_adpcm(11_025, 1, 256);
+        let file = wav_file(format, 300, &[0; 512]);
+        assert_eq!(parse(&file).unwrap().frames, Some(300));
+
+        let file = riff(&[(b"fmt ", &pcm16_stereo()), (b"data", &[0; 4])]);
+        assert_eq!(parse(&file).unwrap().frames, None);
+    }
+
+    #[test]
+    fn odd_sized_chunks_are_padded() {
+        // A 3-byte tag inside the list and a 3-byte chunk before the data
+        let info = [
+            b"INFO".as_slice(),
+            b"IART",
+            &[3, 0, 0, 0],
+            b"Abc\0",
+            b"INAM",
+            &[1, 0, 0, 0],
+            b"X\0",
+        ]
+        .concat();
+        let file = riff(&[
+            (b"fmt ", &fmt(WAVE_FORMAT_PCM, 1, 8_000, 1, 8)),
+            (b"LIST", &info),
+            (b"junk", &[9, 9, 9]),
+            (b"data", &[0x80, 0x81, 0x82]),
+        ]);
+
+        let wav = parse(&file).unwrap();
+        assert_eq!(wav.data, [0x80, 0x81, 0x82]);
+        assert_eq!(wav.artist, Some("Abc"));
+        assert_eq!(wav.title, Some("X"));
+        // The final pad byte may be missing
+        assert_eq!(
+            parse(&file[..file.len() - 1]).unwrap().data,
+            [0x80, 0x81, 0x82]
+        );
+    }
+
+    #[test]
+    fn reads_the_encoding_of_extensible_formats() {
+        let mut extensible = fmt(WAVE_FORMAT_EXTENSIBLE, 2, 48_000, 6, 24);
+        // cbSize, valid bits, channel mask, then the sub-format GUID
+        extensible.extend_from_slice(&[22, 0, 24, 0, 3, 0, 0, 0]);
+        extensible.extend_from_slice(&WAVE_FORMAT_PCM.to_le_bytes());
+        extensible.extend_from_slice(&[0, 0, 0, 0, 0x10, 0, 0x80, 0, 0, 0xAA, 0, 0x38, 0x9B, 0x71]);
+        let file = riff(&[(b"fmt ", &extensible), (b"data", &[0; 12])]);
+        assert_eq!(
+            parse(&file).unwrap().format,
+            AudioFormat::pcm(48_000, 2, 24)
+        );
+
+        extensible[24] = 0x03; // IEEE float
+        let file = riff(&[(b"fmt ", &extensible), (b"data", &[0; 12])]);
+        assert_eq!(parse(&file), Err(WavError::UnsupportedEncoding(3)));
+    }
+
+    #[test]
+    fn reads_ima_adpcm() {
+        let file = riff(&[
+            (b"fmt ", &fmt(WAVE_FORMAT_IMA_ADPCM, 1, 11_025, 256, 4)),
+            (b"data", &[0; 512]),
+        ]);
+        let format = parse(&file).unwrap().format;
+        assert_eq!(format, AudioFormat::ima_adpcm(11_025, 1, 256));
+        assert_eq!(format.frames_per_block(), 505);
+
+        // Too small for the block headers
+        let file = riff(&[
+            (b"fmt ", &fmt(WAVE_FORMAT_IMA_ADPCM, 2, 11_025, 6, 4)),
+            (b"data", &[]),
+        ]);
+        assert_eq!(parse(&file), Err(WavError::Truncated));
+    }
+
+    #[test]
+    fn truncated_headers_are_rejected() {
+        let file = riff(&[(b"fmt ", &pcm16_stereo()), (b"data", &[0; 8])]);
+
+        assert_eq!(parse(&file[..11]), Err(WavError::NotWave));
+        assert_eq!(parse(b"RIFX\0\0\0\0WAVE"), Err(WavError::NotWave));
+        // Cut inside the format chunk
+        assert_eq!(parse(&file[..30]), Err(WavError::Truncated));
+        // A format chunk too short to hold a format
+        let file = riff(&[(b"fmt ", &pcm16_stereo()[..14]), (b"data", &[0; 8])]);
+        assert_eq!(parse(&file), Err(WavError::Truncated));
+        // Nothing after the header
+        assert_eq!(parse(&file[..12]), Err(WavError::MissingFormat));
+    }
+
+    #[test]
+    fn data_larger_than_the_file_is_cut_to_it() {
+        let mut file = riff(&[
+            (b"fmt ", &pcm16_stereo()),
+            (b"data", &[1, 2, 3, 4, 5, 6, 7, 8]),
+        ]);
+        let size_at = file.len() - 12;
+        file[size_at..size_at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(parse(&file).unwrap().data, [1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn the_format_must_come_first() {
+        let file = riff(&[(b"data", &[0; 4]), (b"fmt ", &pcm16_stereo())]);
+        assert_eq!(parse(&file), Err(WavError::MissingFormat));
+        let file = riff(&[(b"fmt ", &pcm16_stereo())]);
+        assert_eq!(parse(&file), Err(WavError::MissingData));
+    }
+
+    #[test]
+    fn unplayable_formats_are_rejected() {
+        let cases = [
+            (
+                fmt(WAVE_FORMAT_PCM, 0, 44_100, 0, 16),
+                WavError::UnsupportedChannels(0),
+            ),
+            (
+                fmt(WAVE_FORMAT_PCM, 6, 44_100, 12, 16),
+                WavError::UnsupportedChannels(6),
+            ),
+            (
+                fmt(WAVE_FORMAT_PCM, 2, 0, 4, 16),
+                WavError::InvalidSampleRate,
+            ),
+            (
+                fmt(WAVE_FORMAT_IMA_ADPCM, 1, 0, 256, 4),
+                WavError::InvalidSampleRate,
+            ),
+            (
+                fmt(WAVE_FORMAT_PCM, 2, 44_100, 8, 32),
+                WavError::UnsupportedBitDepth(32),
+            ),
+            (
+                fmt(WAVE_FORMAT_IMA_ADPCM, 1, 11_025, 256, 3),
+                WavError::UnsupportedBitDepth(3),
+            ),
+            (
+                fmt(0x0055, 2, 44_100, 1, 0),
+                WavError::UnsupportedEncoding(0x0055),
+            ),
+        ];
+        for (format, error) in cases {
+            let file = riff(&[(b"fmt ", &format), (b"data", &[0; 4])]);
+            assert_eq!(parse(&file), Err(error));
+        }
+    }
+}
@@ -1,9 +1,11 @@
+use embassy_futures::select::select;
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
-use embassy_time::{Duration, Timer};
-use esp_hal::gpio::{AnyPin, Input, InputConfig, Pull};
+use embassy_time::{Duration, Instant};
+
+use crate::hal::InputSource;
 
 /// Channel for encoder rotation events (buffer size: 10).
-pub static ENCODER_CHANNEL: Channel<CriticalSectionRawMutex, EncoderDirection, 10> = Channel::new();
+pub static ENCODER_CHANNEL: Channel<CriticalSectionRawMutex, EncoderEvent, 10> = Channel::new();
 
 /// Represents the direction of encoder rotation.
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -12,36 +14,295 @@ pub enum EncoderDirection {
     CounterClockwise,
 }
 
-/// Reads a rotary encoder and sends rotation events to `ENCODER_CHANNEL`.
+/// One detent of rotation, scaled by the acceleration.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EncoderEvent {
+    pub direction: EncoderDirection,
+    /// Number of steps this detent is worth: 1 when turning slowly, up to
+    /// [`EncoderConfig::max_multiplier`] on a fast spin.
+    pub steps: u8,
+}
+
+/// Quarter steps for each transition, indexed by `previous << 2 | current`
+/// with the pin state encoded as `A << 1 | B`.
+///
+/// Clockwise is the Gray sequence `00 -> 10 -> 11 -> 01 -> 00`. Transitions
+/// where both pins changed at once are impossible for a real rotation and count as 0.
+const TRANSITIONS: [i8; 16] = [0, -1, 1, 0, 1, 0, 0, -1, -1, 0, 0, 1, 0, 1, -1, 0];
+
+/// Decoder settings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EncoderConfig {
+    /// Gray-code transitions between two detents (4 for a full cycle per click).
+    pub transitions_per_detent: u8,
+    /// Detents further apart than this are not accelerated.
+    pub slow: Duration,
+    /// Detents this close together get the full [`max_multiplier`](Self::max_multiplier).
+    pub fast: Duration,
+    /// Largest number of steps a single detent can be worth.
+    pub max_multiplier: u8,
+}
+
+impl EncoderConfig {
+    /// Full cycle per detent; acceleration from 100ms down to 20ms per detent, up to 4x.
+    pub const fn new() -> Self {
+        Self {
+            transitions_per_detent: 4,
+            slow: Duration::from_millis(100),
+            fast: Duration::from_millis(20),
+            max_multiplier: 4,
+        }
+    }
+
+    pub const fn with_transitions_per_detent(mut self, transitions: u8) -> Self {
+        self.transitions_per_detent = transitions;
+        self
+    }
+
+    pub const fn with_acceleration(mut self, slow: Duration, fast: Duration, max: u8) -> Self {
+        self.slow = slow;
+        self.fast = fast;
+        self.max_multiplier = max;
+        self
+    }
+
+    /// Disables acceleration: every detent is one step.
+    pub const fn without_acceleration(mut self) -> Self {
+        self.max_multiplier = 1;
+        self
+    }
+}
+
+impl Default for EncoderConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Gray-code state-table decoder for a quadrature encoder.
+///
+/// Every edge of either pin is decoded, so no step is lost when turning
+/// quickly, and contact bounce cancels itself out: a bounce moves the state
+/// back and forth between two neighbours, adding and removing the same
+/// quarter step.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuadratureDecoder {
+    config: EncoderConfig,
+    state: u8,
+    /// Quarter steps accumulated towards the next detent.
+    count: i8,
+    last_detent: Option<(EncoderDirection, Instant)>,
+}
+
+impl QuadratureDecoder {
+    /// Creates a decoder starting from the current pin levels.
+    pub const fn new(config: EncoderConfig, a: bool, b: bool) -> Self {
+        Self {
+            config,
+            state: ((a as u8) << 1) | b as u8,
+            count: 0,
+            last_detent: None,
+        }
+    }
+
+    /// Feeds the pin levels sampled at `now`, returning an event when a detent is completed.
+    pub fn update(&mut self, a: bool, b: bool, now: Instant) -> Option<EncoderEvent> {
+        let state = ((a as u8) << 1) | b as u8;
+        self.count += TRANSITIONS[((self.state << 2) | state) as usize];
+        self.state = state;
+
+        let detent = self.config.transitions_per_detent.max(1) as i8;
+        let direction = if self.count >= detent {
+            EncoderDirection::Clockwise
+        } else if self.count <= -detent {
+            EncoderDirection::CounterClockwise
+        } else {
+            return None;
+        };
+        self.count = 0;
+
+        let steps = match self.last_detent {
+            // Reversing always starts slow
+            Some((last, at)) if last == direction => self.multiplier(now - at),
+            _ => 1,
+        };
+        self.last_detent = Some((direction, now));
+        Some(EncoderEvent { direction, steps })
+    }
+
+    /// Steps for a detent `interval` after the previous one, rising linearly
+    /// from 1 at `slow` to `max_multiplier` at `fast`.
+    fn multiplier(&self, interval: Duration) -> u8 {
+        let EncoderConfig {
+            slow,
+            fast,
+            max_multiplier,
+            ..
+        } = self.config;
+        let max = max_multiplier.max(1);
+        if interval >= slow {
+            1
+        } else if interval <= fast {
+            max
+        } else {
+            let range = (slow - fast).as_micros();
+            let speed = (slow - interval).as_micros();
+            1 + ((max - 1) as u64 * speed / range) as u8
+        }
+    }
+}
+
+/// Reads a rotary encoder on pins `a` and `b` and sends rotation events to
+/// `ENCODER_CHANNEL`. Never returns.
 ///
-/// Detects rotation direction by reading pin B state when pin A rises.
-/// Uses 50μs + 20ms debouncing to filter noise.
-#[embassy_executor::task]
-pub async fn encoder_reader_task(pin_a: AnyPin<'static>, pin_b: AnyPin<'static>) {
-    let config = InputConfig::default().with_pull(Pull::Up);
-    let mut tra = Input::new(pin_a, config);
-    let trb = Input::new(pin_b, config);
+/// Both pins are watched and sampled as soon as either has an edge, then fed
+/// to a [`QuadratureDecoder`]. There is no settle delay, which would let the
+/// next edge of a fast turn slip by: bounce is left to the state table, which
+/// cancels it out and ignores transitions where both pins changed.
+pub async fn read_encoder<A: InputSource, B: InputSource>(
+    mut a: A,
+    mut b: B,
+    config: EncoderConfig,
+) -> ! {
+    let mut decoder = QuadratureDecoder::new(config, a.is_high(), b.is_high());
 
     loop {
-        tra.wait_for_rising_edge().await;
+        select(a.wait_for_any_edge(), b.wait_for_any_edge()).await;
+
+        if let Some(event) = decoder.update(a.is_high(), b.is_high(), Instant::now()) {
+            ENCODER_CHANNEL.send(event).await;
+
+            log::debug!("Encoder: {event:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_time::Timer;
+
+    use super::*;
+    use crate::hal::VirtualPin;
+    use crate::hal::mock;
+
+    /// One detent clockwise, from both pins high.
+    const CLOCKWISE: [(bool, bool); 4] =
+        [(false, true), (false, false), (true, false), (true, true)];
 
-        Timer::after(Duration::from_micros(50)).await; // Debounce
+    async fn turn(a: &VirtualPin, b: &VirtualPin, levels: impl IntoIterator<Item = (bool, bool)>) {
+        for (level_a, level_b) in levels {
+            a.set_high(level_a);
+            b.set_high(level_b);
+            Timer::after_millis(2).await;
+        }
+    }
+
+    fn events() -> Vec<EncoderEvent> {
+        core::iter::from_fn(|| ENCODER_CHANNEL.try_receive().ok()).collect()
+    }
+
+    #[test]
+    fn read_encoder_reports_detents_in_both_directions() {
+        let _lock = mock::lock();
+        events();
+        let (a, b) = (VirtualPin::new(), VirtualPin::new());
+        let config = EncoderConfig::new().without_acceleration();
+
+        mock::run(read_encoder(&a, &b, config), async {
+            turn(&a, &b, CLOCKWISE).await;
+            turn(&a, &b, CLOCKWISE).await;
+            // Back through the same states: counter-clockwise
+            turn(
+                &a,
+                &b,
+                CLOCKWISE.into_iter().rev().skip(1).chain([(true, true)]),
+            )
+            .await;
+        });
+
+        let event = |direction| EncoderEvent {
+            direction,
+            steps: 1,
+        };
+        assert_eq!(
+            events(),
+            [
+                event(EncoderDirection::Clockwise),
+                event(EncoderDirection::Clockwise),
+                event(EncoderDirection::CounterClockwise),
+            ]
+        );
+    }
 
-        // Pin A must remain high after the debounce delay
-        // If it is not, the edge was likely caused by noise
-        if tra.is_high() {
-            let direction = if trb.is_low() {
-                EncoderDirection::Clockwise
-            } else {
-                EncoderDirection::CounterClockwise
-            };
-            ENCODER_CHANNEL.send(direction).await;
+    #[test]
+    fn read_encoder_keeps_up_with_edges_microseconds_apart() {
+        let _lock = mock::lock();
+        events();
+        let (a, b) = (VirtualPin::new(), VirtualPin::new());
+        let config = EncoderConfig::new().without_acceleration();
 
-            log::debug!("Encoder: {direction:?}");
+        mock::run(read_encoder(&a, &b, config), async {
+            // Two detents in a row, far quicker than any settle delay
+            for (level_a, level_b) in CLOCKWISE.into_iter().chain(CLOCKWISE) {
+                a.set_high(level_a);
+                b.set_high(level_b);
+                Timer::after_micros(20).await;
+            }
+            Timer::after_millis(2).await;
+        });
+
+        let event = EncoderEvent {
+            direction: EncoderDirection::Clockwise,
+            steps: 1,
+        };
+        assert_eq!(events(), [event, event]);
+    }
+
+    #[test]
+    fn decoder_ignores_transitions_where_both_pins_changed() {
+        let config = EncoderConfig::new().without_acceleration();
+        let mut decoder = QuadratureDecoder::new(config, true, true);
+        let now = Instant::from_secs(1);
+
+        // Skipping a state is not a rotation in either direction
+        assert_eq!(decoder.update(false, false, now), None);
+        assert_eq!(decoder.update(true, true, now), None);
+        assert_eq!(decoder.count, 0);
+        // Nor is a bounce back and forth on one pin
+        for _ in 0..3 {
+            decoder.update(false, true, now);
+            decoder.update(true, true, now);
         }
+        assert_eq!(decoder.count, 0);
+
+        let detent = CLOCKWISE.map(|(a, b)| decoder.update(a, b, now));
+        assert_eq!(detent[..3], [None; 3]);
+        assert!(detent[3].is_some());
+    }
+
+    #[test]
+    fn read_encoder_ignores_bounce() {
+        let _lock = mock::lock();
+        events();
+        let (a, b) = (VirtualPin::new(), VirtualPin::new());
 
-        tra.wait_for_falling_edge().await;
+        mock::run(read_encoder(&a, &b, EncoderConfig::new()), async {
+            // A bounces on the first edge before the detent completes
+            let bouncy = [(false, true), (true, true), (false, true)];
+            turn(
+                &a,
+                &b,
+                bouncy.into_iter().chain(CLOCKWISE[1..].iter().copied()),
+            )
+            .await;
+        });
 
-        Timer::after(Duration::from_millis(20)).await; // Debounce 
+        assert_eq!(
+            events(),
+            [EncoderEvent {
+                direction: EncoderDirection::Clockwise,
+                steps: 1
+            }]
+        );
     }
 }
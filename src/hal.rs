@@ -0,0 +1,360 @@
+//! What the player needs from the hardware.
+//!
+//! The loops driving the audio output, the screen and the inputs are generic
+//! over these traits: the board implements them on top of `esp-hal` and the
+//! OLED driver, the simulator on files, a framebuffer and scripted input.
+
+use core::fmt::Debug;
+use core::sync::atomic::{AtomicBool, Ordering};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embedded_graphics::{pixelcolor::BinaryColor, prelude::DrawTarget};
+
+/// Queue of interleaved 16-bit little-endian stereo frames on its way to the DAC.
+pub trait AudioSink {
+    type Error: Debug;
+
+    /// Bytes the sink holds at most. Whatever is queued has not been heard yet.
+    const CAPACITY: usize;
+
+    /// Bytes that can be pushed right now.
+    fn available(&mut self) -> Result<usize, Self::Error>;
+
+    /// Queues as much of `bytes` as fits, returning how much was taken.
+    fn push(&mut self, bytes: &[u8]) -> Result<usize, Self::Error>;
+}
+
+impl<S: AudioSink> AudioSink for &mut S {
+    type Error = S::Error;
+
+    const CAPACITY: usize = S::CAPACITY;
+
+    fn available(&mut self) -> Result<usize, Self::Error> {
+        S::available(self)
+    }
+
+    fn push(&mut self, bytes: &[u8]) -> Result<usize, Self::Error> {
+        S::push(self, bytes)
+    }
+}
+
+/// Monochrome 128x64 screen with a frame buffer: drawing only changes the
+/// buffer, [`flush`](Self::flush) shows it.
+pub trait Panel: DrawTarget<Color = BinaryColor, Error: Debug> {
+    /// Errors talking to the panel controller.
+    type BusError: Debug;
+
+    /// Sends the buffer to the screen.
+    fn flush(&mut self) -> impl Future<Output = Result<(), Self::BusError>>;
+
+    /// Sets the brightness, 0-255.
+    fn set_brightness(&mut self, level: u8) -> impl Future<Output = Result<(), Self::BusError>>;
+}
+
+/// Digital input pin, such as a button or one of the encoder's contacts.
+pub trait InputSource {
+    fn is_high(&mut self) -> bool;
+
+    fn is_low(&mut self) -> bool {
+        !self.is_high()
+    }
+
+    /// Waits until the level changes in either direction.
+    fn wait_for_any_edge(&mut self) -> impl Future<Output = ()>;
+}
+
+/// Input pin whose level is set in software, pulled up like the board's: the
+/// simulator's buttons and encoder, and the tests'.
+pub struct VirtualPin {
+    high: AtomicBool,
+    edge: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl VirtualPin {
+    pub const fn new() -> Self {
+        Self {
+            high: AtomicBool::new(true),
+            edge: Signal::new(),
+        }
+    }
+
+    pub fn set_high(&self, high: bool) {
+        if self.high.swap(high, Ordering::Relaxed) != high {
+            self.edge.signal(());
+        }
+    }
+}
+
+impl Default for VirtualPin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl InputSource for &VirtualPin {
+    fn is_high(&mut self) -> bool {
+        self.high.load(Ordering::Relaxed)
+    }
+
+    async fn wait_for_any_edge(&mut self) {
+        self.edge.wait().await
+    }
+}
+
+/// Byte stream to a computer, such as the USB serial port.
+pub trait SerialPort {
+    type Error: Debug;
+
+    /// Waits for data and reads what has arrived into `buf`, returning how many bytes.
+    fn read(&mut self, buf: &mut [u8]) -> impl Future<Output = Result<usize, Self::Error>>;
+
+    /// Sends all of `bytes`.
+    fn write_all(&mut self, bytes: &[u8]) -> impl Future<Output = Result<(), Self::Error>>;
+}
+
+/// Stand-ins for the hardware, to drive the generic loops in host tests.
+#[cfg(test)]
+pub mod mock {
+    use core::convert::Infallible;
+    use std::cell::{Cell, RefCell};
+    use std::collections::VecDeque;
+    use std::sync::{Mutex, MutexGuard, PoisonError};
+
+    use embassy_futures::{
+        block_on,
+        select::{Either, select},
+    };
+    use embassy_time::{Duration, Instant, Timer};
+    use embedded_graphics::{pixelcolor::BinaryColor, prelude::*, primitives::Rectangle};
+
+    use super::{AudioSink, Panel, SerialPort};
+
+    const WIDTH: usize = 128;
+    const HEIGHT: usize = 64;
+
+    /// Serialises the tests going through the firmware's statics (player
+    /// commands and state, input channels), which would otherwise run in parallel.
+    pub fn lock() -> MutexGuard<'static, ()> {
+        static LOCK: Mutex<()> = Mutex::new(());
+        LOCK.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Runs `driver`, one of the loops that never return, until `test` completes.
+    pub fn run<T>(driver: impl Future, test: impl Future<Output = T>) -> T {
+        match block_on(select(driver, test)) {
+            Either::First(_) => unreachable!("the loop returned"),
+            Either::Second(output) => output,
+        }
+    }
+
+    /// Waits until `condition` holds, failing the test after two seconds.
+    pub async fn wait_until(what: &str, mut condition: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out waiting for {what}");
+            Timer::after_millis(1).await;
+        }
+    }
+
+    /// Audio sink keeping everything pushed into it.
+    ///
+    /// Its "DAC" either keeps up instantly, so nothing stays queued, or only
+    /// plays when the test calls [`play`](Self::play).
+    #[derive(Default)]
+    pub struct MockSink {
+        pushed: RefCell<Vec<u8>>,
+        played: Cell<usize>,
+        instant: bool,
+    }
+
+    impl MockSink {
+        pub const CAPACITY: usize = 4096;
+
+        /// A sink that only plays when told to.
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// A sink that plays everything as soon as it is pushed.
+        pub fn instant() -> Self {
+            Self {
+                instant: true,
+                ..Self::default()
+            }
+        }
+
+        /// Plays up to `bytes` of what is queued.
+        pub fn play(&self, bytes: usize) {
+            let pushed = self.pushed.borrow().len();
+            self.played.set((self.played.get() + bytes).min(pushed));
+        }
+
+        /// Bytes pushed and not played yet.
+        pub fn queued(&self) -> usize {
+            self.pushed.borrow().len() - self.played.get()
+        }
+
+        /// Everything pushed so far.
+        pub fn pushed(&self) -> Vec<u8> {
+            self.pushed.borrow().clone()
+        }
+    }
+
+    impl AudioSink for &MockSink {
+        type Error = Infallible;
+
+        const CAPACITY: usize = MockSink::CAPACITY;
+
+        fn available(&mut self) -> Result<usize, Self::Error> {
+            Ok(Self::CAPACITY - self.queued())
+        }
+
+        fn push(&mut self, bytes: &[u8]) -> Result<usize, Self::Error> {
+            let len = bytes.len().min(self.available()?);
+            let mut pushed = self.pushed.borrow_mut();
+            pushed.extend_from_slice(&bytes[..len]);
+            if self.instant {
+                self.played.set(pushed.len());
+            }
+            Ok(len)
+        }
+    }
+
+    /// 128x64 panel drawing into a back buffer, shown on [`flush`](Panel::flush).
+    pub struct MockPanel {
+        buffer: RefCell<[[bool; WIDTH]; HEIGHT]>,
+        shown: RefCell<[[bool; WIDTH]; HEIGHT]>,
+        flushes: Cell<usize>,
+        brightness: Cell<Option<u8>>,
+    }
+
+    impl Default for MockPanel {
+        fn default() -> Self {
+            Self {
+                buffer: RefCell::new([[false; WIDTH]; HEIGHT]),
+                shown: RefCell::new([[false; WIDTH]; HEIGHT]),
+                flushes: Cell::new(0),
+                brightness: Cell::new(None),
+            }
+        }
+    }
+
+    impl MockPanel {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Number of frames sent to the screen.
+        pub fn flushes(&self) -> usize {
+            self.flushes.get()
+        }
+
+        /// Brightness last set, if any.
+        pub fn brightness(&self) -> Option<u8> {
+            self.brightness.get()
+        }
+
+        /// Lit pixels of the frame on screen within `area`.
+        pub fn lit(&self, area: Rectangle) -> usize {
+            let shown = self.shown.borrow();
+            area.points()
+                .filter(|point| shown[point.y as usize][point.x as usize])
+                .count()
+        }
+
+        /// The frame on screen.
+        pub fn frame(&self) -> [[bool; WIDTH]; HEIGHT] {
+            *self.shown.borrow()
+        }
+    }
+
+    impl OriginDimensions for &MockPanel {
+        fn size(&self) -> Size {
+            Size::new(WIDTH as u32, HEIGHT as u32)
+        }
+    }
+
+    impl DrawTarget for &MockPanel {
+        type Color = BinaryColor;
+        type Error = Infallible;
+
+        fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+        where
+            I: IntoIterator<Item = Pixel<Self::Color>>,
+        {
+            let mut buffer = self.buffer.borrow_mut();
+            for Pixel(point, color) in pixels {
+                if let (Ok(x @ 0..WIDTH), Ok(y @ 0..HEIGHT)) =
+                    (usize::try_from(point.x), usize::try_from(point.y))
+                {
+                    buffer[y][x] = color.is_on();
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl Panel for &MockPanel {
+        type BusError = Infallible;
+
+        async fn flush(&mut self) -> Result<(), Self::BusError> {
+            *self.shown.borrow_mut() = *self.buffer.borrow();
+            self.flushes.set(self.flushes.get() + 1);
+            Ok(())
+        }
+
+        async fn set_brightness(&mut self, level: u8) -> Result<(), Self::BusError> {
+            self.brightness.set(Some(level));
+            Ok(())
+        }
+    }
+
+    /// Serial port reading what the test queued and keeping what is written.
+    /// Reads wait forever once the input runs out.
+    #[derive(Default)]
+    pub struct MockSerial {
+        input: RefCell<VecDeque<u8>>,
+        output: RefCell<Vec<u8>>,
+    }
+
+    impl MockSerial {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Queues `bytes` to be read.
+        pub fn send(&self, bytes: &[u8]) {
+            self.input.borrow_mut().extend(bytes);
+        }
+
+        /// Takes what has been written so far.
+        pub fn take_output(&self) -> Vec<u8> {
+            self.output.take()
+        }
+
+        /// Takes what has been written so far, as text.
+        pub fn take_text(&self) -> String {
+            String::from_utf8_lossy(&self.take_output()).into_owned()
+        }
+    }
+
+    impl SerialPort for &MockSerial {
+        type Error = Infallible;
+
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            if self.input.borrow().is_empty() {
+                core::future::pending::<()>().await;
+            }
+            let mut input = self.input.borrow_mut();
+            let len = buf.len().min(input.len());
+            for (slot, byte) in buf.iter_mut().zip(input.drain(..len)) {
+                *slot = byte;
+            }
+            Ok(len)
+        }
+
+        async fn write_all(&mut self, bytes: &[u8]) -> Result<(), Self::Error> {
+            self.output.borrow_mut().extend_from_slice(bytes);
+            Ok(())
+        }
+    }
+}
@@ -1,8 +1,19 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 pub mod assets;
 pub mod audio;
+#[cfg(feature = "esp32s3")]
+pub mod board;
 pub mod button;
+pub mod console;
 pub mod display;
+pub mod dsp;
 pub mod encoder;
+pub mod hal;
+pub mod menu;
 pub mod music;
+pub mod persist;
+pub mod player;
+pub mod playlist;
+pub mod storage;
+pub mod streaming;
@@ -3,22 +3,28 @@
 
 use display_interface_i2c::I2CInterface;
 use embassy_executor::Spawner;
+use embassy_time::Duration;
 use esp_hal::{
     clock::CpuClock,
     i2c::master::{Config, I2c},
     i2s::master as i2s,
     time::Rate,
     timer::timg::TimerGroup,
+    usb_serial_jtag::UsbSerialJtag,
 };
 use oled_async::builder::Builder;
 use panic_rtt_target as _; // This defines panic handler
 
-use pds::audio::{
-    DMA_BUFFER_SIZE, IS_PLAYING_SIGNAL, NEXT, PREVIOUS, audio_task, volume_handler_task,
-};
-use pds::button::button_task;
-use pds::display::{OledDisplay, display_task};
-use pds::encoder::encoder_reader_task;
+use pds::audio::{SAMPLE_RATE, button_handler_task, volume_handler_task};
+use pds::board::audio::{DMA_BUFFER_SIZE, audio_task};
+use pds::board::button::button_task;
+use pds::board::console::console_task;
+use pds::board::display::{OledDisplay, display_task};
+use pds::board::encoder::encoder_reader_task;
+use pds::board::flash::{open_settings, persist_task};
+use pds::button::{ButtonId, ButtonTimings};
+use pds::encoder::EncoderConfig;
+use pds::persist;
 
 // This creates a default app-descriptor required by the esp-idf bootloader.
 esp_bootloader_esp_idf::esp_app_desc!();
@@ -36,6 +42,12 @@ async fn main(spawner: Spawner) {
     let timg0 = TimerGroup::new(peripherals.TIMG0);
     esp_rtos::start(timg0.timer0);
 
+    // Restore volume, track and position saved before the last power-off
+    let mut settings = open_settings(peripherals.FLASH);
+    if let Some(store) = settings.as_mut() {
+        persist::restore(store);
+    }
+
     // --- 1. I2C Configuration (OLED Display) ---
     let i2c = I2c::new(peripherals.I2C0, Config::default())
         .unwrap()
@@ -61,9 +73,9 @@ async fn main(spawner: Spawner) {
         peripherals.I2S0,
         dma_channel,
         i2s::Config::new_tdm_philips()
-            .with_sample_rate(Rate::from_hz(11025)) // Optimized for low-res audio
+            .with_sample_rate(Rate::from_hz(SAMPLE_RATE)) // Optimized for low-res audio
             .with_data_format(i2s::DataFormat::Data16Channel16)
-            .with_channels(i2s::Channels::MONO),
+            .with_channels(i2s::Channels::STEREO),
     )
     .unwrap();
 
@@ -77,30 +89,55 @@ async fn main(spawner: Spawner) {
 
     // --- 3. Task Spawning (System Orchestration) ---
     // Buttons for Play/Pause, Previous, and Next
+    // (gestures are decoded per button and mapped to actions by `button_handler_task`:
+    // the encoder button also takes double clicks for the playback mode and long
+    // presses for the menu, Prev/Next seek while held)
     spawner
         .spawn(button_task(
             peripherals.GPIO4.into(),
-            "Encoder",
-            &IS_PLAYING_SIGNAL,
+            ButtonId::Encoder,
+            ButtonTimings::new().with_double_click(Duration::from_millis(300)),
         ))
         .unwrap();
     spawner
-        .spawn(button_task(peripherals.GPIO1.into(), "Prev", &PREVIOUS))
+        .spawn(button_task(
+            peripherals.GPIO1.into(),
+            ButtonId::Prev,
+            ButtonTimings::new()
+                .with_long_press(Duration::from_millis(500))
+                .with_repeat(Duration::from_millis(250)),
+        ))
         .unwrap();
     spawner
-        .spawn(button_task(peripherals.GPIO7.into(), "Next", &NEXT))
+        .spawn(button_task(
+            peripherals.GPIO7.into(),
+            ButtonId::Next,
+            ButtonTimings::new()
+                .with_long_press(Duration::from_millis(500))
+                .with_repeat(Duration::from_millis(250)),
+        ))
         .unwrap();
+    spawner.spawn(button_handler_task()).unwrap();
 
-    // Rotary Encoder for volume control
+    // Rotary Encoder for volume control (one full Gray cycle per detent, fast spins accelerate)
     spawner
         .spawn(encoder_reader_task(
             peripherals.GPIO3.into(),
             peripherals.GPIO2.into(),
+            EncoderConfig::new(),
         ))
         .unwrap();
 
     // Core system tasks
     spawner.spawn(volume_handler_task()).unwrap();
     spawner.spawn(display_task(display)).unwrap();
-    spawner.spawn(audio_task(i2s_tx, tx_buffer)).unwrap();
+    // Command console on the USB port, next to the physical controls
+    let usb_serial = UsbSerialJtag::new(peripherals.USB_DEVICE).into_async();
+    spawner.spawn(console_task(usb_serial)).unwrap();
+    // Hardware RNG seeds the shuffle order
+    let seed = esp_hal::rng::Rng::new().random();
+    spawner.spawn(audio_task(i2s_tx, tx_buffer, seed)).unwrap();
+    if let Some(store) = settings {
+        spawner.spawn(persist_task(store)).unwrap();
+    }
 }
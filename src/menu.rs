@@ -0,0 +1,562 @@
+use core::fmt::{self, Write};
+use core::sync::atomic::AtomicBool;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::Instant;
+use embedded_graphics::{
+    mono_font::{MonoTextStyle, ascii::FONT_6X10},
+    pixelcolor::BinaryColor,
+    prelude::*,
+    primitives::{Line as Rule, PrimitiveStyle, Rectangle},
+    text::{Alignment, Text},
+};
+
+use crate::audio::SAMPLE_RATE;
+use crate::display::{Orientation, draw_progress_bar};
+use crate::dsp::{DEFAULT_EQ, EQ_BANDS, EqSettings, FilterKind, MAX_GAIN_DB};
+use crate::music::Musics;
+use crate::player::PlayerState;
+use crate::playlist::PlaybackMode;
+
+/// Navigation events for the menu, sent by the input tasks while it is open.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MenuInput {
+    /// Opens the menu, or closes it from any page (long press on the encoder button).
+    Toggle,
+    /// Moves the cursor down, or raises the value being edited (encoder clockwise).
+    Increment,
+    /// Moves the cursor up, or lowers the value being edited (encoder counter-clockwise).
+    Decrement,
+    /// Opens a page, runs an item, or starts/stops editing a value.
+    Select,
+    /// Returns to the main page, or closes the menu from there.
+    Back,
+}
+
+/// Menu navigation events, consumed by the display task.
+pub static MENU_INPUT: Channel<CriticalSectionRawMutex, MenuInput, 8> = Channel::new();
+/// Set while the menu is on screen, so input tasks route events to it.
+pub static MENU_OPEN: AtomicBool = AtomicBool::new(false);
+
+/// Change requested from the menu, applied by the display task.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MenuAction {
+    PlayTrack(Musics),
+    SetEq(EqSettings),
+    SetMode(PlaybackMode),
+    SetBrightness(u8),
+}
+
+/// Screens reachable from the menu.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Page {
+    Main,
+    Tracks,
+    Equalizer,
+    Mode,
+    Brightness,
+    About,
+}
+
+impl Page {
+    fn title(&self) -> &'static str {
+        match self {
+            Page::Main => "Menu",
+            Page::Tracks => "Tracks",
+            Page::Equalizer => "Equalizer",
+            Page::Mode => "Playback mode",
+            Page::Brightness => "Brightness",
+            Page::About => "About",
+        }
+    }
+}
+
+/// Entries of the main page, in display order.
+const MAIN_PAGES: [Page; 5] = [
+    Page::Tracks,
+    Page::Equalizer,
+    Page::Mode,
+    Page::Brightness,
+    Page::About,
+];
+
+/// Equalizer gain change per encoder step, in dB.
+const EQ_STEP_DB: f32 = 1.0;
+/// Brightness change per encoder step.
+const BRIGHTNESS_STEP: u8 = 16;
+
+/// Height of one list row, in pixels.
+const ROW_HEIGHT: i32 = 10;
+/// Top of the first row, below the page title.
+const FIRST_ROW_Y: i32 = 12;
+/// Rows that fit on the 64-px panel under the title.
+const VISIBLE_ROWS: usize = 5;
+/// Longest row in characters; the panel fits 21 characters of `FONT_6X10`.
+const LINE_CAPACITY: usize = 21;
+
+/// Navigable settings menu drawn over the player screen.
+///
+/// The menu only holds navigation state and the values it edits; changes
+/// leave it as [`MenuAction`]s for the caller to apply.
+#[derive(Debug, Clone)]
+pub struct Menu {
+    open: bool,
+    page: Page,
+    cursor: usize,
+    /// Main page entry to return to when backing out of a page.
+    main_cursor: usize,
+    /// Set while the encoder adjusts the selected value instead of moving.
+    editing: bool,
+    eq: EqSettings,
+    brightness: u8,
+}
+
+impl Menu {
+    pub fn new(brightness: u8) -> Self {
+        Self {
+            open: false,
+            page: Page::Main,
+            cursor: 0,
+            main_cursor: 0,
+            editing: false,
+            eq: DEFAULT_EQ,
+            brightness,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn page(&self) -> Page {
+        self.page
+    }
+
+    /// Applies a navigation event. The player `state` positions the cursor
+    /// when the track list or mode page is opened, and provides the equalizer
+    /// settings to edit, which may have been changed from elsewhere.
+    pub fn handle(&mut self, input: MenuInput, state: &PlayerState) -> Option<MenuAction> {
+        if input == MenuInput::Toggle {
+            self.open = !self.open;
+            self.show(Page::Main, 0);
+            return None;
+        }
+        if !self.open {
+            return None;
+        }
+
+        match input {
+            MenuInput::Toggle => None,
+            MenuInput::Increment => self.step(1),
+            MenuInput::Decrement => self.step(-1),
+            MenuInput::Select => self.select(state),
+            MenuInput::Back => {
+                if self.editing {
+                    self.editing = false;
+                } else if self.page == Page::Main {
+                    self.open = false;
+                } else {
+                    self.show(Page::Main, self.main_cursor);
+                }
+                None
+            }
+        }
+    }
+
+    fn show(&mut self, page: Page, cursor: usize) {
+        self.page = page;
+        self.cursor = cursor;
+        self.editing = false;
+    }
+
+    /// Number of selectable rows on the current page.
+    fn rows(&self) -> usize {
+        match self.page {
+            Page::Main => MAIN_PAGES.len(),
+            Page::Tracks => Musics::COUNT,
+            // One row per band, plus "Reset"
+            Page::Equalizer => EQ_BANDS + 1,
+            Page::Mode => PlaybackMode::COUNT,
+            Page::Brightness | Page::About => 0,
+        }
+    }
+
+    fn step(&mut self, delta: i32) -> Option<MenuAction> {
+        if self.page == Page::Brightness {
+            self.brightness = if delta > 0 {
+                self.brightness.saturating_add(BRIGHTNESS_STEP)
+            } else {
+                self.brightness.saturating_sub(BRIGHTNESS_STEP)
+            };
+            return Some(MenuAction::SetBrightness(self.brightness));
+        }
+
+        if self.editing {
+            let band = &mut self.eq[self.cursor];
+            band.gain_db =
+                (band.gain_db + delta as f32 * EQ_STEP_DB).clamp(-MAX_GAIN_DB, MAX_GAIN_DB);
+            return Some(MenuAction::SetEq(self.eq));
+        }
+
+        let last = self.rows().saturating_sub(1);
+        self.cursor = (self.cursor as i32 + delta).clamp(0, last as i32) as usize;
+        None
+    }
+
+    fn select(&mut self, state: &PlayerState) -> Option<MenuAction> {
+        match self.page {
+            Page::Main => {
+                self.main_cursor = self.cursor;
+                let page = MAIN_PAGES[self.cursor];
+                let cursor = match page {
+                    Page::Tracks => state.track.to_index() as usize,
+                    Page::Mode => state.mode.to_index() as usize,
+                    Page::Equalizer => {
+                        self.eq = state.eq;
+                        0
+                    }
+                    _ => 0,
+                };
+                self.show(page, cursor);
+                None
+            }
+            Page::Tracks => {
+                self.open = false;
+                Some(MenuAction::PlayTrack(Musics::from_index(
+                    &(self.cursor as u8),
+                )))
+            }
+            Page::Equalizer if self.cursor < EQ_BANDS => {
+                self.editing = !self.editing;
+                None
+            }
+            Page::Equalizer => {
+                self.eq = DEFAULT_EQ;
+                Some(MenuAction::SetEq(self.eq))
+            }
+            Page::Mode => Some(MenuAction::SetMode(PlaybackMode::from_index(
+                self.cursor as u8,
+            ))),
+            Page::Brightness | Page::About => None,
+        }
+    }
+
+    /// Renders the current page over the whole panel.
+    pub fn draw<D>(
+        &self,
+        target: &mut D,
+        current: Musics,
+        mode: PlaybackMode,
+    ) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = BinaryColor>,
+    {
+        let style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+        Text::new(self.page.title(), Point::new(0, 8), style).draw(target)?;
+        Rule::new(Point::new(0, 10), Point::new(127, 10))
+            .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+            .draw(target)?;
+
+        match self.page {
+            Page::Brightness => {
+                draw_progress_bar(
+                    target,
+                    (self.brightness as u32 * 100 / u8::MAX as u32) as u8,
+                    Point::new(14, 26),
+                    Size::new(100, 10),
+                    Orientation::Horizontal,
+                )?;
+                let mut line = Line::new();
+                write!(line, "{}%", self.brightness as u32 * 100 / u8::MAX as u32).ok();
+                Text::with_alignment(line.as_str(), Point::new(64, 52), style, Alignment::Center)
+                    .draw(target)?;
+            }
+            Page::About => {
+                for (i, line) in about_lines().iter().enumerate() {
+                    draw_row(target, i, line.as_str(), false)?;
+                }
+            }
+            _ => {
+                // Scroll just enough to keep the cursor on screen
+                let first = self.cursor.saturating_sub(VISIBLE_ROWS - 1);
+                for index in first..self.rows().min(first + VISIBLE_ROWS) {
+                    let line = self.row(index, current, mode);
+                    draw_row(target, index - first, line.as_str(), index == self.cursor)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Text of one row of a list page.
+    fn row(&self, index: usize, current: Musics, mode: PlaybackMode) -> Line {
+        let mut line = Line::new();
+        match self.page {
+            Page::Main => {
+                line.push_str(MAIN_PAGES[index].title());
+            }
+            Page::Tracks => {
+                let track = Musics::from_index(&(index as u8));
+                let marker = if track == current { '>' } else { ' ' };
+                write!(line, "{marker}{}", track.title()).ok();
+            }
+            Page::Equalizer if index < EQ_BANDS => {
+                let band = &self.eq[index];
+                let label = match band.kind {
+                    FilterKind::LowShelf => "Bass",
+                    FilterKind::Peaking => "Mid",
+                    FilterKind::HighShelf => "Treble",
+                };
+                let gain = libm::roundf(band.gain_db) as i32;
+                if self.editing && index == self.cursor {
+                    write!(line, "{label:<8}[{gain:+3} dB]").ok();
+                } else {
+                    write!(line, "{label:<8} {gain:+3} dB").ok();
+                }
+            }
+            Page::Equalizer => {
+                line.push_str("Reset");
+            }
+            Page::Mode => {
+                let option = PlaybackMode::from_index(index as u8);
+                let marker = if option == mode { '>' } else { ' ' };
+                write!(line, "{marker}{}", option.name()).ok();
+            }
+            Page::Brightness | Page::About => {}
+        }
+        line
+    }
+}
+
+/// Diagnostics shown on the about page.
+fn about_lines() -> [Line; 4] {
+    let mut lines = [Line::new(), Line::new(), Line::new(), Line::new()];
+    write!(lines[0], "pds v{}", env!("CARGO_PKG_VERSION")).ok();
+    write!(lines[1], "Tracks: {}", Musics::COUNT).ok();
+    write!(lines[2], "Output: {SAMPLE_RATE} Hz").ok();
+    let uptime = Instant::now().as_secs();
+    write!(
+        lines[3],
+        "Uptime: {}:{:02}:{:02}",
+        uptime / 3600,
+        uptime / 60 % 60,
+        uptime % 60
+    )
+    .ok();
+    lines
+}
+
+/// Draws a list row, inverted when selected.
+fn draw_row<D>(target: &mut D, slot: usize, text: &str, selected: bool) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let top = FIRST_ROW_Y + slot as i32 * ROW_HEIGHT;
+    let color = if selected {
+        Rectangle::new(Point::new(0, top), Size::new(128, ROW_HEIGHT as u32))
+            .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+            .draw(target)?;
+        BinaryColor::Off
+    } else {
+        BinaryColor::On
+    };
+    let style = MonoTextStyle::new(&FONT_6X10, color);
+    Text::new(text, Point::new(1, top + 8), style).draw(target)?;
+    Ok(())
+}
+
+/// Fixed-capacity text buffer for one row, since there is no allocator.
+/// Text past the end of the row is dropped.
+#[derive(Debug, Clone, Copy)]
+struct Line {
+    buf: [u8; LINE_CAPACITY],
+    len: usize,
+}
+
+impl Line {
+    const fn new() -> Self {
+        Self {
+            buf: [0; LINE_CAPACITY],
+            len: 0,
+        }
+    }
+
+    fn push_str(&mut self, s: &str) {
+        for c in s.chars() {
+            let mut utf8 = [0; 4];
+            let bytes = c.encode_utf8(&mut utf8).as_bytes();
+            if self.len + bytes.len() > LINE_CAPACITY {
+                break;
+            }
+            self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+            self.len += bytes.len();
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        // Only whole characters are ever copied in
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+}
+
+impl Write for Line {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push_str(s);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Opens a menu and walks into `page` from the main page.
+    fn open_at(page: Page, state: &PlayerState) -> Menu {
+        let mut menu = Menu::new(128);
+        menu.handle(MenuInput::Toggle, state);
+        let index = MAIN_PAGES.iter().position(|&p| p == page).unwrap();
+        for _ in 0..index {
+            menu.handle(MenuInput::Increment, state);
+        }
+        menu.handle(MenuInput::Select, state);
+        assert_eq!(menu.page(), page);
+        menu
+    }
+
+    #[test]
+    fn input_is_ignored_while_closed() {
+        let state = PlayerState::default();
+        let mut menu = Menu::new(128);
+        for input in [MenuInput::Increment, MenuInput::Select, MenuInput::Back] {
+            assert_eq!(menu.handle(input, &state), None);
+        }
+        assert!(!menu.is_open());
+
+        menu.handle(MenuInput::Toggle, &state);
+        assert!(menu.is_open());
+        assert_eq!(menu.page(), Page::Main);
+        menu.handle(MenuInput::Toggle, &state);
+        assert!(!menu.is_open());
+    }
+
+    #[test]
+    fn the_cursor_stops_at_both_ends() {
+        let state = PlayerState::default();
+        let mut menu = Menu::new(128);
+        menu.handle(MenuInput::Toggle, &state);
+
+        // Up from the first entry stays there rather than wrapping to the last
+        menu.handle(MenuInput::Decrement, &state);
+        menu.handle(MenuInput::Select, &state);
+        assert_eq!(menu.page(), Page::Tracks);
+        menu.handle(MenuInput::Back, &state);
+
+        // Down past the last entry stays on it
+        for _ in 0..MAIN_PAGES.len() + 2 {
+            menu.handle(MenuInput::Increment, &state);
+        }
+        menu.handle(MenuInput::Select, &state);
+        assert_eq!(menu.page(), Page::About);
+
+        // Back returns to the entry the page was opened from, then closes
+        menu.handle(MenuInput::Back, &state);
+        menu.handle(MenuInput::Select, &state);
+        assert_eq!(menu.page(), Page::About);
+        menu.handle(MenuInput::Back, &state);
+        menu.handle(MenuInput::Back, &state);
+        assert!(!menu.is_open());
+    }
+
+    #[test]
+    fn picking_a_track_plays_it_and_closes() {
+        let state = PlayerState {
+            track: Musics::from_index(&1),
+            ..PlayerState::default()
+        };
+        let mut menu = open_at(Page::Tracks, &state);
+
+        // The cursor starts on the current track
+        menu.handle(MenuInput::Increment, &state);
+        let next = 2.min(Musics::COUNT - 1) as u8;
+        assert_eq!(
+            menu.handle(MenuInput::Select, &state),
+            Some(MenuAction::PlayTrack(Musics::from_index(&next)))
+        );
+        assert!(!menu.is_open());
+    }
+
+    #[test]
+    fn mode_and_brightness_pages_emit_their_setting() {
+        let state = PlayerState::default();
+        let mut menu = open_at(Page::Mode, &state);
+        menu.handle(MenuInput::Increment, &state);
+        let mode = PlaybackMode::from_index(state.mode.to_index() + 1);
+        assert_eq!(
+            menu.handle(MenuInput::Select, &state),
+            Some(MenuAction::SetMode(mode))
+        );
+        assert!(menu.is_open());
+
+        let mut menu = open_at(Page::Brightness, &state);
+        assert_eq!(
+            menu.handle(MenuInput::Increment, &state),
+            Some(MenuAction::SetBrightness(128 + BRIGHTNESS_STEP))
+        );
+        for _ in 0..20 {
+            menu.handle(MenuInput::Decrement, &state);
+        }
+        assert_eq!(
+            menu.handle(MenuInput::Decrement, &state),
+            Some(MenuAction::SetBrightness(0))
+        );
+        assert_eq!(menu.handle(MenuInput::Select, &state), None);
+    }
+
+    #[test]
+    fn editing_a_band_changes_its_gain_within_range() {
+        let mut state = PlayerState::default();
+        state.eq[0].gain_db = MAX_GAIN_DB - 1.0;
+        let mut menu = open_at(Page::Equalizer, &state);
+
+        // Moving the cursor does not touch the settings until a band is selected
+        assert_eq!(menu.handle(MenuInput::Increment, &state), None);
+        menu.handle(MenuInput::Decrement, &state);
+        menu.handle(MenuInput::Select, &state);
+
+        let mut eq = state.eq;
+        eq[0].gain_db = MAX_GAIN_DB;
+        for _ in 0..3 {
+            assert_eq!(
+                menu.handle(MenuInput::Increment, &state),
+                Some(MenuAction::SetEq(eq))
+            );
+        }
+        for _ in 0..2 * MAX_GAIN_DB as usize + 5 {
+            menu.handle(MenuInput::Decrement, &state);
+        }
+        eq[0].gain_db = -MAX_GAIN_DB;
+        assert_eq!(
+            menu.handle(MenuInput::Decrement, &state),
+            Some(MenuAction::SetEq(eq))
+        );
+
+        // Back leaves the editor first, then the page
+        menu.handle(MenuInput::Back, &state);
+        assert_eq!(menu.handle(MenuInput::Increment, &state), None);
+        menu.handle(MenuInput::Back, &state);
+        assert_eq!(menu.page(), Page::Main);
+    }
+
+    #[test]
+    fn reset_restores_the_flat_equalizer() {
+        let mut state = PlayerState::default();
+        state.eq[1].gain_db = 6.0;
+        let mut menu = open_at(Page::Equalizer, &state);
+        for _ in 0..EQ_BANDS {
+            menu.handle(MenuInput::Increment, &state);
+        }
+        assert_eq!(
+            menu.handle(MenuInput::Select, &state),
+            Some(MenuAction::SetEq(DEFAULT_EQ))
+        );
+    }
+}
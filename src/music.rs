@@ -1,86 +1,170 @@
-use embedded_graphics::prelude::Point;
+use embedded_graphics::{
+    mono_font::{
+        MonoFont,
+        ascii::{FONT_4X6, FONT_7X13_BOLD},
+    },
+    prelude::Point,
+};
 
-use crate::assets;
+use crate::assets::TRACKS;
 
-/// Enum representing available tracks in the system.
-#[derive(Debug, PartialEq, Copy, Clone)]
-pub enum Musics {
-    LikeStone,
-    Tetris,
-    MarioWorld,
-    TopGear,
+/// Horizontal space available for the title, left of the volume gauge.
+pub const TITLE_AREA_WIDTH: i32 = 112;
+/// Baseline of the title text.
+const TITLE_Y: i32 = 12;
+/// Font of the track title.
+pub const TITLE_FONT: MonoFont<'static> = FONT_7X13_BOLD;
+/// Baseline of the artist line, just above the spectrum.
+const ARTIST_Y: i32 = 20;
+/// Font of the artist line.
+pub const ARTIST_FONT: MonoFont<'static> = FONT_4X6;
+
+/// An embedded track, as listed in the table generated by `build.rs`.
+#[derive(Debug)]
+pub struct Track {
+    /// Display title (manifest entry, `INAM` tag or file name).
+    pub title: &'static str,
+    /// Artist from the manifest or the `IART` tag, if any.
+    pub artist: Option<&'static str>,
+    /// Name of the source file in `assets/music/`, which stays the same
+    /// when other tracks are added or removed.
+    pub file: &'static str,
+    /// WAV file stored in Flash.
+    pub bytes: &'static [u8],
 }
 
+/// Handle to one of the tracks available in the system.
+///
+/// The list of tracks is generated at build time from `assets/music/`.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Musics(u8);
+
 impl Musics {
-    /// Internal registry of all tracks to facilitate indexing and navigation.
-    const ALL: [Musics; 4] = [
-        Musics::Tetris,
-        Musics::LikeStone,
-        Musics::MarioWorld,
-        Musics::TopGear,
-    ];
+    /// Number of embedded tracks.
+    pub const COUNT: usize = TRACKS.len();
+
+    fn track(&self) -> &'static Track {
+        &TRACKS[self.0 as usize]
+    }
 
     /// Returns the next track in the list, wrapping back to the start if at the end.
     pub fn next(&self) -> Self {
-        let index = Self::ALL.iter().position(|x| x == self).unwrap();
-        Self::ALL[(index + 1) % Self::ALL.len()]
+        Self(((self.0 as usize + 1) % Self::COUNT) as u8)
     }
 
     /// Returns the previous track, wrapping to the end if at the start.
     pub fn prev(&self) -> Self {
-        let index = Self::ALL.iter().position(|x| x == self).unwrap();
-        Self::ALL[(index + Self::ALL.len() - 1) % Self::ALL.len()]
+        Self(((self.0 as usize + Self::COUNT - 1) % Self::COUNT) as u8)
     }
 
-    /// Returns a static reference to the raw audio bytes stored in Flash.
+    /// Returns a static reference to the audio file (WAV or headerless raw) stored in Flash.
     pub fn bytes(&self) -> &'static [u8] {
-        match self {
-            Musics::LikeStone => assets::LIKE_A_STONE_MUSIC,
-            Musics::Tetris => assets::TETRIS_MUSIC,
-            Musics::MarioWorld => assets::MARIO_WORLD,
-            Musics::TopGear => assets::TOP_GEAR,
-        }
+        self.track().bytes
     }
 
     /// Returns the displayable string title for the track.
     pub fn title(&self) -> &'static str {
-        match self {
-            Musics::LikeStone => "Like a Stone",
-            Musics::Tetris => "Tetris",
-            Musics::MarioWorld => "Mario World",
-            Musics::TopGear => "Top Gear",
-        }
+        self.track().title
     }
 
-    /// Provides the UI coordinates (X, Y) to render the title on the OLED.
-    /// Values are manually tuned for centering based on title length.
+    /// Returns the artist of the track, if known.
+    pub fn artist(&self) -> Option<&'static str> {
+        self.track().artist
+    }
+
+    /// Returns the name of the file the track was built from.
+    pub fn file(&self) -> &'static str {
+        self.track().file
+    }
+
+    /// Width of the title in pixels when drawn with [`TITLE_FONT`].
+    pub fn title_width(&self) -> i32 {
+        text_width(self.title(), &TITLE_FONT)
+    }
+
+    /// Returns `true` when the title does not fit the title area and has to scroll.
+    pub fn title_overflows(&self) -> bool {
+        self.title_width() > TITLE_AREA_WIDTH
+    }
+
+    /// Provides the UI coordinates (X, Y) to render the title on the OLED,
+    /// horizontally centered based on the title length. Titles too wide to
+    /// center start at the left edge.
     pub fn title_pos(&self) -> Point {
-        match self {
-            Musics::LikeStone => Point::new(17, 15),
-            Musics::Tetris => Point::new(35, 15),
-            Musics::MarioWorld => Point::new(19, 15),
-            Musics::TopGear => Point::new(28, 15),
-        }
+        Point::new(centered_x(self.title_width()), TITLE_Y)
     }
 
-    /// Factory method to retrieve a track variant from a numeric index.
+    /// Coordinates of the artist line, centered under the title like
+    /// [`title_pos`](Self::title_pos).
+    pub fn artist_pos(&self) -> Option<Point> {
+        let width = text_width(self.artist()?, &ARTIST_FONT);
+        Some(Point::new(centered_x(width), ARTIST_Y))
+    }
+
+    /// Factory method to retrieve a track from a numeric index.
     pub fn from_index(idx: &u8) -> Self {
-        match idx {
-            0 => Musics::Tetris,
-            1 => Musics::LikeStone,
-            2 => Musics::MarioWorld,
-            3 => Musics::TopGear,
-            _ => Musics::Tetris, // Default fallback
+        if (*idx as usize) < Self::COUNT {
+            Self(*idx)
+        } else {
+            Self(0) // Default fallback
         }
     }
 
-    /// Converts the current track variant back into a numeric index.
+    /// Converts the current track back into a numeric index.
     pub fn to_index(&self) -> u8 {
-        match self {
-            Musics::Tetris => 0,
-            Musics::LikeStone => 1,
-            Musics::MarioWorld => 2,
-            Musics::TopGear => 3,
+        self.0
+    }
+}
+
+/// Left edge of text `width` pixels wide centered in the title area, or the
+/// area's left edge when it does not fit.
+fn centered_x(width: i32) -> i32 {
+    ((TITLE_AREA_WIDTH - width) / 2).max(0)
+}
+
+/// Width of `text` in pixels for a monospaced font.
+fn text_width(text: &str, font: &MonoFont) -> i32 {
+    let advance = font.character_size.width + font.character_spacing;
+    text.chars().count() as i32 * advance as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracks() -> impl Iterator<Item = Musics> {
+        (0..Musics::COUNT as u8).map(|index| Musics::from_index(&index))
+    }
+
+    #[test]
+    fn short_titles_are_centered() {
+        let advance = TITLE_FONT.character_size.width as i32;
+        assert_eq!(text_width("Intro", &TITLE_FONT), 5 * advance);
+        assert_eq!(
+            centered_x(5 * advance),
+            (TITLE_AREA_WIDTH - 5 * advance) / 2
+        );
+        assert_eq!(centered_x(0), TITLE_AREA_WIDTH / 2);
+        assert_eq!(centered_x(TITLE_AREA_WIDTH), 0);
+    }
+
+    #[test]
+    fn long_titles_start_at_the_left_edge() {
+        assert_eq!(centered_x(TITLE_AREA_WIDTH + 1), 0);
+        assert_eq!(centered_x(3 * TITLE_AREA_WIDTH), 0);
+    }
+
+    #[test]
+    fn every_title_is_placed_in_the_title_area() {
+        for track in tracks() {
+            let pos = track.title_pos();
+            assert_eq!(pos.y, TITLE_Y);
+            if track.title_overflows() {
+                assert_eq!(pos.x, 0, "{}", track.title());
+            } else {
+                assert_eq!(pos.x, (TITLE_AREA_WIDTH - track.title_width()) / 2);
+                assert!(pos.x + track.title_width() <= TITLE_AREA_WIDTH);
+            }
         }
     }
 }
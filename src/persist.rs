@@ -0,0 +1,302 @@
+use embassy_time::{Duration, Instant, Timer};
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::dsp::PcmStream;
+use crate::music::Musics;
+use crate::player::{PLAYER_STATE, PlayerState};
+use crate::storage::{KvStore, StorageError};
+
+/// Keys of the values kept in the settings partition.
+mod key {
+    pub const VOLUME: u8 = 0;
+    /// [`track_key`](super::track_key) of the track, not its index in the list.
+    pub const TRACK: u8 = 1;
+    pub const POSITION: u8 = 2;
+}
+
+/// Key slots reserved in the store, leaving room for future settings.
+const KEYS: usize = 8;
+
+/// Store holding the player state across power cycles, on flash `F`.
+pub type SettingsStore<F> = KvStore<F, KEYS>;
+
+/// How often [`save_changes`] looks at the player state.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Time the state must stay unchanged before it is written.
+const SAVE_DELAY: Duration = Duration::from_secs(2);
+/// Granularity of the position saved while playing, so the steadily moving
+/// position costs one write every few seconds instead of one per poll.
+const POSITION_STEP_MS: u32 = 10_000;
+
+/// Player state worth restoring after a power cycle.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Snapshot {
+    volume: u8,
+    track: Musics,
+    position_ms: u32,
+}
+
+impl Snapshot {
+    fn capture() -> Self {
+        Self::of(&PlayerState::current())
+    }
+
+    fn of(state: &PlayerState) -> Self {
+        let position_ms = state.position_ms;
+        Self {
+            volume: state.volume,
+            track: state.track,
+            // Exact position once paused, coarse steps while it keeps moving
+            position_ms: if state.playing {
+                position_ms - position_ms % POSITION_STEP_MS
+            } else {
+                position_ms
+            },
+        }
+    }
+
+    fn save<F: NorFlash>(
+        &self,
+        store: &mut SettingsStore<F>,
+    ) -> Result<(), StorageError<F::Error>> {
+        store.set(key::VOLUME, &[self.volume])?;
+        store.set(key::TRACK, &track_key(self.track).to_le_bytes())?;
+        store.set(key::POSITION, &self.position_ms.to_le_bytes())
+    }
+}
+
+/// Debounces the snapshots taken by [`save_changes`].
+#[derive(Debug)]
+struct Debounce {
+    saved: Snapshot,
+    latest: Snapshot,
+    changed_at: Instant,
+}
+
+impl Debounce {
+    fn new(saved: Snapshot, now: Instant) -> Self {
+        Self {
+            saved,
+            latest: saved,
+            changed_at: now,
+        }
+    }
+
+    /// Takes the snapshot of one poll. Returns the snapshot to write once it
+    /// differs from the saved one and has not changed for [`SAVE_DELAY`].
+    fn update(&mut self, current: Snapshot, now: Instant) -> Option<Snapshot> {
+        if current != self.latest {
+            self.latest = current;
+            self.changed_at = now;
+        }
+        if self.latest == self.saved || now.duration_since(self.changed_at) < SAVE_DELAY {
+            return None;
+        }
+        // Not retried on failure, the next change tries again
+        self.saved = self.latest;
+        Some(self.latest)
+    }
+}
+
+/// Identifies a track by its file name (FNV-1a hash), so a saved track still
+/// resolves after tracks are added or removed, and is forgotten once it is gone.
+fn track_key(music: Musics) -> u32 {
+    music.file().bytes().fold(0x811C_9DC5, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// Publishes the saved volume, track and position as the player state.
+/// Must run before `audio_task` starts, which resumes from it when opening the first track.
+///
+/// A saved track that is no longer embedded falls back to the first one,
+/// from the start.
+pub fn restore<F: NorFlash>(store: &mut SettingsStore<F>) {
+    let mut state = PlayerState::default();
+    let mut byte = [0; 1];
+    if let Ok(Some(_)) = store.get(key::VOLUME, &mut byte) {
+        state.volume = byte[0].min(100);
+    }
+    let mut word = [0; 4];
+    let track = match store.get(key::TRACK, &mut word) {
+        Ok(Some(4)) => {
+            let saved = u32::from_le_bytes(word);
+            (0..Musics::COUNT as u8)
+                .map(|index| Musics::from_index(&index))
+                .find(|&music| track_key(music) == saved)
+        }
+        _ => None,
+    };
+    if let Some(track) = track {
+        state.track = track;
+        if let Ok(Some(4)) = store.get(key::POSITION, &mut word) {
+            // Past the end if the track was shortened since
+            let duration_ms = PcmStream::from_asset(track.bytes()).map_or(0, |s| s.duration_ms());
+            state.position_ms = u32::from_le_bytes(word).min(duration_ms);
+        }
+    }
+
+    log::info!(
+        "Restored volume {}, track {}, position {}ms",
+        state.volume,
+        state.track.to_index(),
+        state.position_ms
+    );
+    PLAYER_STATE.sender().send(state);
+}
+
+/// Saves the volume, track and position whenever they change. Never returns.
+///
+/// Changes are debounced: a value is written once the state has stayed the
+/// same for `SAVE_DELAY`, so spinning the volume knob or skipping through
+/// tracks ends in a single write. Unchanged values are not rewritten.
+pub async fn save_changes<F: NorFlash>(mut store: SettingsStore<F>) -> ! {
+    let mut debounce = Debounce::new(Snapshot::capture(), Instant::now());
+
+    loop {
+        Timer::after(POLL_INTERVAL).await;
+
+        // The position of a stream is not one to resume the track from
+        if PlayerState::current().streaming {
+            continue;
+        }
+        if let Some(snapshot) = debounce.update(Snapshot::capture(), Instant::now()) {
+            match snapshot.save(&mut store) {
+                Ok(()) => log::debug!("Saved {snapshot:?}"),
+                Err(err) => log::error!("Failed to save settings: {err:?}"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::mock;
+    use crate::storage::RamFlash;
+
+    type Store = SettingsStore<RamFlash<1024, 2>>;
+
+    fn snapshot(volume: u8, track: u8, position_ms: u32) -> Snapshot {
+        Snapshot {
+            volume,
+            track: Musics::from_index(&track),
+            position_ms,
+        }
+    }
+
+    fn duration_ms(track: Musics) -> u32 {
+        PcmStream::from_asset(track.bytes()).unwrap().duration_ms()
+    }
+
+    /// Restores from `store` and returns the published state.
+    fn restored(store: &mut Store) -> PlayerState {
+        restore(store);
+        PlayerState::current()
+    }
+
+    #[test]
+    fn snapshots_round_the_position_while_playing() {
+        let mut state = PlayerState {
+            position_ms: 12_345,
+            playing: true,
+            ..PlayerState::default()
+        };
+        assert_eq!(Snapshot::of(&state).position_ms, 10_000);
+        state.playing = false;
+        assert_eq!(Snapshot::of(&state).position_ms, 12_345);
+    }
+
+    #[test]
+    fn writes_wait_for_the_state_to_settle() {
+        let start = Instant::from_secs(100);
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut debounce = Debounce::new(snapshot(50, 0, 0), start);
+
+        assert_eq!(debounce.update(snapshot(50, 0, 0), at(5_000)), None);
+        // Turning the knob keeps postponing the write
+        assert_eq!(debounce.update(snapshot(55, 0, 0), at(5_500)), None);
+        assert_eq!(debounce.update(snapshot(60, 0, 0), at(7_000)), None);
+        assert_eq!(debounce.update(snapshot(60, 0, 0), at(8_500)), None);
+        assert_eq!(
+            debounce.update(snapshot(60, 0, 0), at(9_000)),
+            Some(snapshot(60, 0, 0))
+        );
+        assert_eq!(debounce.update(snapshot(60, 0, 0), at(20_000)), None);
+
+        // A change undone before the delay is never written
+        assert_eq!(debounce.update(snapshot(60, 1, 0), at(20_500)), None);
+        assert_eq!(debounce.update(snapshot(60, 0, 0), at(21_000)), None);
+        assert_eq!(debounce.update(snapshot(60, 0, 0), at(30_000)), None);
+    }
+
+    #[test]
+    fn saved_state_survives_a_remount() {
+        let _lock = mock::lock();
+        let mut store = Store::mount(RamFlash::new()).unwrap();
+        assert_eq!(restored(&mut store), PlayerState::default());
+
+        let last = (Musics::COUNT - 1) as u8;
+        let saved = snapshot(35, last, 4_000);
+        saved.save(&mut store).unwrap();
+        let mut store = Store::mount(store.into_inner()).unwrap();
+
+        let state = restored(&mut store);
+        assert_eq!(Snapshot::of(&state), saved);
+        assert!(!state.playing);
+    }
+
+    #[test]
+    fn out_of_range_values_are_clamped() {
+        let _lock = mock::lock();
+        let mut store = Store::mount(RamFlash::new()).unwrap();
+        let track = Musics::from_index(&0);
+        store.set(key::VOLUME, &[250]).unwrap();
+        store
+            .set(key::TRACK, &track_key(track).to_le_bytes())
+            .unwrap();
+        store.set(key::POSITION, &u32::MAX.to_le_bytes()).unwrap();
+
+        let state = restored(&mut store);
+        assert_eq!(state.volume, 100);
+        assert_eq!(state.track, track);
+        assert_eq!(state.position_ms, duration_ms(track));
+    }
+
+    #[test]
+    fn a_track_no_longer_embedded_restarts_the_first() {
+        let _lock = mock::lock();
+        let mut store = Store::mount(RamFlash::new()).unwrap();
+        snapshot(35, (Musics::COUNT - 1) as u8, 4_000)
+            .save(&mut store)
+            .unwrap();
+        store
+            .set(key::TRACK, &0xDEAD_BEEFu32.to_le_bytes())
+            .unwrap();
+
+        let state = restored(&mut store);
+        assert_eq!(state.volume, 35);
+        assert_eq!(
+            (state.track, state.position_ms),
+            (Musics::from_index(&0), 0)
+        );
+
+        // Nor is the index saved by earlier versions trusted
+        store.set(key::TRACK, &[1]).unwrap();
+        let state = restored(&mut store);
+        assert_eq!(
+            (state.track, state.position_ms),
+            (Musics::from_index(&0), 0)
+        );
+    }
+
+    #[test]
+    fn every_track_has_its_own_key() {
+        let keys: Vec<u32> = (0..Musics::COUNT as u8)
+            .map(|index| track_key(Musics::from_index(&index)))
+            .collect();
+        for (i, key) in keys.iter().enumerate() {
+            assert!(!keys[i + 1..].contains(key));
+        }
+    }
+}
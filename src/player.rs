@@ -0,0 +1,751 @@
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel, watch::Watch};
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::audio::{SAMPLE_RATE, SPECTRUM};
+use crate::dsp::{
+    AudioFormat, CHUNK_BYTES, DEFAULT_EQ, EqSettings, FRAME_BYTES, Gain, PcmStream, Pipeline,
+    ResampleQuality,
+};
+use crate::hal::AudioSink;
+use crate::music::Musics;
+use crate::playlist::{PlaybackMode, Playlist};
+use crate::streaming::LiveStream;
+
+/// Requests to the player, from the buttons, the menu or any other front end.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlayerCommand {
+    /// Resumes playback, fading in.
+    Play,
+    /// Fades out and pauses.
+    Pause,
+    /// Pauses while playing, plays otherwise.
+    Toggle,
+    /// Skips to the next track of the playlist.
+    Next,
+    /// Restarts the track if more than 10% has been played, otherwise goes to the previous one.
+    Prev,
+    /// Jumps straight to a track, e.g. from the menu's track list.
+    Select(Musics),
+    /// Moves the position by this many seconds (negative rewinds).
+    Seek(i32),
+    /// Moves to this position in the track, in milliseconds.
+    SeekTo(u32),
+    /// Sets the volume (0-100%), mapped onto a dB scale by the gain stage.
+    SetVolume(u8),
+    /// Raises or lowers the volume by this many points, within 0-100%.
+    ChangeVolume(i8),
+    SetMode(PlaybackMode),
+    SetEq(EqSettings),
+    /// Selects the sample-rate converter quality.
+    SetResampleQuality(ResampleQuality),
+    /// Sets the stereo balance, from -100 (left only) to 100 (right only).
+    SetBalance(i8),
+    /// Sets the overlap between consecutive tracks, in milliseconds. Zero
+    /// plays them back to back without a gap.
+    SetCrossfade(u16),
+    /// Sets the length of the fade around play, pause and track changes, in milliseconds.
+    SetFade(u16),
+    /// Plays the audio arriving in [`STREAM_BUFFER`](crate::streaming::STREAM_BUFFER)
+    /// instead of the track, until the stream ends or a track is chosen.
+    Stream(AudioFormat),
+}
+
+/// Everything the rest of the system needs to know about playback.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlayerState {
+    pub track: Musics,
+    pub playing: bool,
+    /// Volume, 0-100%.
+    pub volume: u8,
+    pub mode: PlaybackMode,
+    /// Playback position of the audio reaching the DAC, in milliseconds.
+    pub position_ms: u32,
+    /// Length of the current track, in milliseconds. Zero while streaming.
+    pub duration_ms: u32,
+    pub eq: EqSettings,
+    /// Set while playing a stream from the serial port rather than `track`.
+    pub streaming: bool,
+}
+
+impl PlayerState {
+    /// The latest published state, or the defaults before anything was published.
+    pub fn current() -> Self {
+        PLAYER_STATE.try_get().unwrap_or_default()
+    }
+
+    /// Playback progress, 0-100%.
+    pub fn percentage(&self) -> u8 {
+        match self.duration_ms {
+            0 => 0,
+            d => (self.position_ms.min(d) as u64 * 100 / d as u64) as u8,
+        }
+    }
+}
+
+impl Default for PlayerState {
+    fn default() -> Self {
+        Self {
+            track: Musics::from_index(&0),
+            playing: false,
+            volume: 50,
+            mode: PlaybackMode::from_index(0),
+            position_ms: 0,
+            duration_ms: 0,
+            eq: DEFAULT_EQ,
+            streaming: false,
+        }
+    }
+}
+
+/// Commands for the player, applied between two chunks of audio.
+pub static PLAYER_COMMANDS: Channel<CriticalSectionRawMutex, PlayerCommand, 16> = Channel::new();
+
+/// State published by the player whenever it changes. Before the player
+/// starts it holds the state to resume from, restored from flash in `main`.
+pub static PLAYER_STATE: Watch<CriticalSectionRawMutex, PlayerState, 4> = Watch::new();
+
+/// Fade around play, pause and track changes until [`PlayerCommand::SetFade`] says otherwise.
+const DEFAULT_FADE_MS: u16 = 20;
+
+/// Transport action deferred until the fade-out has reached silence.
+#[derive(Debug, Clone, Copy)]
+enum Transition {
+    Pause,
+    Load(Musics),
+    Restart,
+    Stream(AudioFormat),
+}
+
+/// The player's transport: track loading, play/pause, skipping, seeking and
+/// the playback mode, wrapped around the hardware-independent [`Pipeline`].
+///
+/// Commands come in through [`PLAYER_COMMANDS`] and the state is published to
+/// [`PLAYER_STATE`]. The player never touches an output device; [`play`]
+/// pulls audio with [`render`](Self::render) whenever the [`AudioSink`] has
+/// room, which is the I2S DMA ring on the board and a WAV file in the simulator.
+pub struct Player {
+    state: PlayerState,
+    stream: PcmStream,
+    pipeline: Pipeline,
+    playlist: Playlist,
+    /// Next track while a crossfade is in progress
+    incoming: Option<(Musics, PcmStream)>,
+    /// Stream played instead of `stream`, which stays where it was left
+    live: Option<LiveStream>,
+    pending: Option<Transition>,
+    /// Set by a pause while another transition is pending: it is still
+    /// carried out, and playback stays paused afterwards
+    hold: bool,
+    last_log_time: Instant,
+}
+
+impl Player {
+    /// Opens the track of the published state, where the previous session left off.
+    /// `seed` drives the shuffle order and should come from a random source.
+    pub fn new(seed: u32) -> Self {
+        let state = PlayerState::current();
+        let mut stream = open_track(state.track);
+        stream.seek_ms(state.position_ms);
+        let mut pipeline = Pipeline::new(SAMPLE_RATE);
+        pipeline.gain = Gain::new(state.volume);
+        pipeline.eq.set_bands(state.eq);
+        pipeline.fade.set_duration_ms(DEFAULT_FADE_MS);
+        pipeline.start_track(&stream);
+
+        let playlist = Playlist::new(state.mode, state.track, seed);
+
+        if !state.playing {
+            pipeline.fade.mute();
+        }
+
+        Self {
+            state,
+            stream,
+            pipeline,
+            playlist,
+            incoming: None,
+            live: None,
+            pending: None,
+            hold: false,
+            last_log_time: Instant::now(),
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.state.playing
+    }
+
+    /// Applies the pending commands and publishes the resulting state.
+    ///
+    /// Transitions fade out first and are carried out here once the output
+    /// has reached silence, so this must be called regularly while rendering.
+    pub fn update(&mut self) {
+        // Coalesce pending seeks so a fast spin is a single jump
+        let mut seek = 0i32;
+        while let Ok(command) = PLAYER_COMMANDS.try_receive() {
+            match command {
+                PlayerCommand::Seek(seconds) => seek = seek.saturating_add(seconds),
+                // A jump to a position overrides the moves queued before it
+                PlayerCommand::SeekTo(ms) => {
+                    seek = 0;
+                    self.seek_to(ms);
+                }
+                command => self.handle(command),
+            }
+        }
+        if seek != 0 {
+            self.seek(seek);
+        }
+
+        if self.pipeline.fade.is_silent()
+            && let Some(transition) = self.pending.take()
+        {
+            match transition {
+                Transition::Pause => self.state.playing = false,
+                Transition::Load(new_music) => self.load_track(new_music),
+                Transition::Restart => {
+                    self.stream.restart();
+                    self.pipeline.start_track(&self.stream);
+                }
+                Transition::Stream(format) => {
+                    let live = LiveStream::new(format);
+                    self.pipeline.start_track(&live);
+                    self.live = Some(live);
+                    self.state.streaming = true;
+                }
+            }
+            if core::mem::take(&mut self.hold) {
+                self.state.playing = false;
+            } else if self.state.playing {
+                self.pipeline.fade.fade_in();
+            }
+        }
+
+        if !self.state.playing {
+            SPECTRUM.clear();
+        }
+
+        // Skipping, seeking and restarting reset the pipeline, which abandons the crossfade
+        if !self.pipeline.crossfade.is_active() {
+            self.incoming = None;
+        }
+
+        self.publish();
+    }
+
+    fn handle(&mut self, command: PlayerCommand) {
+        let pausing = self.hold || matches!(self.pending, Some(Transition::Pause));
+        // Repeated presses during a fade step on from the track already queued
+        let queued = match self.pending {
+            Some(Transition::Load(music)) => music,
+            _ => self.state.track,
+        };
+
+        match command {
+            PlayerCommand::Toggle if self.state.playing && !pausing => {
+                self.handle(PlayerCommand::Pause)
+            }
+            PlayerCommand::Toggle => self.handle(PlayerCommand::Play),
+            PlayerCommand::Play => {
+                self.hold = false;
+                // Another transition fades back in once it is carried out
+                if matches!(self.pending, None | Some(Transition::Pause)) {
+                    self.pending = None;
+                    self.pipeline.fade.fade_in();
+                }
+                self.state.playing = true;
+                log::info!("Play");
+            }
+            PlayerCommand::Pause => {
+                if self.state.playing && !pausing {
+                    // A track queued during the fade still loads, paused
+                    match self.pending {
+                        Some(_) => self.hold = true,
+                        None => self.pending = Some(Transition::Pause),
+                    }
+                    self.pipeline.fade.fade_out();
+                    log::info!("Pause");
+                }
+            }
+            PlayerCommand::Next => {
+                let new_music = self.playlist.next(queued);
+                self.queue(Transition::Load(new_music));
+                log::info!("Next music: {}", new_music.title());
+            }
+            PlayerCommand::Prev => {
+                if self.pending.is_none() && self.live.is_none() && self.stream.percentage() > 10 {
+                    self.queue(Transition::Restart);
+                    log::info!("Restarting current music: {}", self.state.track.title());
+                } else {
+                    let new_music = self.playlist.prev(queued);
+                    self.queue(Transition::Load(new_music));
+                    log::info!("Previous music: {}", new_music.title());
+                }
+            }
+            PlayerCommand::Select(new_music) => {
+                self.queue(Transition::Load(new_music));
+                log::info!("Selected music: {}", new_music.title());
+            }
+            PlayerCommand::Seek(seconds) => self.seek(seconds),
+            PlayerCommand::SeekTo(ms) => self.seek_to(ms),
+            PlayerCommand::SetVolume(volume) => self.set_volume(volume),
+            PlayerCommand::ChangeVolume(delta) => {
+                self.set_volume(self.state.volume.saturating_add_signed(delta));
+            }
+            PlayerCommand::SetMode(mode) => {
+                if mode != self.playlist.mode() {
+                    self.playlist.set_mode(mode, self.state.track);
+                    self.state.mode = mode;
+                    log::info!("Playback mode: {mode:?}");
+                }
+            }
+            PlayerCommand::SetEq(settings) => {
+                self.pipeline.eq.set_bands(settings);
+                self.state.eq = settings;
+                log::info!("Equalizer updated");
+            }
+            PlayerCommand::SetResampleQuality(quality) => {
+                self.pipeline.resampler.set_quality(quality);
+                log::info!("Resampler quality: {quality:?}");
+            }
+            PlayerCommand::SetBalance(balance) => self.pipeline.balance.set_balance(balance),
+            PlayerCommand::SetCrossfade(ms) => self.pipeline.crossfade.set_duration_ms(ms),
+            PlayerCommand::SetFade(ms) => self.pipeline.fade.set_duration_ms(ms),
+            PlayerCommand::Stream(format) => {
+                self.queue(Transition::Stream(format));
+                log::info!("Streaming from the serial port");
+            }
+        }
+    }
+
+    /// Fades out and carries out `transition` once silent, playing on afterwards.
+    fn queue(&mut self, transition: Transition) {
+        self.pending = Some(transition);
+        self.hold = false;
+        self.pipeline.fade.fade_out();
+        self.state.playing = true;
+    }
+
+    fn seek(&mut self, seconds: i32) {
+        // A stream only plays forward, as it arrives
+        if self.live.is_some() {
+            return;
+        }
+        let frame = self.stream.seek_by_seconds(seconds);
+        self.pipeline.start_track(&self.stream);
+        log::info!("Seek {seconds:+}s to frame {frame}");
+    }
+
+    fn seek_to(&mut self, ms: u32) {
+        if self.live.is_some() {
+            return;
+        }
+        let frame = self.stream.seek_ms(ms);
+        self.pipeline.start_track(&self.stream);
+        log::info!("Seek to {ms}ms, frame {frame}");
+    }
+
+    fn set_volume(&mut self, volume: u8) {
+        self.state.volume = volume.min(100); // Cap at 100%
+        self.pipeline.gain.set_volume(self.state.volume);
+        log::info!("Volume changed: {}", self.state.volume);
+    }
+
+    /// Publishes the playback position, `latency_ms` behind the audio
+    /// rendered so far to account for what the sink still has queued.
+    pub fn publish_position(&mut self, latency_ms: u32) {
+        let (position_ms, duration_ms) = match &self.live {
+            Some(live) => (live.position_ms(), 0),
+            None => (self.stream.position_ms(), self.stream.duration_ms()),
+        };
+        self.state.position_ms = position_ms.saturating_sub(latency_ms);
+        self.state.duration_ms = duration_ms;
+        self.publish();
+    }
+
+    fn publish(&self) {
+        PLAYER_STATE.sender().send_if_modified(|published| {
+            let changed = *published != Some(self.state);
+            *published = Some(self.state);
+            changed
+        });
+    }
+
+    /// Renders the next chunk of audio into `out`, returning the number of bytes written.
+    ///
+    /// Only meant to be called while [`is_playing`](Self::is_playing); the
+    /// caller feeds silence otherwise. At the end of a track the playback
+    /// mode decides whether the next one follows or playback stops.
+    pub fn render(&mut self, out: &mut [u8; CHUNK_BYTES]) -> usize {
+        if self.live.is_some() {
+            return self.render_live(out);
+        }
+
+        // Start mixing in the next track shortly before this one ends. The
+        // playlist only moves on once the crossfade completes, as skipping
+        // or seeking abandons it.
+        if self.incoming.is_none()
+            && self.pipeline.crossfade_due(&self.stream)
+            && let Some(new_music) = self.playlist.peek_after_end(self.state.track)
+        {
+            let new_stream = open_track(new_music);
+            self.pipeline.begin_crossfade(&self.stream, &new_stream);
+            self.incoming = Some((new_music, new_stream));
+            log::info!("Crossfading into: {}", new_music.title());
+        }
+
+        let len = match &mut self.incoming {
+            Some((_, new_stream)) => {
+                self.pipeline
+                    .fill_crossfade(&mut self.stream, new_stream, out)
+            }
+            None => self.pipeline.fill(&mut self.stream, out),
+        };
+
+        // Once the old track has faded out the incoming one takes its place
+        if !self.pipeline.crossfade.is_active()
+            && let Some((new_music, new_stream)) = self.incoming.take()
+        {
+            self.playlist.after_end(self.state.track);
+            self.state.track = new_music;
+            self.stream = new_stream;
+            log::info!("Now playing: {}", new_music.title());
+        }
+
+        if let Some(bands) = self.pipeline.analyzer.take_bands() {
+            SPECTRUM.publish(&bands);
+        }
+
+        // Track Progress Logging
+        if self.last_log_time.elapsed() > Duration::from_secs(1) {
+            log::info!("Playing: {}%", self.stream.percentage());
+            self.last_log_time = Instant::now();
+        }
+
+        // At EOF the playback mode picks the next track, or stops
+        if self.pipeline.is_finished(&self.stream) {
+            log::info!("Music '{}' ended!", self.state.track.title());
+            match self.playlist.after_end(self.state.track) {
+                Some(new_music) => {
+                    self.advance_track(new_music);
+                    log::info!("Now playing: {}", new_music.title());
+                }
+                None => {
+                    self.stream.restart();
+                    self.pipeline.start_track(&self.stream);
+                    self.pipeline.fade.mute();
+                    self.state.playing = false;
+                }
+            }
+        }
+
+        len
+    }
+
+    /// Renders the next chunk of the stream. Once it has been played to the
+    /// end playback pauses, ready to resume the track where it was left.
+    fn render_live(&mut self, out: &mut [u8; CHUNK_BYTES]) -> usize {
+        let Some(live) = &mut self.live else {
+            return 0;
+        };
+        let mut len = self.pipeline.fill(live, out);
+
+        if let Some(bands) = self.pipeline.analyzer.take_bands() {
+            SPECTRUM.publish(&bands);
+        }
+
+        if live.is_finished() && self.pipeline.resampler.is_drained() {
+            log::info!("Stream ended");
+            self.live = None;
+            self.state.streaming = false;
+            self.pipeline.start_track(&self.stream);
+            self.pipeline.fade.mute();
+            self.state.playing = false;
+        } else if len == 0 {
+            // The computer is late: play silence rather than leave the sink to run dry
+            out.fill(0);
+            len = CHUNK_BYTES;
+        }
+        len
+    }
+
+    /// Switches to `new_music`, starting the signal chain afresh.
+    fn load_track(&mut self, new_music: Musics) {
+        self.live = None;
+        self.state.streaming = false;
+        self.state.track = new_music;
+        self.stream = open_track(new_music);
+        self.pipeline.start_track(&self.stream);
+    }
+
+    /// Moves on to `new_music` right after the current track ends, keeping the
+    /// signal chain running so there is no gap between them.
+    fn advance_track(&mut self, new_music: Musics) {
+        self.state.track = new_music;
+        self.stream = open_track(new_music);
+        self.pipeline.continue_track(&self.stream);
+    }
+}
+
+/// Plays into `sink` forever, handling the controls in between chunks.
+///
+/// The sink is kept topped up, with silence while paused so the output never
+/// runs dry, and the published position accounts for the audio still queued
+/// in it, not the silence queued behind it.
+/// `seed` drives the shuffle order and should come from a random source.
+pub async fn play<S: AudioSink>(mut sink: S, seed: u32) -> ! {
+    let mut player = Player::new(seed);
+    // Silence pushed since the last rendered audio, which the sink plays after it
+    let mut trailing_silence = 0;
+
+    loop {
+        // 1. Handle Control Signals (Play/Pause/Next/Prev)
+        player.update();
+
+        // 2. Audio Processing & Output Feed
+        let avail = sink.available().unwrap();
+
+        // Audio still queued in the sink has been rendered but not heard yet
+        let queued = S::CAPACITY - avail.min(S::CAPACITY);
+        let queued_frames = queued.saturating_sub(trailing_silence) / FRAME_BYTES;
+        let queued_ms = (queued_frames as u64 * 1000 / SAMPLE_RATE as u64) as u32;
+        player.publish_position(queued_ms);
+
+        if !player.is_playing() {
+            // Feed silence to prevent audio artifacts while paused.
+            // Whole frames only, so the left/right alignment is never lost.
+            let silence = [0u8; CHUNK_BYTES];
+            let chunk = avail.min(CHUNK_BYTES) / FRAME_BYTES * FRAME_BYTES;
+            sink.push(&silence[..chunk]).unwrap();
+            trailing_silence = (trailing_silence + chunk).min(S::CAPACITY);
+            Timer::after(Duration::from_millis(10)).await;
+            continue;
+        }
+
+        if avail > 2 * CHUNK_BYTES {
+            let mut chunk = [0u8; CHUNK_BYTES];
+            let len = player.render(&mut chunk);
+            sink.push(&chunk[..len]).unwrap();
+            if len > 0 {
+                trailing_silence = 0;
+            }
+        }
+        Timer::after(Duration::from_millis(5)).await;
+    }
+}
+
+/// Opens the audio stream of a track, adapting to the format in its header.
+/// Unreadable files yield an empty stream, which ends immediately.
+fn open_track(music: Musics) -> PcmStream {
+    PcmStream::from_asset(music.bytes()).unwrap_or_else(|err| {
+        log::error!("Invalid audio file for '{}': {err:?}", music.title());
+        PcmStream::new(&[], AudioFormat::RAW_DEFAULT)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hal::mock::{self, MockSink};
+
+    /// Starts from the default state, with no command left over from another test.
+    fn reset() {
+        PLAYER_STATE.sender().send(PlayerState::default());
+        while PLAYER_COMMANDS.try_receive().is_ok() {}
+    }
+
+    fn is_silent(bytes: &[u8]) -> bool {
+        bytes.iter().all(|&byte| byte == 0)
+    }
+
+    #[test]
+    fn play_feeds_silence_until_playing() {
+        let _lock = mock::lock();
+        reset();
+        let sink = MockSink::instant();
+
+        mock::run(play(&sink, 1), async {
+            mock::wait_until("silence", || sink.pushed().len() >= 4 * CHUNK_BYTES).await;
+            assert!(is_silent(&sink.pushed()));
+            assert!(!PlayerState::current().playing);
+
+            PLAYER_COMMANDS.send(PlayerCommand::Play).await;
+            mock::wait_until("the track", || !is_silent(&sink.pushed())).await;
+            assert!(PlayerState::current().playing);
+            assert_eq!(sink.pushed().len() % FRAME_BYTES, 0);
+        });
+    }
+
+    #[test]
+    fn play_fades_out_to_silence_on_pause() {
+        let _lock = mock::lock();
+        reset();
+        let sink = MockSink::instant();
+
+        mock::run(play(&sink, 1), async {
+            PLAYER_COMMANDS.send(PlayerCommand::Play).await;
+            mock::wait_until("the track", || !is_silent(&sink.pushed())).await;
+
+            PLAYER_COMMANDS.send(PlayerCommand::Pause).await;
+            mock::wait_until("the pause", || !PlayerState::current().playing).await;
+            let paused_at = sink.pushed().len();
+            mock::wait_until("more silence", || {
+                sink.pushed().len() >= paused_at + 4 * CHUNK_BYTES
+            })
+            .await;
+            assert!(is_silent(&sink.pushed()[paused_at..]));
+        });
+    }
+
+    #[test]
+    fn play_never_overfills_the_sink() {
+        let _lock = mock::lock();
+        reset();
+        let sink = MockSink::new();
+
+        mock::run(play(&sink, 1), async {
+            mock::wait_until("a full sink", || sink.queued() == MockSink::CAPACITY).await;
+            PLAYER_COMMANDS.send(PlayerCommand::Play).await;
+            // A DAC playing faster than real time
+            mock::wait_until("the track", || {
+                sink.play(8 * FRAME_BYTES);
+                assert!(sink.queued() <= MockSink::CAPACITY);
+                !is_silent(&sink.pushed())
+            })
+            .await;
+        });
+    }
+
+    #[test]
+    fn an_abandoned_crossfade_skips_no_track() {
+        let _lock = mock::lock();
+        reset();
+        PLAYER_STATE.sender().send(PlayerState {
+            playing: true,
+            mode: PlaybackMode::Shuffle,
+            ..PlayerState::default()
+        });
+        let mut player = Player::new(7);
+        let track = player.state.track;
+        let following = player.playlist.peek_after_end(track);
+        player.handle(PlayerCommand::SetCrossfade(500));
+        player.seek_to(player.stream.duration_ms() - 300);
+
+        let mut chunk = [0; CHUNK_BYTES];
+        while player.incoming.is_none() {
+            player.render(&mut chunk);
+        }
+        assert_eq!(player.incoming.as_ref().map(|(music, _)| *music), following);
+
+        // Seeking back abandons the crossfade, and the track stays next in line
+        player.handle(PlayerCommand::SeekTo(0));
+        player.update();
+        assert!(player.incoming.is_none());
+        assert_eq!(player.playlist.peek_after_end(track), following);
+
+        // Once a crossfade completes, the playlist moves on from the new track
+        player.seek_to(player.stream.duration_ms() - 300);
+        while player.state.track == track {
+            player.render(&mut chunk);
+        }
+        assert_eq!(Some(player.state.track), following);
+        let mut expected = Playlist::new(PlaybackMode::Shuffle, track, 7);
+        expected.after_end(track);
+        assert_eq!(
+            player.playlist.peek_after_end(player.state.track),
+            expected.after_end(player.state.track)
+        );
+    }
+
+    /// Renders and updates until no transition is pending.
+    fn settle(player: &mut Player) {
+        let mut chunk = [0; CHUNK_BYTES];
+        while player.pending.is_some() {
+            player.render(&mut chunk);
+            player.update();
+        }
+    }
+
+    #[test]
+    fn pausing_during_a_track_change_keeps_the_new_track() {
+        let _lock = mock::lock();
+        reset();
+        PLAYER_STATE.sender().send(PlayerState {
+            playing: true,
+            mode: PlaybackMode::RepeatAll,
+            ..PlayerState::default()
+        });
+        let mut player = Player::new(1);
+        let track = player.state.track;
+
+        player.handle(PlayerCommand::Next);
+        player.handle(PlayerCommand::Pause);
+        settle(&mut player);
+        assert_eq!(player.state.track, track.next());
+        assert!(!player.is_playing());
+
+        // Toggling back plays it, and a second pause before the load lands keeps it too
+        player.handle(PlayerCommand::Toggle);
+        assert!(player.is_playing());
+        player.handle(PlayerCommand::Next);
+        player.handle(PlayerCommand::Toggle);
+        player.handle(PlayerCommand::Toggle);
+        player.handle(PlayerCommand::Pause);
+        settle(&mut player);
+        assert_eq!(player.state.track, track.next().next());
+        assert!(!player.is_playing());
+
+        // Resuming before the load lands plays on after it
+        player.handle(PlayerCommand::Play);
+        player.handle(PlayerCommand::Prev);
+        player.handle(PlayerCommand::Pause);
+        player.handle(PlayerCommand::Play);
+        settle(&mut player);
+        assert_eq!(player.state.track, track.next());
+        assert!(player.is_playing());
+        assert!(!player.pipeline.fade.is_silent());
+    }
+
+    #[test]
+    fn position_stops_where_the_audio_paused() {
+        let _lock = mock::lock();
+        reset();
+        let sink = MockSink::new();
+
+        mock::run(play(&sink, 1), async {
+            mock::wait_until("a full sink", || sink.queued() == MockSink::CAPACITY).await;
+            let start = sink.pushed().len();
+            PLAYER_COMMANDS.send(PlayerCommand::Play).await;
+            mock::wait_until("some playback", || {
+                sink.play(CHUNK_BYTES);
+                PlayerState::current().position_ms > 500
+            })
+            .await;
+
+            PLAYER_COMMANDS.send(PlayerCommand::Pause).await;
+            mock::wait_until("the pause", || {
+                sink.play(FRAME_BYTES);
+                !PlayerState::current().playing
+            })
+            .await;
+            // Everything rendered has been played and the sink holds only silence
+            let paused_at = sink.pushed().len();
+            mock::wait_until("the last of the audio", || {
+                sink.play(CHUNK_BYTES);
+                sink.pushed().len() > paused_at + 2 * MockSink::CAPACITY
+            })
+            .await;
+            mock::wait_until("a full sink", || sink.queued() == MockSink::CAPACITY).await;
+            Timer::after_millis(50).await;
+            let pushed = sink.pushed();
+            let end = pushed.iter().rposition(|&byte| byte != 0).unwrap();
+            let played_ms = ((end - start) / FRAME_BYTES) as u64 * 1000 / SAMPLE_RATE as u64;
+
+            let position = PlayerState::current().position_ms;
+            assert!(
+                position.abs_diff(played_ms as u32) <= 15,
+                "{position}ms shown, {played_ms}ms played"
+            );
+        });
+    }
+}
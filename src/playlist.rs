@@ -0,0 +1,285 @@
+use crate::music::Musics;
+
+/// What happens when a track ends, and how Next/Previous walk the track list.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlaybackMode {
+    /// Play through the track list once and stop after the last track.
+    RepeatOff,
+    /// Play the same track again.
+    RepeatOne,
+    /// Continue with the next track, wrapping after the last one.
+    RepeatAll,
+    /// Play every track once in random order, then reshuffle.
+    Shuffle,
+}
+
+impl PlaybackMode {
+    /// Number of available modes.
+    pub const COUNT: usize = Self::ALL.len();
+
+    const ALL: [PlaybackMode; 4] = [
+        PlaybackMode::RepeatOff,
+        PlaybackMode::RepeatOne,
+        PlaybackMode::RepeatAll,
+        PlaybackMode::Shuffle,
+    ];
+
+    /// Returns the following mode, cycling back to the first one.
+    pub fn next(&self) -> Self {
+        Self::ALL[(self.to_index() as usize + 1) % Self::ALL.len()]
+    }
+
+    /// Short label for the display.
+    pub fn label(&self) -> &'static str {
+        match self {
+            PlaybackMode::RepeatOff => "OFF",
+            PlaybackMode::RepeatOne => "ONE",
+            PlaybackMode::RepeatAll => "ALL",
+            PlaybackMode::Shuffle => "SHF",
+        }
+    }
+
+    /// Full name, for the settings menu.
+    pub fn name(&self) -> &'static str {
+        match self {
+            PlaybackMode::RepeatOff => "Repeat off",
+            PlaybackMode::RepeatOne => "Repeat one",
+            PlaybackMode::RepeatAll => "Repeat all",
+            PlaybackMode::Shuffle => "Shuffle",
+        }
+    }
+
+    /// Factory method to retrieve a mode from a numeric index.
+    pub fn from_index(idx: u8) -> Self {
+        Self::ALL
+            .get(idx as usize)
+            .copied()
+            .unwrap_or(PlaybackMode::RepeatOff)
+    }
+
+    /// Converts the mode into a numeric index, e.g. for storing in an atomic.
+    pub fn to_index(&self) -> u8 {
+        Self::ALL.iter().position(|m| m == self).unwrap() as u8
+    }
+}
+
+/// Decides which track plays next according to the [`PlaybackMode`].
+///
+/// In shuffle mode a random permutation of all tracks is walked in order, so
+/// no track repeats until every other one has played. Randomness comes from a
+/// small xorshift generator, seeded by the caller (the hardware RNG on the
+/// target, a fixed value in tests).
+#[derive(Debug, Clone)]
+pub struct Playlist {
+    mode: PlaybackMode,
+    order: [u8; Musics::COUNT],
+    position: usize,
+    rng: u32,
+}
+
+impl Playlist {
+    /// Starts in `mode` from `current`; in shuffle mode the first permutation
+    /// begins with it.
+    pub fn new(mode: PlaybackMode, current: Musics, seed: u32) -> Self {
+        let mut order = [0u8; Musics::COUNT];
+        for (i, slot) in order.iter_mut().enumerate() {
+            *slot = i as u8;
+        }
+        let mut playlist = Self {
+            mode,
+            order,
+            position: 0,
+            // xorshift gets stuck on zero
+            rng: if seed == 0 { 0x9E37_79B9 } else { seed },
+        };
+        if mode == PlaybackMode::Shuffle {
+            playlist.shuffle(current);
+        }
+        playlist
+    }
+
+    pub fn mode(&self) -> PlaybackMode {
+        self.mode
+    }
+
+    /// Switches mode. Entering shuffle starts a new permutation from `current`.
+    pub fn set_mode(&mut self, mode: PlaybackMode, current: Musics) {
+        if mode == PlaybackMode::Shuffle && self.mode != PlaybackMode::Shuffle {
+            self.shuffle(current);
+        }
+        self.mode = mode;
+    }
+
+    /// Track to load when the user presses Next.
+    pub fn next(&mut self, current: Musics) -> Musics {
+        match self.mode {
+            PlaybackMode::Shuffle => self.shuffle_next(current),
+            _ => current.next(),
+        }
+    }
+
+    /// Track to load when the user presses Previous.
+    pub fn prev(&mut self, current: Musics) -> Musics {
+        match self.mode {
+            PlaybackMode::Shuffle => {
+                self.position = (self.position + Musics::COUNT - 1) % Musics::COUNT;
+                Musics::from_index(&self.order[self.position])
+            }
+            _ => current.prev(),
+        }
+    }
+
+    /// Track to play after `current` ends on its own, or `None` to stop.
+    pub fn after_end(&mut self, current: Musics) -> Option<Musics> {
+        match self.mode {
+            PlaybackMode::RepeatOff if current.to_index() as usize + 1 >= Musics::COUNT => None,
+            PlaybackMode::RepeatOff => Some(current.next()),
+            PlaybackMode::RepeatOne => Some(current),
+            PlaybackMode::RepeatAll => Some(current.next()),
+            PlaybackMode::Shuffle => Some(self.shuffle_next(current)),
+        }
+    }
+
+    /// Track [`after_end`](Self::after_end) would return, without moving on,
+    /// e.g. to start a crossfade that may still be abandoned.
+    pub fn peek_after_end(&self, current: Musics) -> Option<Musics> {
+        // The generator is deterministic, so a copy reshuffles the same way
+        self.clone().after_end(current)
+    }
+
+    fn shuffle_next(&mut self, current: Musics) -> Musics {
+        self.position += 1;
+        if self.position >= Musics::COUNT {
+            self.shuffle(current);
+            self.position = if Musics::COUNT > 1 { 1 } else { 0 };
+        }
+        Musics::from_index(&self.order[self.position])
+    }
+
+    /// Builds a new permutation with `current` first (Fisher-Yates on the rest).
+    fn shuffle(&mut self, current: Musics) {
+        let first = current.to_index();
+        let mut rest = 0;
+        for i in 0..Musics::COUNT as u8 {
+            if i != first {
+                rest += 1;
+                self.order[rest] = i;
+            }
+        }
+        self.order[0] = first;
+
+        for i in (2..Musics::COUNT).rev() {
+            let j = 1 + (self.random() as usize % i);
+            self.order.swap(i, j);
+        }
+        self.position = 0;
+    }
+
+    fn random(&mut self) -> u32 {
+        let mut x = self.rng;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng = x;
+        x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEEDS: [u32; 6] = [0, 1, 7, 0xDEAD_BEEF, 123_456_789, u32::MAX];
+
+    fn track(index: usize) -> Musics {
+        Musics::from_index(&(index as u8))
+    }
+
+    fn is_permutation(tracks: &[Musics]) -> bool {
+        let mut indexes: Vec<u8> = tracks.iter().map(Musics::to_index).collect();
+        indexes.sort();
+        indexes.iter().copied().eq(0..Musics::COUNT as u8)
+    }
+
+    #[test]
+    fn shuffle_starts_from_the_current_track() {
+        let start = track(Musics::COUNT - 1);
+        let mut shuffled = false;
+        for seed in SEEDS {
+            let mut playlist = Playlist::new(PlaybackMode::Shuffle, start, seed);
+            let mut tracks = vec![start];
+            for _ in 1..Musics::COUNT {
+                let next = playlist.next(*tracks.last().unwrap());
+                tracks.push(next);
+            }
+            assert!(is_permutation(&tracks), "seed {seed}: {tracks:?}");
+            let in_order = (0..Musics::COUNT)
+                .all(|i| tracks[i] == track((start.to_index() as usize + i) % Musics::COUNT));
+            shuffled |= !in_order;
+        }
+        assert!(shuffled || Musics::COUNT < 3);
+    }
+
+    #[test]
+    fn shuffle_plays_every_track_before_repeating_one() {
+        for seed in SEEDS {
+            let mut playlist = Playlist::new(PlaybackMode::Shuffle, track(0), seed);
+            let mut tracks = vec![track(0)];
+            for _ in 0..20 * Musics::COUNT {
+                let next = playlist.after_end(*tracks.last().unwrap()).unwrap();
+                tracks.push(next);
+            }
+            // Each permutation starts with the last track of the one before
+            for cycle in tracks.windows(Musics::COUNT).step_by(Musics::COUNT - 1) {
+                assert!(is_permutation(cycle), "seed {seed}: {tracks:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn previous_undoes_next() {
+        for mode in [PlaybackMode::RepeatAll, PlaybackMode::Shuffle] {
+            let mut playlist = Playlist::new(mode, track(0), 42);
+            let mut current = track(0);
+            for _ in 0..5 * Musics::COUNT {
+                let mut there = playlist.clone();
+                let next = there.next(current);
+                assert_eq!(there.prev(next), current, "{mode:?}");
+
+                // Going back from the first track of a permutation wraps to its last
+                if mode != PlaybackMode::Shuffle || playlist.position > 0 {
+                    let mut back = playlist.clone();
+                    let prev = back.prev(current);
+                    assert_eq!(back.next(prev), current, "{mode:?}");
+                }
+                current = playlist.next(current);
+            }
+        }
+    }
+
+    #[test]
+    fn peeking_does_not_move_on() {
+        for seed in SEEDS {
+            let mut playlist = Playlist::new(PlaybackMode::Shuffle, track(0), seed);
+            let mut current = track(0);
+            for _ in 0..3 * Musics::COUNT {
+                let peeked = playlist.peek_after_end(current);
+                assert_eq!(playlist.peek_after_end(current), peeked);
+                current = playlist.after_end(current).unwrap();
+                assert_eq!(Some(current), peeked, "seed {seed}");
+            }
+        }
+    }
+
+    #[test]
+    fn repeat_modes_decide_what_follows_the_end() {
+        let last = track(Musics::COUNT - 1);
+        let mut playlist = Playlist::new(PlaybackMode::RepeatOff, last, 1);
+        assert_eq!(playlist.after_end(track(0)), Some(track(1 % Musics::COUNT)));
+        assert_eq!(playlist.after_end(last), None);
+        playlist.set_mode(PlaybackMode::RepeatOne, last);
+        assert_eq!(playlist.after_end(last), Some(last));
+        playlist.set_mode(PlaybackMode::RepeatAll, last);
+        assert_eq!(playlist.after_end(last), Some(track(0)));
+    }
+}
@@ -0,0 +1,487 @@
+use embedded_storage::nor_flash::NorFlash;
+
+/// Largest value a single key can hold, in bytes.
+pub const MAX_VALUE_LEN: usize = 32;
+
+/// Marks a sector formatted by this store ("PDS1").
+const SECTOR_MAGIC: u32 = 0x3153_4450;
+/// Sector header: magic and sequence number, both little-endian.
+const SECTOR_HEADER: u32 = 8;
+/// Record header: key, value length and a CRC-16 over them and the value.
+const RECORD_HEADER: usize = 4;
+/// Records are padded to whole words, the write unit of the ESP32 flash.
+const ALIGN: usize = 4;
+const MAX_RECORD: usize = RECORD_HEADER + MAX_VALUE_LEN;
+/// Value of erased flash; also the one key that can never be stored.
+const ERASED: u8 = 0xFF;
+
+/// Reasons a [`KvStore`] operation may fail.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StorageError<E> {
+    /// The flash driver reported an error.
+    Flash(E),
+    /// Fewer than two sectors, or a sector cannot hold one full-size record per key.
+    TooSmall,
+    /// The flash reads or writes in units that do not divide a word.
+    Unaligned,
+    /// The key is outside the store's `0..KEYS` range.
+    InvalidKey(u8),
+    /// The value is longer than [`MAX_VALUE_LEN`].
+    ValueTooLong(usize),
+    /// The buffer given to [`KvStore::get`] is shorter than the stored value.
+    BufferTooSmall(usize),
+}
+
+/// Small wear-levelled key-value store on NOR flash.
+///
+/// Values are appended to the active sector as checksummed records, so
+/// updating a key never rewrites flash in place: the newest record of a key
+/// wins. When the sector fills up, the latest value of every key is copied to
+/// the next sector, which then becomes the active one. Sectors are used in
+/// turn, spreading the erases evenly over the whole partition.
+///
+/// The new sector's header is only written once the copy is complete, and a
+/// damaged record ends the scan of a sector, so losing power at any point
+/// leaves either the old or the new value of each key readable.
+///
+/// Keys are `0..KEYS`; an index of the latest record per key is kept in RAM.
+pub struct KvStore<F, const KEYS: usize> {
+    flash: F,
+    sectors: u32,
+    active: u32,
+    sequence: u32,
+    /// Offset of the next record inside the active sector.
+    write_offset: u32,
+    /// Absolute offset of the latest record of each key.
+    index: [Option<u32>; KEYS],
+}
+
+impl<F: NorFlash, const KEYS: usize> KvStore<F, KEYS> {
+    const SECTOR_SIZE: u32 = F::ERASE_SIZE as u32;
+
+    /// Opens the store on `flash`, formatting it if no sector holds a valid header.
+    pub fn mount(flash: F) -> Result<Self, StorageError<F::Error>> {
+        if !ALIGN.is_multiple_of(F::READ_SIZE) || !ALIGN.is_multiple_of(F::WRITE_SIZE) {
+            return Err(StorageError::Unaligned);
+        }
+        let sectors = (flash.capacity() / F::ERASE_SIZE) as u32;
+        let full = SECTOR_HEADER as usize + KEYS * MAX_RECORD;
+        if sectors < 2 || full > F::ERASE_SIZE || KEYS > ERASED as usize {
+            return Err(StorageError::TooSmall);
+        }
+
+        let mut store = Self {
+            flash,
+            sectors,
+            active: 0,
+            sequence: 0,
+            write_offset: SECTOR_HEADER,
+            index: [None; KEYS],
+        };
+
+        let mut newest = None;
+        for sector in 0..sectors {
+            if let Some(sequence) = store.sector_sequence(sector)?
+                && newest.is_none_or(|(_, latest)| sequence > latest)
+            {
+                newest = Some((sector, sequence));
+            }
+        }
+
+        match newest {
+            Some((sector, sequence)) => {
+                store.active = sector;
+                store.sequence = sequence;
+                store.scan()?;
+            }
+            None => store.format()?,
+        }
+        Ok(store)
+    }
+
+    /// Gives the flash back, e.g. to mount it again.
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    /// Erases every sector, dropping all values.
+    pub fn format(&mut self) -> Result<(), StorageError<F::Error>> {
+        self.erase(0, self.sectors * Self::SECTOR_SIZE)?;
+        self.active = 0;
+        self.sequence = 1;
+        self.write_offset = SECTOR_HEADER;
+        self.index = [None; KEYS];
+        self.write_sector_header(0, 1)
+    }
+
+    /// Copies the value of `key` into `buf`, returning its length,
+    /// or `None` if the key was never set.
+    pub fn get(
+        &mut self,
+        key: u8,
+        buf: &mut [u8],
+    ) -> Result<Option<usize>, StorageError<F::Error>> {
+        let Some(offset) = self.lookup(key)? else {
+            return Ok(None);
+        };
+        let mut record = [0; MAX_RECORD];
+        let len = self.read_record(offset, &mut record)?;
+        buf.get_mut(..len)
+            .ok_or(StorageError::BufferTooSmall(len))?
+            .copy_from_slice(&record[RECORD_HEADER..RECORD_HEADER + len]);
+        Ok(Some(len))
+    }
+
+    /// Stores `value` under `key`. Writing the value already stored is a no-op,
+    /// so callers may save unconditionally without wearing the flash.
+    pub fn set(&mut self, key: u8, value: &[u8]) -> Result<(), StorageError<F::Error>> {
+        if value.len() > MAX_VALUE_LEN {
+            return Err(StorageError::ValueTooLong(value.len()));
+        }
+        if let Some(offset) = self.lookup(key)? {
+            let mut current = [0; MAX_RECORD];
+            let len = self.read_record(offset, &mut current)?;
+            if current[RECORD_HEADER..RECORD_HEADER + len] == *value {
+                return Ok(());
+            }
+        }
+
+        let size = record_size(value.len());
+        if self.write_offset + size > Self::SECTOR_SIZE {
+            self.compact()?;
+        }
+
+        let mut record = [ERASED; MAX_RECORD];
+        record[0] = key;
+        record[1] = value.len() as u8;
+        record[2..RECORD_HEADER].copy_from_slice(&checksum(key, value).to_le_bytes());
+        record[RECORD_HEADER..RECORD_HEADER + value.len()].copy_from_slice(value);
+
+        let offset = self.active * Self::SECTOR_SIZE + self.write_offset;
+        self.write(offset, &record[..size as usize])?;
+        self.index[key as usize] = Some(offset);
+        self.write_offset += size;
+        Ok(())
+    }
+
+    fn lookup(&self, key: u8) -> Result<Option<u32>, StorageError<F::Error>> {
+        self.index
+            .get(key as usize)
+            .copied()
+            .ok_or(StorageError::InvalidKey(key))
+    }
+
+    /// Sequence number of a formatted sector, or `None` if it holds no valid header.
+    fn sector_sequence(&mut self, sector: u32) -> Result<Option<u32>, StorageError<F::Error>> {
+        let mut header = [0; SECTOR_HEADER as usize];
+        self.read(sector * Self::SECTOR_SIZE, &mut header)?;
+        let [m0, m1, m2, m3, s0, s1, s2, s3] = header;
+        let sequence = u32::from_le_bytes([s0, s1, s2, s3]);
+        let valid = u32::from_le_bytes([m0, m1, m2, m3]) == SECTOR_MAGIC && sequence != u32::MAX;
+        Ok(valid.then_some(sequence))
+    }
+
+    fn write_sector_header(
+        &mut self,
+        sector: u32,
+        sequence: u32,
+    ) -> Result<(), StorageError<F::Error>> {
+        let mut header = [0; SECTOR_HEADER as usize];
+        header[..4].copy_from_slice(&SECTOR_MAGIC.to_le_bytes());
+        header[4..].copy_from_slice(&sequence.to_le_bytes());
+        self.write(sector * Self::SECTOR_SIZE, &header)
+    }
+
+    /// Rebuilds the index from the records of the active sector.
+    fn scan(&mut self) -> Result<(), StorageError<F::Error>> {
+        let base = self.active * Self::SECTOR_SIZE;
+        let mut offset = SECTOR_HEADER;
+        self.index = [None; KEYS];
+
+        while offset + RECORD_HEADER as u32 <= Self::SECTOR_SIZE {
+            let mut record = [0; MAX_RECORD];
+            self.read(base + offset, &mut record[..RECORD_HEADER])?;
+            if record[..RECORD_HEADER] == [ERASED; RECORD_HEADER] {
+                break;
+            }
+
+            let (key, len) = (record[0], record[1] as usize);
+            let size = record_size(len);
+            let mut intact = len <= MAX_VALUE_LEN && offset + size <= Self::SECTOR_SIZE;
+            if intact {
+                let value = RECORD_HEADER..size as usize;
+                self.read(base + offset + RECORD_HEADER as u32, &mut record[value])?;
+                let stored = u16::from_le_bytes([record[2], record[3]]);
+                intact = stored == checksum(key, &record[RECORD_HEADER..RECORD_HEADER + len]);
+            }
+            if !intact {
+                // Interrupted write: treat the sector as full so the next
+                // write moves the valid records to a fresh one
+                offset = Self::SECTOR_SIZE;
+                break;
+            }
+
+            if let Some(slot) = self.index.get_mut(key as usize) {
+                *slot = Some(base + offset);
+            }
+            offset += size;
+        }
+
+        self.write_offset = offset;
+        Ok(())
+    }
+
+    /// Copies the latest record of every key to the next sector and makes it the active one.
+    fn compact(&mut self) -> Result<(), StorageError<F::Error>> {
+        let next = (self.active + 1) % self.sectors;
+        let base = next * Self::SECTOR_SIZE;
+        self.erase(base, base + Self::SECTOR_SIZE)?;
+
+        let mut offset = SECTOR_HEADER;
+        let mut index = [None; KEYS];
+        for (key, from) in self.index.into_iter().enumerate() {
+            if let Some(from) = from {
+                let mut record = [0; MAX_RECORD];
+                let size = record_size(self.read_record(from, &mut record)?);
+                self.write(base + offset, &record[..size as usize])?;
+                index[key] = Some(base + offset);
+                offset += size;
+            }
+        }
+
+        // Written last: until then, mounting still picks the old sector
+        let sequence = self.sequence + 1;
+        self.write_sector_header(next, sequence)?;
+
+        self.active = next;
+        self.sequence = sequence;
+        self.write_offset = offset;
+        self.index = index;
+        Ok(())
+    }
+
+    /// Reads a record already validated by [`scan`](Self::scan), returning its value length.
+    fn read_record(
+        &mut self,
+        offset: u32,
+        record: &mut [u8; MAX_RECORD],
+    ) -> Result<usize, StorageError<F::Error>> {
+        self.read(offset, &mut record[..RECORD_HEADER])?;
+        let len = (record[1] as usize).min(MAX_VALUE_LEN);
+        let size = record_size(len) as usize;
+        self.read(
+            offset + RECORD_HEADER as u32,
+            &mut record[RECORD_HEADER..size],
+        )?;
+        Ok(len)
+    }
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), StorageError<F::Error>> {
+        self.flash.read(offset, bytes).map_err(StorageError::Flash)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), StorageError<F::Error>> {
+        self.flash.write(offset, bytes).map_err(StorageError::Flash)
+    }
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), StorageError<F::Error>> {
+        self.flash.erase(from, to).map_err(StorageError::Flash)
+    }
+}
+
+/// Bytes taken by a record holding `len` bytes of value.
+const fn record_size(len: usize) -> u32 {
+    (RECORD_HEADER + len.next_multiple_of(ALIGN)) as u32
+}
+
+/// CRC-16/CCITT over the key, the length and the value.
+fn checksum(key: u8, value: &[u8]) -> u16 {
+    [key, value.len() as u8]
+        .iter()
+        .chain(value)
+        .fold(0xFFFF, |crc, &byte| {
+            (0..8).fold(crc ^ ((byte as u16) << 8), |crc, _| {
+                if crc & 0x8000 != 0 {
+                    (crc << 1) ^ 0x1021
+                } else {
+                    crc << 1
+                }
+            })
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_storage::nor_flash::NorFlashErrorKind;
+
+    use super::*;
+    use crate::storage::RamFlash;
+
+    /// Three sectors, each fitting a header and a full-size record per key.
+    type Flash = RamFlash<256, 3>;
+    type Store = KvStore<Flash, 4>;
+
+    fn value(store: &mut Store, key: u8) -> Option<Vec<u8>> {
+        let mut buf = [0; MAX_VALUE_LEN];
+        let len = store.get(key, &mut buf).unwrap()?;
+        Some(buf[..len].to_vec())
+    }
+
+    fn remount(store: Store) -> Store {
+        Store::mount(store.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn mount_formats_a_blank_flash_once() {
+        let mut store = Store::mount(Flash::new()).unwrap();
+        assert_eq!(value(&mut store, 0), None);
+        store.set(0, b"volume").unwrap();
+        store.set(3, b"").unwrap();
+
+        let mut store = remount(store);
+        assert_eq!(value(&mut store, 0).as_deref(), Some(&b"volume"[..]));
+        assert_eq!(value(&mut store, 3).as_deref(), Some(&b""[..]));
+        assert_eq!(value(&mut store, 1), None);
+        assert_eq!(store.into_inner().erase_counts(), &[1, 1, 1]);
+    }
+
+    #[test]
+    fn newest_record_wins() {
+        let mut store = Store::mount(Flash::new()).unwrap();
+        for text in [&b"a"[..], b"bb", b"ccccc"] {
+            store.set(2, text).unwrap();
+        }
+        assert_eq!(value(&mut store, 2).as_deref(), Some(&b"ccccc"[..]));
+
+        // Saving the same value again writes nothing
+        let flash = store.into_inner();
+        let mut store = Store::mount(flash.clone()).unwrap();
+        store.set(2, b"ccccc").unwrap();
+        assert_eq!(store.into_inner(), flash);
+
+        let mut store = Store::mount(flash).unwrap();
+        assert_eq!(value(&mut store, 2).as_deref(), Some(&b"ccccc"[..]));
+    }
+
+    #[test]
+    fn compaction_wraps_around_and_levels_the_wear() {
+        let mut store = Store::mount(Flash::new()).unwrap();
+        for round in 0..300u32 {
+            let key = (round % 4) as u8;
+            store.set(key, &round.to_le_bytes()).unwrap();
+        }
+
+        let mut store = remount(store);
+        for (key, round) in (296..300u32).enumerate() {
+            assert_eq!(
+                value(&mut store, key as u8),
+                Some(round.to_le_bytes().to_vec())
+            );
+        }
+        // Every sector has been through several cycles, evenly
+        let erases = *store.into_inner().erase_counts();
+        assert!(erases.iter().all(|&count| count >= 4), "{erases:?}");
+        assert!(erases.iter().max().unwrap() - erases.iter().min().unwrap() <= 1);
+    }
+
+    #[test]
+    fn rejects_bad_keys_buffers_and_sizes() {
+        let mut store = Store::mount(Flash::new()).unwrap();
+        store.set(1, b"twelve bytes").unwrap();
+
+        assert_eq!(store.get(4, &mut [0; 4]), Err(StorageError::InvalidKey(4)));
+        assert_eq!(store.set(4, b"x"), Err(StorageError::InvalidKey(4)));
+        assert_eq!(
+            store.get(1, &mut [0; 11]),
+            Err(StorageError::BufferTooSmall(12))
+        );
+        assert_eq!(
+            store.set(1, &[0; MAX_VALUE_LEN + 1]),
+            Err(StorageError::ValueTooLong(MAX_VALUE_LEN + 1))
+        );
+
+        let one_sector = KvStore::<RamFlash<256, 1>, 4>::mount(RamFlash::new());
+        assert!(matches!(one_sector, Err(StorageError::TooSmall)));
+        let small_sectors = KvStore::<RamFlash<64, 4>, 4>::mount(RamFlash::new());
+        assert!(matches!(small_sectors, Err(StorageError::TooSmall)));
+    }
+
+    #[test]
+    fn torn_record_keeps_the_previous_value() {
+        let mut store = Store::mount(Flash::new()).unwrap();
+        store.set(0, b"old").unwrap();
+        store.set(0, b"new!").unwrap();
+
+        // Power lost while the second record was written: its value stayed erased
+        let mut flash = store.into_inner();
+        let second = (SECTOR_HEADER as usize + record_size(3) as usize) + RECORD_HEADER;
+        flash.sectors_mut()[0][second..second + 4].fill(ERASED);
+
+        let mut store = Store::mount(flash).unwrap();
+        assert_eq!(value(&mut store, 0).as_deref(), Some(&b"old"[..]));
+
+        // The damaged sector is left behind on the next write
+        store.set(1, b"x").unwrap();
+        let mut store = remount(store);
+        assert_eq!(value(&mut store, 0).as_deref(), Some(&b"old"[..]));
+        assert_eq!(value(&mut store, 1).as_deref(), Some(&b"x"[..]));
+        assert_eq!(store.into_inner().erase_counts(), &[1, 2, 1]);
+    }
+
+    #[test]
+    fn interrupted_compaction_falls_back_to_the_old_sector() {
+        let mut store = Store::mount(Flash::new()).unwrap();
+        let mut round = 0u32;
+        while store.active == 0 {
+            store.set((round % 4) as u8, &round.to_le_bytes()).unwrap();
+            round += 1;
+        }
+        let last = round - 1;
+
+        // Power lost before the new sector's header was written
+        let mut flash = store.into_inner();
+        flash.sectors_mut()[1][..SECTOR_HEADER as usize].fill(ERASED);
+
+        let mut store = Store::mount(flash).unwrap();
+        assert_eq!(store.active, 0);
+        // Everything but the write that triggered the compaction survives
+        let key = (last % 4) as u8;
+        assert_eq!(
+            value(&mut store, key),
+            Some((last - 4).to_le_bytes().to_vec())
+        );
+    }
+
+    #[test]
+    fn flash_errors_are_reported() {
+        struct Broken;
+        impl embedded_storage::nor_flash::ErrorType for Broken {
+            type Error = NorFlashErrorKind;
+        }
+        impl embedded_storage::nor_flash::ReadNorFlash for Broken {
+            const READ_SIZE: usize = 4;
+            fn read(&mut self, _: u32, _: &mut [u8]) -> Result<(), Self::Error> {
+                Err(NorFlashErrorKind::Other)
+            }
+            fn capacity(&self) -> usize {
+                1024
+            }
+        }
+        impl NorFlash for Broken {
+            const WRITE_SIZE: usize = 4;
+            const ERASE_SIZE: usize = 256;
+            fn erase(&mut self, _: u32, _: u32) -> Result<(), Self::Error> {
+                Err(NorFlashErrorKind::Other)
+            }
+            fn write(&mut self, _: u32, _: &[u8]) -> Result<(), Self::Error> {
+                Err(NorFlashErrorKind::Other)
+            }
+        }
+
+        let mounted = KvStore::<_, 4>::mount(Broken);
+        assert!(matches!(
+            mounted,
+            Err(StorageError::Flash(NorFlashErrorKind::Other))
+        ));
+    }
+}
@@ -0,0 +1,13 @@
+//! Hardware-independent persistent storage.
+//!
+//! [`KvStore`] runs on top of the `embedded-storage` NOR flash traits, so the
+//! same code drives the settings partition on the target and, in the unit
+//! tests, `RamFlash` on the host.
+
+mod kv;
+#[cfg(test)]
+mod ram;
+
+pub use kv::{KvStore, MAX_VALUE_LEN, StorageError};
+#[cfg(test)]
+pub use ram::RamFlash;
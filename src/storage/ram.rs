@@ -0,0 +1,92 @@
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashErrorKind, ReadNorFlash, check_erase, check_read, check_write,
+};
+
+/// NOR flash emulated in RAM, for testing [`KvStore`](super::KvStore) on the host.
+///
+/// It follows the rules of the real part: erasing sets a whole sector to
+/// `0xFF` and writing can only clear bits, so a store that rewrites flash in
+/// place reads back garbage here too. Erases are counted per sector to check
+/// the wear levelling.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RamFlash<const SECTOR: usize, const SECTORS: usize> {
+    sectors: [[u8; SECTOR]; SECTORS],
+    erases: [u32; SECTORS],
+}
+
+impl<const SECTOR: usize, const SECTORS: usize> RamFlash<SECTOR, SECTORS> {
+    /// Creates a fully erased flash.
+    pub const fn new() -> Self {
+        Self {
+            sectors: [[0xFF; SECTOR]; SECTORS],
+            erases: [0; SECTORS],
+        }
+    }
+
+    /// Number of times each sector has been erased.
+    pub fn erase_counts(&self) -> &[u32; SECTORS] {
+        &self.erases
+    }
+
+    /// Content of the whole flash, one slice per sector.
+    pub fn sectors(&self) -> &[[u8; SECTOR]; SECTORS] {
+        &self.sectors
+    }
+
+    /// Mutable content, e.g. to simulate a write cut short by a power loss.
+    pub fn sectors_mut(&mut self) -> &mut [[u8; SECTOR]; SECTORS] {
+        &mut self.sectors
+    }
+
+    fn byte_mut(&mut self, offset: usize) -> &mut u8 {
+        &mut self.sectors[offset / SECTOR][offset % SECTOR]
+    }
+}
+
+impl<const SECTOR: usize, const SECTORS: usize> Default for RamFlash<SECTOR, SECTORS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const SECTOR: usize, const SECTORS: usize> ErrorType for RamFlash<SECTOR, SECTORS> {
+    type Error = NorFlashErrorKind;
+}
+
+impl<const SECTOR: usize, const SECTORS: usize> ReadNorFlash for RamFlash<SECTOR, SECTORS> {
+    const READ_SIZE: usize = 4;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len())?;
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = *self.byte_mut(offset as usize + i);
+        }
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        SECTOR * SECTORS
+    }
+}
+
+impl<const SECTOR: usize, const SECTORS: usize> NorFlash for RamFlash<SECTOR, SECTORS> {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = SECTOR;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(self, from, to)?;
+        for sector in from as usize / SECTOR..to as usize / SECTOR {
+            self.sectors[sector] = [0xFF; SECTOR];
+            self.erases[sector] += 1;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len())?;
+        for (i, &byte) in bytes.iter().enumerate() {
+            *self.byte_mut(offset as usize + i) &= byte;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,413 @@
+//! Audio streamed into the player from a computer over a serial port.
+//!
+//! The frames described in [`protocol`] share the USB serial port with the
+//! console: its loop hands them to a [`StreamReceiver`], which queues the
+//! audio in [`STREAM_BUFFER`] and asks the player to switch to it with
+//! [`PlayerCommand::Stream`]. The player pulls the samples through a
+//! [`LiveStream`], the same way it reads a track from flash, and credits go
+//! back to the computer as it makes room. A stream plays from start to end;
+//! skipping to a track leaves it.
+
+pub mod protocol;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, pipe::Pipe};
+use embassy_time::{Duration, Instant};
+
+use crate::dsp::adpcm::{self, AdpcmChannel};
+use crate::dsp::{AudioFormat, CHANNELS, Encoding, SampleSource};
+use crate::hal::SerialPort;
+use crate::player::{PLAYER_COMMANDS, PlayerCommand, PlayerState};
+use protocol::{CRC_SIZE, FrameDecoder, HEADER_SIZE, MAX_AUDIO, Message, SYNC};
+
+/// Room for audio received ahead of playback, in bytes. About 1.5s of 16-bit
+/// stereo at 11025 Hz, or 0.2s at 44100 Hz.
+pub const BUFFER_SIZE: usize = 16 * 1024;
+
+/// Credits are sent once this much room has been freed since the last one.
+const CREDIT_STEP: u32 = BUFFER_SIZE as u32 / 4;
+/// Credits are repeated this often while streaming, in case one got lost.
+const CREDIT_INTERVAL: Duration = Duration::from_millis(250);
+/// Size of a credit frame: header, offset, free space and CRC.
+const CREDIT_FRAME: usize = HEADER_SIZE + 8 + CRC_SIZE;
+
+/// Audio received and not played yet, from the [`StreamReceiver`] to the player.
+pub static STREAM_BUFFER: Pipe<CriticalSectionRawMutex, BUFFER_SIZE> = Pipe::new();
+
+/// Set once the computer has sent everything; the stream is over when the buffer runs dry.
+static STREAM_ENDED: AtomicBool = AtomicBool::new(false);
+
+/// Reader over the audio in [`STREAM_BUFFER`], decoding whole frames (PCM)
+/// or blocks (IMA ADPCM) as they become available.
+///
+/// Running out of data is not the end of the stream: [`read`](SampleSource::read)
+/// returns nothing until more arrives, and [`is_finished`](Self::is_finished)
+/// only turns `true` after the computer sent [`Message::End`].
+pub struct LiveStream {
+    format: AudioFormat,
+    /// The frame or block being decoded, collected from the buffer.
+    unit: [u8; MAX_AUDIO],
+    /// Bytes of `unit` received so far.
+    filled: usize,
+    /// Next frame to decode from `unit`.
+    unit_frame: usize,
+    adpcm: [AdpcmChannel; CHANNELS],
+    /// Frames decoded since the stream started.
+    frames: u64,
+}
+
+impl LiveStream {
+    pub fn new(format: AudioFormat) -> Self {
+        Self {
+            format,
+            unit: [0; MAX_AUDIO],
+            filled: 0,
+            unit_frame: 0,
+            adpcm: [AdpcmChannel::new(); CHANNELS],
+            frames: 0,
+        }
+    }
+
+    /// Time of the next frame to be read, in milliseconds.
+    pub fn position_ms(&self) -> u32 {
+        (self.frames * 1000 / self.format.sample_rate as u64) as u32
+    }
+
+    /// Returns `true` once the computer ended the stream and everything it
+    /// sent has been read.
+    pub fn is_finished(&self) -> bool {
+        STREAM_ENDED.load(Ordering::Relaxed)
+            && STREAM_BUFFER.is_empty()
+            && self.filled < self.format.block_align as usize
+    }
+
+    /// Decodes the next frame of the unit into `out`, one sample per channel.
+    fn decode_frame(&mut self, out: &mut [i16]) -> bool {
+        let channels = out.len();
+        let unit = &self.unit[..self.format.block_align as usize];
+        match self.format.encoding {
+            Encoding::Pcm => {
+                for (sample, bytes) in out.iter_mut().zip(unit.chunks_exact(2)) {
+                    *sample = i16::from_le_bytes([bytes[0], bytes[1]]);
+                }
+                true
+            }
+            Encoding::ImaAdpcm => {
+                adpcm::decode_frame(unit, self.unit_frame, &mut self.adpcm[..channels], out)
+            }
+        }
+    }
+}
+
+impl SampleSource for LiveStream {
+    fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    fn read(&mut self, out: &mut [i16]) -> usize {
+        let channels = self.format.channels as usize;
+        let unit_size = self.format.block_align as usize;
+
+        let mut count = 0;
+        while count + CHANNELS <= out.len() {
+            if self.filled < unit_size {
+                match STREAM_BUFFER.try_read(&mut self.unit[self.filled..unit_size]) {
+                    Ok(len) => self.filled += len,
+                    Err(_) => break,
+                }
+                continue;
+            }
+
+            let mut frame = [0i16; CHANNELS];
+            let decoded = self.decode_frame(&mut frame[..channels]);
+            if decoded {
+                if channels == 1 {
+                    frame[1] = frame[0];
+                }
+                out[count..count + CHANNELS].copy_from_slice(&frame);
+                count += CHANNELS;
+                self.frames += 1;
+                self.unit_frame += 1;
+            }
+            if !decoded || self.unit_frame == self.format.frames_per_block() {
+                self.filled = 0;
+                self.unit_frame = 0;
+            }
+        }
+        count
+    }
+}
+
+/// Receiving end of the stream protocol, fed the bytes of frames by the
+/// serial port's loop.
+///
+/// A [`Message::Start`] clears the buffer and switches the player to the new
+/// stream; audio is only accepted while a stream is open. Frames that arrive
+/// out of order are dropped, so audio lost on the way only leaves a gap.
+pub struct StreamReceiver {
+    decoder: FrameDecoder,
+    /// Format of the open stream, if any.
+    format: Option<AudioFormat>,
+    /// Set once the player has switched to the open stream.
+    playing: bool,
+    /// Bytes of the stream received so far, which is where the next audio frame should start.
+    offset: u32,
+    /// End of the window granted by the last credit, `offset + free`.
+    granted: u32,
+    last_credit: Instant,
+}
+
+impl StreamReceiver {
+    pub fn new() -> Self {
+        Self {
+            decoder: FrameDecoder::new(),
+            format: None,
+            playing: false,
+            offset: 0,
+            granted: 0,
+            last_credit: Instant::now(),
+        }
+    }
+
+    /// Returns `true` if `byte` belongs to a frame: it continues the one
+    /// being received or may start a new one. Other bytes are text for the
+    /// console.
+    ///
+    /// Frames only start where the console is at the start of a line
+    /// (`line_start`): `0xA5` is also a continuation byte of UTF-8 text, such
+    /// as "ť" or "¥", but never the first byte of a character. A first byte
+    /// not followed by the second of the sync word is dropped, and the byte
+    /// after it goes back to the console.
+    pub fn accepts(&mut self, byte: u8, line_start: bool) -> bool {
+        match self.decoder.received() {
+            0 => line_start && byte == SYNC[0],
+            1 if byte != SYNC[1] => {
+                self.decoder.reset();
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Takes the next byte of a frame, acting on the message it completes.
+    pub async fn receive(&mut self, byte: u8) {
+        match self.decoder.push(byte) {
+            Some(Ok(Message::Start(format))) => {
+                STREAM_BUFFER.clear();
+                STREAM_ENDED.store(false, Ordering::Relaxed);
+                self.format = Some(format);
+                self.playing = false;
+                self.offset = 0;
+                self.granted = 0;
+                PLAYER_COMMANDS.send(PlayerCommand::Stream(format)).await;
+                log::info!("Stream started: {format:?}");
+            }
+            Some(Ok(Message::Audio { offset, data })) => {
+                if let Some(format) = self.format {
+                    self.offset = queue_audio(&format, self.offset, offset, data);
+                }
+            }
+            Some(Ok(Message::End)) if self.format.is_some() => {
+                self.format = None;
+                STREAM_ENDED.store(true, Ordering::Relaxed);
+                log::info!("Stream ended after {} bytes", self.offset);
+            }
+            // Only the player grants credits, and an end needs a stream to close
+            Some(Ok(Message::End | Message::Credit { .. })) => {}
+            Some(Err(err)) => log::warn!("Dropped stream frame: {err:?}"),
+            None => {}
+        }
+    }
+
+    /// Sends a credit on `port` while a stream is open, once enough room has
+    /// been freed since the last one or when it is time to repeat it.
+    ///
+    /// A stream the player has left for a track is closed instead: without
+    /// credits the computer gives up rather than wait for a buffer nobody reads.
+    pub async fn send_credit<P: SerialPort>(&mut self, port: &mut P) {
+        if self.format.is_none() {
+            return;
+        }
+        let streaming = PlayerState::current().streaming;
+        if self.playing && !streaming {
+            self.format = None;
+            log::info!("Stream left after {} bytes", self.offset);
+            return;
+        }
+        self.playing |= streaming;
+        let free = STREAM_BUFFER.free_capacity() as u32;
+        let limit = self.offset + free;
+        if limit < self.granted + CREDIT_STEP && self.last_credit.elapsed() < CREDIT_INTERVAL {
+            return;
+        }
+        self.granted = limit;
+        self.last_credit = Instant::now();
+
+        let mut frame = [0; CREDIT_FRAME];
+        let credit = Message::Credit {
+            offset: self.offset,
+            free,
+        };
+        let len = credit.encode(&mut frame);
+        if let Err(err) = port.write_all(&frame[..len]).await {
+            log::warn!("Stream credit failed: {err:?}");
+        }
+    }
+}
+
+impl Default for StreamReceiver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Queues the audio of one frame for the player, returning the new stream offset.
+fn queue_audio(format: &AudioFormat, expected: u32, offset: u32, data: &[u8]) -> u32 {
+    if offset < expected {
+        log::warn!("Dropped repeated audio at {offset}");
+        return expected;
+    }
+    if !data.len().is_multiple_of(format.block_align as usize) {
+        log::warn!("Dropped audio at {offset}: not whole frames");
+        return expected;
+    }
+    if data.len() > STREAM_BUFFER.free_capacity() {
+        log::warn!("Dropped audio at {offset}: sent beyond the credit");
+        return expected;
+    }
+    if offset > expected {
+        log::warn!("Lost {} bytes of audio", offset - expected);
+    }
+
+    // There is room, though the ring may take it in two pieces
+    let mut rest = data;
+    while !rest.is_empty()
+        && let Ok(len) = STREAM_BUFFER.try_write(rest)
+    {
+        rest = &rest[len..];
+    }
+    offset + data.len() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::hal::mock::{self, MockSerial};
+    use crate::player::PLAYER_STATE;
+
+    const FORMAT: AudioFormat = AudioFormat::pcm(11_025, 2, 16);
+
+    /// Starts from an idle player and an empty buffer.
+    fn reset() {
+        PLAYER_STATE.sender().send(PlayerState::default());
+        while PLAYER_COMMANDS.try_receive().is_ok() {}
+        STREAM_BUFFER.clear();
+        STREAM_ENDED.store(false, Ordering::Relaxed);
+    }
+
+    fn feed(stream: &mut StreamReceiver, message: &Message) {
+        let mut frame = [0; protocol::MAX_FRAME];
+        let len = message.encode(&mut frame);
+        block_on(async {
+            for &byte in &frame[..len] {
+                stream.receive(byte).await;
+            }
+        });
+    }
+
+    fn audio(stream: &mut StreamReceiver, offset: u32, len: usize) {
+        let data = [offset as u8; MAX_AUDIO];
+        let data = &data[..len];
+        feed(stream, &Message::Audio { offset, data });
+    }
+
+    /// Takes the credits `stream` sends, as `(offset, free)`.
+    fn credits(stream: &mut StreamReceiver) -> Vec<(u32, u32)> {
+        let port = MockSerial::new();
+        block_on(stream.send_credit(&mut &port));
+        let mut decoder = FrameDecoder::new();
+        let mut credits = Vec::new();
+        for byte in port.take_output() {
+            if let Some(Ok(Message::Credit { offset, free })) = decoder.push(byte) {
+                credits.push((offset, free));
+            }
+        }
+        credits
+    }
+
+    #[test]
+    fn credits_keep_the_buffer_from_overflowing() {
+        let _lock = mock::lock();
+        reset();
+        let mut stream = StreamReceiver::new();
+        assert!(credits(&mut stream).is_empty());
+
+        feed(&mut stream, &Message::Start(FORMAT));
+        assert_eq!(
+            PLAYER_COMMANDS.try_receive(),
+            Ok(PlayerCommand::Stream(FORMAT))
+        );
+        assert_eq!(credits(&mut stream), [(0, BUFFER_SIZE as u32)]);
+        // Nothing new to grant yet
+        assert!(credits(&mut stream).is_empty());
+
+        for offset in (0..BUFFER_SIZE).step_by(MAX_AUDIO) {
+            audio(&mut stream, offset as u32, MAX_AUDIO);
+        }
+        assert_eq!(STREAM_BUFFER.len(), BUFFER_SIZE);
+        // Beyond the credit, repeated or not whole frames: all dropped
+        audio(&mut stream, BUFFER_SIZE as u32, 4);
+        audio(&mut stream, 0, 4);
+        assert_eq!(STREAM_BUFFER.len(), BUFFER_SIZE);
+
+        // The player makes room, though not enough for a credit
+        let mut played = [0; CREDIT_STEP as usize];
+        STREAM_BUFFER.try_read(&mut played[..100]).unwrap();
+        assert!(credits(&mut stream).is_empty());
+        STREAM_BUFFER.try_read(&mut played).unwrap();
+        let end = BUFFER_SIZE as u32;
+        assert_eq!(credits(&mut stream), [(end, CREDIT_STEP + 100)]);
+
+        // Audio lost on the way only leaves a gap
+        audio(&mut stream, end + 4, 4);
+        assert_eq!(stream.offset, end + 8);
+        audio(&mut stream, end + 3, 2);
+        assert_eq!(stream.offset, end + 8);
+        assert_eq!(STREAM_BUFFER.len(), BUFFER_SIZE - CREDIT_STEP as usize - 96);
+    }
+
+    #[test]
+    fn the_stream_ends_once_played_or_left() {
+        let _lock = mock::lock();
+        reset();
+        let mut stream = StreamReceiver::new();
+        let mut live = LiveStream::new(FORMAT);
+
+        feed(&mut stream, &Message::Start(FORMAT));
+        audio(&mut stream, 0, 8);
+        feed(&mut stream, &Message::End);
+        assert!(!live.is_finished());
+        let mut out = [0; 8];
+        assert_eq!(live.read(&mut out), 4);
+        assert_eq!(live.position_ms(), 0);
+        assert!(live.is_finished());
+        // No more credits for a closed stream
+        assert!(credits(&mut stream).is_empty());
+
+        // Once the player switched to a stream, leaving it closes the stream
+        feed(&mut stream, &Message::Start(FORMAT));
+        let streaming = PlayerState {
+            streaming: true,
+            ..PlayerState::default()
+        };
+        PLAYER_STATE.sender().send(streaming);
+        assert_eq!(credits(&mut stream).len(), 1);
+        PLAYER_STATE.sender().send(PlayerState::default());
+        assert!(credits(&mut stream).is_empty());
+        audio(&mut stream, 0, 4);
+        assert_eq!(STREAM_BUFFER.len(), 0);
+    }
+}
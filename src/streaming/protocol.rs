@@ -0,0 +1,432 @@
+//! Framing of the audio streamed from a computer over a serial port.
+//!
+//! Every message travels in a frame:
+//!
+//! ```text
+//! | 0xA5 0x5A | kind: u8 | length: u16 | payload: length bytes | crc: u16 |
+//! ```
+//!
+//! Integers are little-endian. The CRC (CRC-16/CCITT-FALSE) covers the kind,
+//! the length and the payload; frames that fail it are dropped and the
+//! decoder looks for the next sync word.
+//!
+//! The computer sends [`Message::Start`], then [`Message::Audio`] frames and
+//! finally [`Message::End`]. The player answers with [`Message::Credit`]:
+//! flow control in bytes of audio, so the computer never sends more than the
+//! player's buffer has room for and the link can run as fast as it likes.
+//!
+//! This file only depends on `core` and the WAV format description, and is
+//! also compiled into the `sender` tool so both ends always agree.
+
+use crate::dsp::adpcm;
+use crate::dsp::{AudioFormat, Encoding};
+
+/// Marks the start of a frame.
+pub const SYNC: [u8; 2] = [0xA5, 0x5A];
+/// Sync word, kind and length.
+pub const HEADER_SIZE: usize = 5;
+pub const CRC_SIZE: usize = 2;
+/// Most audio bytes carried by one [`Message::Audio`], which is also the
+/// largest ADPCM block accepted.
+pub const MAX_AUDIO: usize = 2048;
+/// Largest payload of any message: the audio plus its offset.
+pub const MAX_PAYLOAD: usize = 4 + MAX_AUDIO;
+/// Largest frame on the wire.
+pub const MAX_FRAME: usize = HEADER_SIZE + MAX_PAYLOAD + CRC_SIZE;
+
+/// Lowest and highest sample rates accepted for a stream, in Hz.
+pub const SAMPLE_RATES: core::ops::RangeInclusive<u32> = 8_000..=48_000;
+
+mod kind {
+    pub const START: u8 = 0x01;
+    pub const AUDIO: u8 = 0x02;
+    pub const END: u8 = 0x03;
+    pub const CREDIT: u8 = 0x81;
+}
+
+mod encoding {
+    pub const PCM: u8 = 0;
+    pub const IMA_ADPCM: u8 = 1;
+}
+
+/// What a frame carries.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Message<'a> {
+    /// Computer to player: a stream begins, in this format. Only 16-bit PCM
+    /// and IMA ADPCM are accepted, mono or stereo.
+    Start(AudioFormat),
+    /// Computer to player: audio starting `offset` bytes into the stream.
+    /// Always whole frames (PCM) or whole blocks (ADPCM), so a lost frame
+    /// only leaves a gap.
+    Audio { offset: u32, data: &'a [u8] },
+    /// Computer to player: no more audio. Playback stops once what is
+    /// buffered has been played.
+    End,
+    /// Player to computer: `offset` bytes of the stream have arrived and the
+    /// buffer has room for `free` more, so the computer may send everything
+    /// before `offset + free`. Sent after [`Start`](Self::Start), as room
+    /// frees up and regularly while streaming.
+    Credit { offset: u32, free: u32 },
+}
+
+/// Why a frame was dropped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameError {
+    /// The length is larger than [`MAX_PAYLOAD`].
+    TooLong(u16),
+    /// The CRC does not match the contents.
+    Crc,
+    UnknownKind(u8),
+    /// The payload is too short or too long for the kind.
+    Malformed(u8),
+    /// The format in a [`Message::Start`] cannot be played.
+    UnsupportedFormat,
+}
+
+impl Message<'_> {
+    /// Writes the message as a frame into `out` and returns the frame's
+    /// length. [`MAX_FRAME`] bytes are always enough.
+    pub fn encode(&self, out: &mut [u8]) -> usize {
+        let payload = &mut out[HEADER_SIZE..];
+        let (kind, length) = match *self {
+            Message::Start(format) => {
+                let encoding = match format.encoding {
+                    Encoding::Pcm => encoding::PCM,
+                    Encoding::ImaAdpcm => encoding::IMA_ADPCM,
+                };
+                payload[0] = encoding;
+                payload[1] = format.channels as u8;
+                payload[2..6].copy_from_slice(&format.sample_rate.to_le_bytes());
+                payload[6..8].copy_from_slice(&format.block_align.to_le_bytes());
+                (kind::START, 8)
+            }
+            Message::Audio { offset, data } => {
+                payload[..4].copy_from_slice(&offset.to_le_bytes());
+                payload[4..4 + data.len()].copy_from_slice(data);
+                (kind::AUDIO, 4 + data.len())
+            }
+            Message::End => (kind::END, 0),
+            Message::Credit { offset, free } => {
+                payload[..4].copy_from_slice(&offset.to_le_bytes());
+                payload[4..8].copy_from_slice(&free.to_le_bytes());
+                (kind::CREDIT, 8)
+            }
+        };
+
+        out[..2].copy_from_slice(&SYNC);
+        out[2] = kind;
+        out[3..5].copy_from_slice(&(length as u16).to_le_bytes());
+        let end = HEADER_SIZE + length;
+        let crc = crc16(&out[2..end]);
+        out[end..end + CRC_SIZE].copy_from_slice(&crc.to_le_bytes());
+        end + CRC_SIZE
+    }
+
+    fn parse(kind: u8, payload: &[u8]) -> Result<Message<'_>, FrameError> {
+        let malformed = FrameError::Malformed(kind);
+        match kind {
+            kind::START => {
+                let [encoding, channels, r0, r1, r2, r3, b0, b1] = *payload else {
+                    return Err(malformed);
+                };
+                let sample_rate = u32::from_le_bytes([r0, r1, r2, r3]);
+                let block_align = u16::from_le_bytes([b0, b1]);
+                let channels = channels as u16;
+                let format = match encoding {
+                    encoding::PCM => AudioFormat::pcm(sample_rate, channels, 16),
+                    encoding::IMA_ADPCM => {
+                        AudioFormat::ima_adpcm(sample_rate, channels, block_align)
+                    }
+                    _ => return Err(FrameError::UnsupportedFormat),
+                };
+                if is_supported(&format) {
+                    Ok(Message::Start(format))
+                } else {
+                    Err(FrameError::UnsupportedFormat)
+                }
+            }
+            kind::AUDIO => match payload {
+                [o0, o1, o2, o3, data @ ..] => Ok(Message::Audio {
+                    offset: u32::from_le_bytes([*o0, *o1, *o2, *o3]),
+                    data,
+                }),
+                _ => Err(malformed),
+            },
+            kind::END if payload.is_empty() => Ok(Message::End),
+            kind::CREDIT => match *payload {
+                [o0, o1, o2, o3, f0, f1, f2, f3] => Ok(Message::Credit {
+                    offset: u32::from_le_bytes([o0, o1, o2, o3]),
+                    free: u32::from_le_bytes([f0, f1, f2, f3]),
+                }),
+                _ => Err(malformed),
+            },
+            kind::END => Err(malformed),
+            kind => Err(FrameError::UnknownKind(kind)),
+        }
+    }
+}
+
+/// Returns `true` if the player can stream audio in `format`.
+pub fn is_supported(format: &AudioFormat) -> bool {
+    let block_align = format.block_align as usize;
+    let layout = match format.encoding {
+        Encoding::Pcm => format.bits_per_sample == 16,
+        Encoding::ImaAdpcm => {
+            block_align <= MAX_AUDIO
+                && adpcm::frames_per_block(block_align, format.channels as usize) > 0
+        }
+    };
+    layout && matches!(format.channels, 1 | 2) && SAMPLE_RATES.contains(&format.sample_rate)
+}
+
+/// Reassembles frames from the bytes received, in any pieces.
+pub struct FrameDecoder {
+    frame: [u8; MAX_FRAME],
+    len: usize,
+}
+
+impl FrameDecoder {
+    pub const fn new() -> Self {
+        Self {
+            frame: [0; MAX_FRAME],
+            len: 0,
+        }
+    }
+
+    /// Bytes of the frame received so far, sync word included; zero while
+    /// looking for one.
+    pub fn received(&self) -> usize {
+        self.len
+    }
+
+    /// Drops the frame being received and goes back to looking for a sync word.
+    pub fn reset(&mut self) {
+        self.len = 0;
+    }
+
+    /// Takes the next byte, returning the message once a frame is complete,
+    /// or why it was dropped. Bytes outside frames are skipped.
+    pub fn push(&mut self, byte: u8) -> Option<Result<Message<'_>, FrameError>> {
+        match self.len {
+            // Hunting for the sync word; a repeated first byte may still start it
+            0 | 1 if byte != SYNC[self.len] => {
+                self.len = usize::from(byte == SYNC[0]);
+                return None;
+            }
+            _ => {}
+        }
+        self.frame[self.len] = byte;
+        self.len += 1;
+
+        if self.len < HEADER_SIZE {
+            return None;
+        }
+        let length = u16::from_le_bytes([self.frame[3], self.frame[4]]);
+        if length as usize > MAX_PAYLOAD {
+            self.len = 0;
+            return Some(Err(FrameError::TooLong(length)));
+        }
+        let end = HEADER_SIZE + length as usize;
+        if self.len < end + CRC_SIZE {
+            return None;
+        }
+
+        self.len = 0;
+        let crc = u16::from_le_bytes([self.frame[end], self.frame[end + 1]]);
+        if crc != crc16(&self.frame[2..end]) {
+            return Some(Err(FrameError::Crc));
+        }
+        Some(Message::parse(self.frame[2], &self.frame[HEADER_SIZE..end]))
+    }
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// CRC-16/CCITT-FALSE: polynomial 0x1021, initial value 0xFFFF.
+pub fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in bytes {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(message: &Message) -> Vec<u8> {
+        let mut frame = [0; MAX_FRAME];
+        let len = message.encode(&mut frame);
+        frame[..len].to_vec()
+    }
+
+    /// Pushes `bytes` one at a time, returning what every completed frame gave.
+    fn decode(decoder: &mut FrameDecoder, bytes: &[u8]) -> Vec<Result<String, FrameError>> {
+        let mut results = Vec::new();
+        for &byte in bytes {
+            if let Some(result) = decoder.push(byte) {
+                results.push(result.map(|message| format!("{message:?}")));
+            }
+        }
+        results
+    }
+
+    fn messages() -> [Message<'static>; 5] {
+        [
+            Message::Start(AudioFormat::pcm(44_100, 2, 16)),
+            Message::Start(AudioFormat::ima_adpcm(11_025, 1, 256)),
+            Message::Audio {
+                offset: 0x0102_0304,
+                data: &[0xA5, 0x5A, 0, 0xFF],
+            },
+            Message::End,
+            Message::Credit {
+                offset: 4096,
+                free: 12_288,
+            },
+        ]
+    }
+
+    #[test]
+    fn crc_matches_the_standard_check_value() {
+        assert_eq!(crc16(b"123456789"), 0x29B1);
+    }
+
+    #[test]
+    fn messages_survive_a_round_trip() {
+        let mut decoder = FrameDecoder::new();
+        for message in messages() {
+            let frame = frame(&message);
+            assert_eq!(decode(&mut decoder, &frame), [Ok(format!("{message:?}"))]);
+            assert_eq!(decoder.received(), 0);
+        }
+        // The longest audio frame fits exactly
+        let data = [0x5A; MAX_AUDIO];
+        let message = Message::Audio {
+            offset: 7,
+            data: &data,
+        };
+        assert_eq!(frame(&message).len(), MAX_FRAME);
+        assert_eq!(
+            decode(&mut decoder, &frame(&message)),
+            [Ok(format!("{message:?}"))]
+        );
+    }
+
+    #[test]
+    fn frame_layout_is_stable() {
+        let credit = Message::Credit {
+            offset: 0x10,
+            free: 0x4000,
+        };
+        #[rustfmt::skip]
+        assert_eq!(frame(&credit), [
+            0xA5, 0x5A, 0x81, 8, 0, 0x10, 0, 0, 0, 0, 0x40, 0, 0, 0xBE, 0xEF,
+        ]);
+    }
+
+    #[test]
+    fn corrupted_frames_are_dropped() {
+        let mut decoder = FrameDecoder::new();
+        let credit = frame(&Message::Credit { offset: 1, free: 2 });
+        for index in 2..credit.len() {
+            let mut bytes = credit.clone();
+            bytes[index] ^= 0x10;
+            // A corrupted length may hold on to a few of the frames after it
+            for _ in 0..10 {
+                bytes.extend_from_slice(&frame(&Message::End));
+            }
+            let results = decode(&mut decoder, &bytes);
+            assert!(results[0].is_err(), "byte {index}: {results:?}");
+            assert_eq!(results.last(), Some(&Ok("End".into())), "byte {index}");
+        }
+
+        let mut bad = frame(&Message::End);
+        bad[HEADER_SIZE] ^= 1;
+        assert_eq!(decode(&mut decoder, &bad), [Err(FrameError::Crc)]);
+    }
+
+    #[test]
+    fn decoder_resyncs_after_garbage() {
+        let mut decoder = FrameDecoder::new();
+        let mut bytes = b"> status\r\n".to_vec();
+        // A repeated first byte still starts the sync word
+        bytes.extend_from_slice(&[0xA5, 0x00, 0x5A, 0xA5]);
+        bytes.extend_from_slice(&frame(&Message::End));
+        bytes.extend_from_slice(b"ok\r\n");
+        bytes.extend_from_slice(&frame(&Message::Credit { offset: 3, free: 4 }));
+        assert_eq!(
+            decode(&mut decoder, &bytes),
+            [Ok("End".into()), Ok("Credit { offset: 3, free: 4 }".into())]
+        );
+
+        decoder.push(SYNC[0]);
+        decoder.push(SYNC[1]);
+        assert_eq!(decoder.received(), 2);
+        decoder.reset();
+        assert_eq!(
+            decode(&mut decoder, &frame(&Message::End)),
+            [Ok("End".into())]
+        );
+    }
+
+    #[test]
+    fn invalid_frames_are_reported() {
+        let mut decoder = FrameDecoder::new();
+
+        // Rejected from the header alone, without waiting for a payload
+        let too_long = (MAX_PAYLOAD as u16 + 1).to_le_bytes();
+        let header = [SYNC[0], SYNC[1], kind::AUDIO, too_long[0], too_long[1]];
+        assert_eq!(
+            decode(&mut decoder, &header),
+            [Err(FrameError::TooLong(MAX_PAYLOAD as u16 + 1))]
+        );
+
+        // Frames with a valid CRC around contents that make no sense
+        let cases: [(u8, &[u8], FrameError); 6] = [
+            (0x42, &[], FrameError::UnknownKind(0x42)),
+            (kind::END, &[0], FrameError::Malformed(kind::END)),
+            (kind::CREDIT, &[0; 7], FrameError::Malformed(kind::CREDIT)),
+            (kind::AUDIO, &[0; 3], FrameError::Malformed(kind::AUDIO)),
+            (
+                kind::START,
+                &[7, 2, 0x44, 0xAC, 0, 0, 4, 0],
+                FrameError::UnsupportedFormat,
+            ),
+            (
+                kind::START,
+                &[encoding::PCM, 3, 0x44, 0xAC, 0, 0, 6, 0],
+                FrameError::UnsupportedFormat,
+            ),
+        ];
+        for (kind, payload, error) in cases {
+            let mut bytes = vec![SYNC[0], SYNC[1], kind];
+            bytes.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(payload);
+            bytes.extend_from_slice(&crc16(&bytes[2..]).to_le_bytes());
+            assert_eq!(decode(&mut decoder, &bytes), [Err(error)], "{bytes:x?}");
+        }
+    }
+
+    #[test]
+    fn only_playable_formats_are_supported() {
+        assert!(is_supported(&AudioFormat::pcm(8_000, 1, 16)));
+        assert!(is_supported(&AudioFormat::ima_adpcm(48_000, 2, 2048)));
+        assert!(!is_supported(&AudioFormat::pcm(44_100, 2, 8)));
+        assert!(!is_supported(&AudioFormat::pcm(96_000, 2, 16)));
+        assert!(!is_supported(&AudioFormat::ima_adpcm(11_025, 1, 4096)));
+        assert!(!is_supported(&AudioFormat::ima_adpcm(11_025, 2, 4)));
+    }
+}